@@ -1,49 +1,154 @@
-use crate::token::{Token, TokenType};
-use crate::ast::Statement::Let;
+use crate::token::{Span, Token};
+use num_bigint::BigInt;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
 
 pub(crate) trait Node {
     fn token_literal(&self) -> String;
 }
-#[allow(dead_code)]
-#[derive(Debug)]
+
+#[derive(Debug, Clone)]
 pub(crate) enum Statement {
     Let(LetStatement),
-    Ident(Identifier),
-    Return(ReturnStatement)
+    Return(ReturnStatement),
+    Expression(ExpressionStatement),
 }
 
-trait InternalStatement: Node {
-    fn statement_node(&self);
+impl Statement {
+    /// Position of the first token of the statement.
+    pub(crate) fn span(&self) -> Span {
+        match self {
+            Statement::Let(stmt) => stmt.token.span,
+            Statement::Return(stmt) => stmt.token.span,
+            Statement::Expression(stmt) => stmt.token.span,
+        }
+    }
 }
 
-pub(crate) trait Expression: Node {
-    fn expression_node(&self) -> String;
+impl Node for Statement {
+    fn token_literal(&self) -> String {
+        match self {
+            Statement::Let(stmt) => stmt.token_literal(),
+            Statement::Return(stmt) => stmt.token_literal(),
+            Statement::Expression(stmt) => stmt.token_literal(),
+        }
+    }
 }
 
-pub(crate) struct Program {
-    pub(crate) statements: Vec<Statement>,
+impl Display for Statement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Statement::Let(stmt) => write!(f, "{}", stmt),
+            Statement::Return(stmt) => write!(f, "{}", stmt),
+            Statement::Expression(stmt) => write!(f, "{}", stmt),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Expression {
+    Identifier(Identifier),
+    IntegerLiteral(IntegerLiteral),
+    StringLiteral(StringLiteral),
+    Boolean(Boolean),
+    Prefix(PrefixExpression),
+    Infix(InfixExpression),
+    If(IfExpression),
+    FunctionLiteral(FunctionLiteral),
+    Call(CallExpression),
+    ArrayLiteral(ArrayLiteral),
+    HashLiteral(HashLiteral),
+    Index(IndexExpression),
 }
 
-pub(crate) struct DefaultExpression(String);
+impl Expression {
+    /// Position of the first token of the expression.
+    pub(crate) fn span(&self) -> Span {
+        match self {
+            Expression::Infix(exp) => exp.left.span(),
+            Expression::Call(exp) => exp.function.span(),
+            Expression::Index(exp) => exp.left.span(),
+            _ => self.token().span,
+        }
+    }
 
-impl Node for DefaultExpression {
-    fn token_literal(&self) -> String {
-        self.0.clone()
+    fn token(&self) -> &Token {
+        match self {
+            Expression::Identifier(exp) => &exp.token,
+            Expression::IntegerLiteral(exp) => &exp.token,
+            Expression::StringLiteral(exp) => &exp.token,
+            Expression::Boolean(exp) => &exp.token,
+            Expression::Prefix(exp) => &exp.token,
+            Expression::Infix(exp) => &exp.token,
+            Expression::If(exp) => &exp.token,
+            Expression::FunctionLiteral(exp) => &exp.token,
+            Expression::Call(exp) => &exp.token,
+            Expression::ArrayLiteral(exp) => &exp.token,
+            Expression::HashLiteral(exp) => &exp.token,
+            Expression::Index(exp) => &exp.token,
+        }
     }
 }
 
-impl Expression for DefaultExpression {
-    fn expression_node(&self) -> String {
-        self.0.clone()
+impl Node for Expression {
+    fn token_literal(&self) -> String {
+        self.token().literal.clone()
     }
 }
 
-impl Default for DefaultExpression {
-    fn default() -> Self {
-        Self(String::new())
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expression::Identifier(exp) => write!(f, "{}", exp),
+            Expression::IntegerLiteral(exp) => write!(f, "{}", exp.token.literal),
+            Expression::StringLiteral(exp) => write!(f, "{}", exp.value),
+            Expression::Boolean(exp) => write!(f, "{}", exp.token.literal),
+            Expression::Prefix(exp) => write!(f, "({}{})", exp.operator, exp.right),
+            Expression::Infix(exp) => {
+                write!(f, "({} {} {})", exp.left, exp.operator, exp.right)
+            }
+            Expression::If(exp) => {
+                write!(f, "if{} {}", exp.condition, exp.consequence)?;
+                if let Some(alt) = &exp.alternative {
+                    write!(f, "else {}", alt)?;
+                }
+                Ok(())
+            }
+            Expression::FunctionLiteral(exp) => write!(
+                f,
+                "{}({}) {}",
+                exp.token.literal,
+                join(&exp.parameters),
+                exp.body
+            ),
+            Expression::Call(exp) => write!(f, "{}({})", exp.function, join(&exp.arguments)),
+            Expression::ArrayLiteral(exp) => write!(f, "[{}]", join(&exp.elements)),
+            Expression::HashLiteral(exp) => {
+                let pairs: Vec<_> = exp
+                    .pairs
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect();
+                write!(f, "{{{}}}", pairs.join(", "))
+            }
+            Expression::Index(exp) => write!(f, "({}[{}])", exp.left, exp.index),
+        }
     }
 }
 
+fn join<T: Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Program {
+    pub(crate) statements: Vec<Statement>,
+}
+
 impl Program {
     pub fn new() -> Self {
         Program {
@@ -55,50 +160,27 @@ impl Program {
 impl Node for Program {
     fn token_literal(&self) -> String {
         if let Some(first) = self.statements.first() {
-            match first {
-                Statement::Let(lt) => lt.token_literal(),
-                Statement::Ident(id) => id.token_literal(),
-                Statement::Return(ret) => ret.token_literal(),
-            };
-        };
-        String::new()
-    }
-}
-
-pub(crate) struct LetStatement {
-    pub(crate) token: Token, // TokenType::Let
-    pub(crate) name: Identifier,
-    pub(crate) value: Box<dyn Expression>,
-}
-
-impl LetStatement {
-    pub fn new(token: Token) -> Self {
-        let ident = Identifier {
-            token: Token::new(TokenType::EOF, ""),
-            value: "".to_string()
-        };
-        LetStatement {
-            token,
-            name: ident.clone(),
-            value: Box::new(DefaultExpression(String::new()))
+            first.token_literal()
+        } else {
+            String::new()
         }
     }
 }
 
-impl std::fmt::Debug for LetStatement {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "LetStatement {{ token: {:?}, name: {:?}, value: {:?} }}",
-            self.token,
-            self.name,
-            self.value.expression_node()
-        )
+impl Display for Program {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for stmt in &self.statements {
+            write!(f, "{}", stmt)?;
+        }
+        Ok(())
     }
 }
 
-impl InternalStatement for LetStatement {
-    fn statement_node(&self) {}
+#[derive(Debug, Clone)]
+pub(crate) struct LetStatement {
+    pub(crate) token: Token, // TokenType::Let
+    pub(crate) name: Identifier,
+    pub(crate) value: Expression,
 }
 
 impl Node for LetStatement {
@@ -107,53 +189,159 @@ impl Node for LetStatement {
     }
 }
 
+impl Display for LetStatement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} = {};", self.token_literal(), self.name, self.value)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct Identifier {
     pub(crate) token: Token, // TokenType::Ident
     pub(crate) value: String,
 }
 
-impl InternalStatement for Identifier {
-    fn statement_node(&self) {}
-}
-
 impl Node for Identifier {
     fn token_literal(&self) -> String {
         self.token.literal.clone()
     }
 }
 
+impl Display for Identifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub(crate) struct ReturnStatement {
     pub(crate) token: Token, // TokenType::Return
-    pub(crate) return_value: Box<dyn Expression>,
+    pub(crate) return_value: Expression,
 }
 
-impl ReturnStatement {
-    pub fn new(token: Token) -> Self {
-        Self {
-            token,
-            return_value: Box::new(DefaultExpression(String::new()))
-        }
+impl Node for ReturnStatement {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+}
+
+impl Display for ReturnStatement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {};", self.token_literal(), self.return_value)
     }
 }
 
-impl InternalStatement for ReturnStatement {
-    fn statement_node(&self) {}
+#[derive(Debug, Clone)]
+pub(crate) struct ExpressionStatement {
+    pub(crate) token: Token, // the first token of the expression
+    pub(crate) expression: Expression,
 }
 
-impl Node for ReturnStatement {
+impl Node for ExpressionStatement {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+}
+
+impl Display for ExpressionStatement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BlockStatement {
+    pub(crate) token: Token, // TokenType::LBRACE
+    pub(crate) statements: Vec<Statement>,
+    pub(crate) end: Span, // position of the closing brace
+}
+
+impl Node for BlockStatement {
     fn token_literal(&self) -> String {
         self.token.literal.clone()
     }
 }
 
-impl std::fmt::Debug for ReturnStatement {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "LetStatement {{ token: {:?}, return_value: {:?} }}",
-            self.token,
-            self.return_value.expression_node()
-        )
+impl Display for BlockStatement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for stmt in &self.statements {
+            write!(f, "{}", stmt)?;
+        }
+        Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct IntegerLiteral {
+    pub(crate) token: Token, // TokenType::INT
+    pub(crate) value: BigInt, // literals are not limited to 64 bits
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct StringLiteral {
+    pub(crate) token: Token, // TokenType::STRING
+    pub(crate) value: String, // with escapes resolved
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Boolean {
+    pub(crate) token: Token, // TokenType::TRUE or TokenType::FALSE
+    pub(crate) value: bool,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct PrefixExpression {
+    pub(crate) token: Token, // the prefix operator
+    pub(crate) operator: String,
+    pub(crate) right: Box<Expression>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct InfixExpression {
+    pub(crate) token: Token, // the infix operator
+    pub(crate) left: Box<Expression>,
+    pub(crate) operator: String,
+    pub(crate) right: Box<Expression>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct IfExpression {
+    pub(crate) token: Token, // TokenType::IF
+    pub(crate) condition: Box<Expression>,
+    pub(crate) consequence: BlockStatement,
+    pub(crate) alternative: Option<BlockStatement>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct FunctionLiteral {
+    pub(crate) token: Token, // TokenType::FUNCTION
+    pub(crate) name: Option<Rc<str>>, // the `let` binding it is the value of
+    pub(crate) parameters: Vec<Identifier>,
+    pub(crate) body: Rc<BlockStatement>, // shared with the closures created from it
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct CallExpression {
+    pub(crate) token: Token, // TokenType::LPAREN
+    pub(crate) function: Box<Expression>,
+    pub(crate) arguments: Vec<Expression>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ArrayLiteral {
+    pub(crate) token: Token, // TokenType::LBRACKET
+    pub(crate) elements: Vec<Expression>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct HashLiteral {
+    pub(crate) token: Token, // TokenType::LBRACE
+    pub(crate) pairs: Vec<(Expression, Expression)>, // in source order
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct IndexExpression {
+    pub(crate) token: Token, // TokenType::LBRACKET
+    pub(crate) left: Box<Expression>,
+    pub(crate) index: Box<Expression>,
+}
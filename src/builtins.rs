@@ -0,0 +1,111 @@
+use crate::object::{Builtin, ErrorKind, Object, RuntimeError};
+use crate::token::Span;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+lazy_static! {
+    static ref BUILTINS: HashMap<&'static str, Builtin> = {
+        let mut m = HashMap::new();
+        m.insert("len", Builtin::new("len", len));
+        m.insert("first", Builtin::new("first", first));
+        m.insert("last", Builtin::new("last", last));
+        m.insert("rest", Builtin::new("rest", rest));
+        m.insert("push", Builtin::new("push", push));
+        m.insert("puts", Builtin::new("puts", puts));
+        m
+    };
+}
+
+/// Looked up after the environment, so a `let` binding can shadow a builtin.
+pub(crate) fn lookup(name: &str) -> Option<Object> {
+    BUILTINS.get(name).map(|builtin| Object::Builtin(*builtin))
+}
+
+fn len(args: &[Object], span: Span) -> Object {
+    if let Err(err) = check_arity("len", args, 1, span) {
+        return err;
+    }
+    match &args[0] {
+        Object::String(value) => Object::Integer(value.chars().count() as i64),
+        Object::Array(elements) => Object::Integer(elements.len() as i64),
+        arg => unsupported("len", arg, span),
+    }
+}
+
+fn first(args: &[Object], span: Span) -> Object {
+    if let Err(err) = check_arity("first", args, 1, span) {
+        return err;
+    }
+    match &args[0] {
+        Object::Array(elements) => elements.first().cloned().unwrap_or(Object::Null),
+        arg => unsupported("first", arg, span),
+    }
+}
+
+fn last(args: &[Object], span: Span) -> Object {
+    if let Err(err) = check_arity("last", args, 1, span) {
+        return err;
+    }
+    match &args[0] {
+        Object::Array(elements) => elements.last().cloned().unwrap_or(Object::Null),
+        arg => unsupported("last", arg, span),
+    }
+}
+
+fn rest(args: &[Object], span: Span) -> Object {
+    if let Err(err) = check_arity("rest", args, 1, span) {
+        return err;
+    }
+    match &args[0] {
+        Object::Array(elements) if elements.is_empty() => Object::Null,
+        Object::Array(elements) => Object::Array(Rc::new(elements[1..].to_vec())),
+        arg => unsupported("rest", arg, span),
+    }
+}
+
+fn push(args: &[Object], span: Span) -> Object {
+    if let Err(err) = check_arity("push", args, 2, span) {
+        return err;
+    }
+    match &args[0] {
+        Object::Array(elements) => {
+            let mut pushed = Vec::with_capacity(elements.len() + 1);
+            pushed.extend(elements.iter().cloned());
+            pushed.push(args[1].clone());
+            Object::Array(Rc::new(pushed))
+        }
+        arg => unsupported("push", arg, span),
+    }
+}
+
+fn puts(args: &[Object], _span: Span) -> Object {
+    for arg in args {
+        println!("{}", arg);
+    }
+    Object::Null
+}
+
+fn check_arity(name: &str, args: &[Object], want: usize, span: Span) -> Result<(), Object> {
+    if args.len() == want {
+        return Ok(());
+    }
+    Err(Object::Error(Box::new(RuntimeError::new(
+        ErrorKind::WrongArguments,
+        format!(
+            "wrong number of arguments to `{}`: want={}, got={}",
+            name,
+            want,
+            args.len()
+        ),
+        span,
+    ))))
+}
+
+fn unsupported(name: &str, arg: &Object, span: Span) -> Object {
+    Object::Error(Box::new(RuntimeError::new(
+        ErrorKind::InvalidArgument,
+        format!("argument to `{}` not supported, got {}", name, arg.type_name()),
+        span,
+    )))
+}
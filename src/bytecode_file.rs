@@ -0,0 +1,437 @@
+use crate::compiler::Bytecode;
+use crate::object::{CompiledFunction, Object};
+use crate::token::Span;
+use crate::verifier;
+use num_bigint::BigInt;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use std::str;
+
+/// The first bytes of every bytecode file.
+pub(crate) const MAGIC: &[u8; 4] = b"MKBC";
+/// Changes whenever the layout or the instruction set does, as files of
+/// another version would be misread.
+pub(crate) const FORMAT_VERSION: u16 = 3;
+/// Magic, version, body length and checksum.
+const HEADER_LEN: usize = 4 + 2 + 4 + 4;
+
+const INTEGER: u8 = 0;
+const BIG_INTEGER: u8 = 1;
+const STRING: u8 = 2;
+const FUNCTION: u8 = 3;
+
+/// Why bytes could not be loaded as bytecode.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum LoadError {
+    /// They do not start like a bytecode file.
+    NotBytecode,
+    /// They are in a version of the format this build cannot read.
+    UnsupportedVersion(u16),
+    /// They end before the length in the header says.
+    Truncated,
+    /// They do not match the checksum in the header.
+    Corrupt,
+    /// They match their checksum but do not describe a program, so they
+    /// were not written by `to_bytes`.
+    Malformed(String),
+    /// They describe a program the VM cannot run safely, such as one that
+    /// jumps into the middle of an instruction.
+    Invalid(String),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::NotBytecode => write!(f, "not a Monkey bytecode file"),
+            LoadError::UnsupportedVersion(version) => write!(
+                f,
+                "bytecode format version {} is not supported (expected version {}); \
+                 recompile the program",
+                version, FORMAT_VERSION
+            ),
+            LoadError::Truncated => write!(f, "bytecode file is truncated"),
+            LoadError::Corrupt => write!(f, "bytecode file is corrupt: checksum mismatch"),
+            LoadError::Malformed(message) => write!(f, "malformed bytecode file: {}", message),
+            LoadError::Invalid(message) => write!(f, "invalid bytecode: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Encodes `bytecode`: a header, then the names of the globals, a table of
+/// the functions with the program's own first, and the constants, which
+/// refer to functions by their place in the table. Numbers are big-endian.
+pub(crate) fn write(bytecode: &Bytecode) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_len(&mut body, bytecode.globals.len());
+    for name in &bytecode.globals {
+        put_str(&mut body, name);
+    }
+
+    let functions: Vec<&CompiledFunction> = std::iter::once(&*bytecode.main)
+        .chain(bytecode.constants.iter().filter_map(|constant| match constant {
+            Object::CompiledFunction(func) => Some(&**func),
+            _ => None,
+        }))
+        .collect();
+    put_len(&mut body, functions.len());
+    for func in functions {
+        put_function(&mut body, func);
+    }
+
+    put_len(&mut body, bytecode.constants.len());
+    let mut next_function = 1;
+    for constant in &bytecode.constants {
+        match constant {
+            Object::Integer(value) => {
+                body.push(INTEGER);
+                body.extend_from_slice(&value.to_be_bytes());
+            }
+            Object::BigInteger(value) => {
+                body.push(BIG_INTEGER);
+                put_bytes(&mut body, &value.to_signed_bytes_be());
+            }
+            Object::String(value) => {
+                body.push(STRING);
+                put_str(&mut body, value);
+            }
+            Object::CompiledFunction(_) => {
+                body.push(FUNCTION);
+                put_len(&mut body, next_function);
+                next_function += 1;
+            }
+            constant => unreachable!("not a constant: {}", constant.type_name()),
+        }
+    }
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+    put_len(&mut bytes, body.len());
+    bytes.extend_from_slice(&crc32(&body).to_be_bytes());
+    bytes.extend_from_slice(&body);
+    bytes
+}
+
+/// Decodes what `write` encoded, checking the header and checksum first.
+pub(crate) fn load(bytes: &[u8]) -> Result<Bytecode, LoadError> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(LoadError::NotBytecode);
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(LoadError::Truncated);
+    }
+    let mut header = Reader { bytes, at: MAGIC.len() };
+    let version = header.u16()?;
+    if version != FORMAT_VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+    let len = header.u32()? as usize;
+    let checksum = header.u32()?;
+    let body = &bytes[HEADER_LEN..];
+    if body.len() < len {
+        return Err(LoadError::Truncated);
+    }
+    if body.len() > len || crc32(body) != checksum {
+        return Err(LoadError::Corrupt);
+    }
+
+    let mut body = Reader { bytes: body, at: 0 };
+    let mut globals = Vec::new();
+    for _ in 0..body.len()? {
+        globals.push(body.str()?);
+    }
+    let mut functions = Vec::new();
+    for _ in 0..body.len()? {
+        functions.push(Rc::new(body.function()?));
+    }
+    if functions.is_empty() {
+        return Err(malformed("there is no program"));
+    }
+    let mut constants = Vec::new();
+    for _ in 0..body.len()? {
+        let constant = match body.u8()? {
+            INTEGER => Object::Integer(i64::from_be_bytes(body.array()?)),
+            BIG_INTEGER => {
+                let value = BigInt::from_signed_bytes_be(body.bytes()?);
+                Object::BigInteger(Rc::new(value))
+            }
+            STRING => Object::String(body.str()?),
+            FUNCTION => match body.u32()? as usize {
+                // the program is not a function a closure can be made of
+                0 => return Err(malformed("a constant refers to the program")),
+                i if i < functions.len() => Object::CompiledFunction(Rc::clone(&functions[i])),
+                i => return Err(malformed(format!("no function {}", i))),
+            },
+            tag => return Err(malformed(format!("unknown constant tag {}", tag))),
+        };
+        constants.push(constant);
+    }
+    if body.at != body.bytes.len() {
+        return Err(malformed("bytes after the constants"));
+    }
+    let bytecode = Bytecode {
+        main: Rc::clone(&functions[0]),
+        constants,
+        globals,
+    };
+    verifier::verify(&bytecode).map_err(LoadError::Invalid)?;
+    Ok(bytecode)
+}
+
+fn put_function(out: &mut Vec<u8>, func: &CompiledFunction) {
+    match &func.name {
+        Some(name) => {
+            out.push(1);
+            put_str(out, name);
+        }
+        None => out.push(0),
+    }
+    put_str(out, &func.text);
+    put_len(out, func.num_parameters);
+    put_len(out, func.num_locals);
+    put_bytes(out, &func.instructions);
+    put_len(out, func.positions.len());
+    for (offset, span) in &func.positions {
+        put_len(out, *offset);
+        put_len(out, span.line);
+        put_len(out, span.column);
+    }
+}
+
+fn put_len(out: &mut Vec<u8>, len: usize) {
+    out.extend_from_slice(&(len as u32).to_be_bytes());
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_len(out, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+fn put_str(out: &mut Vec<u8>, value: &str) {
+    put_bytes(out, value.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], LoadError> {
+        match self.bytes.get(self.at..self.at.saturating_add(n)) {
+            Some(taken) => {
+                self.at += n;
+                Ok(taken)
+            }
+            None => Err(malformed("it ends in the middle of an item")),
+        }
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], LoadError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    /// A count of items that follow, each at least a byte, so a count
+    /// larger than the bytes left fails before anything is allocated.
+    fn len(&mut self) -> Result<usize, LoadError> {
+        let len = self.u32()? as usize;
+        if len > self.bytes.len() - self.at {
+            return Err(malformed("a length is larger than the file"));
+        }
+        Ok(len)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], LoadError> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    fn str(&mut self) -> Result<Rc<str>, LoadError> {
+        match str::from_utf8(self.bytes()?) {
+            Ok(value) => Ok(value.into()),
+            Err(_) => Err(malformed("a string is not UTF-8")),
+        }
+    }
+
+    fn function(&mut self) -> Result<CompiledFunction, LoadError> {
+        let name = match self.u8()? {
+            0 => None,
+            1 => Some(self.str()?),
+            flag => return Err(malformed(format!("unknown name flag {}", flag))),
+        };
+        let text = self.str()?;
+        let num_parameters = self.u32()? as usize;
+        let num_locals = self.u32()? as usize;
+        let instructions = self.bytes()?.to_vec();
+        let mut positions: Vec<(usize, Span)> = Vec::new();
+        for _ in 0..self.len()? {
+            let offset = self.u32()? as usize;
+            let line = self.u32()? as usize;
+            let column = self.u32()? as usize;
+            // looked up by binary search
+            if matches!(positions.last(), Some((last, _)) if *last >= offset) {
+                return Err(malformed("a line table is out of order"));
+            }
+            positions.push((offset, Span { line, column }));
+        }
+        Ok(CompiledFunction {
+            instructions,
+            positions,
+            num_locals,
+            num_parameters,
+            name,
+            text,
+        })
+    }
+}
+
+fn malformed<P: Into<String>>(message: P) -> LoadError {
+    LoadError::Malformed(message.into())
+}
+
+/// CRC-32 as zlib and PNG compute it.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, LoadError, FORMAT_VERSION, HEADER_LEN};
+    use crate::code::{make, Opcode};
+    use crate::compiler::{compile, Bytecode};
+    use crate::engine::Engine;
+    use crate::evaluator::IntegerMode;
+    use std::rc::Rc;
+
+    const PROGRAM: &str = r#"
+let big = 123456789012345678901234567890;
+let greet = fn(name) { "hello " + name };
+let counter = fn(n) { fn() { n + 1 } };
+[greet("monkey"), counter(41)(), big, -1, fn() { 0 }]"#;
+
+    #[test]
+    fn test_round_trip() {
+        let bytecode = compile(PROGRAM).unwrap();
+        let loaded = Bytecode::from_bytes(&bytecode.to_bytes()).unwrap();
+        assert_eq!(loaded, bytecode);
+
+        let engine = Engine::new().with_integer_mode(IntegerMode::Arbitrary);
+        assert_eq!(
+            engine.run(&loaded).unwrap().to_string(),
+            "[hello monkey, 42, 123456789012345678901234567890, -1, fn() { 0 }]"
+        );
+    }
+
+    #[test]
+    fn test_rejects_bad_files() {
+        let bytes = compile(PROGRAM).unwrap().to_bytes();
+
+        let mut flipped = bytes.clone();
+        flipped[HEADER_LEN + 10] ^= 1;
+        let mut newer = bytes.clone();
+        newer[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_be_bytes());
+        let mut longer = bytes.clone();
+        longer.push(0);
+
+        let tests = vec![
+            (b"let x = 1;".to_vec(), LoadError::NotBytecode),
+            (bytes[..3].to_vec(), LoadError::NotBytecode),
+            (bytes[..bytes.len() - 1].to_vec(), LoadError::Truncated),
+            (bytes[..8].to_vec(), LoadError::Truncated),
+            (flipped, LoadError::Corrupt),
+            (longer, LoadError::Corrupt),
+            (newer, LoadError::UnsupportedVersion(FORMAT_VERSION + 1)),
+        ];
+        for (bytes, expected) in tests {
+            assert_eq!(Bytecode::from_bytes(&bytes), Err(expected));
+        }
+    }
+
+    #[test]
+    fn test_rejects_malformed_bodies() {
+        // a correct header around a body that is not a program
+        let file = |body: &[u8]| {
+            let mut bytes = b"MKBC".to_vec();
+            bytes.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+            bytes.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&crc32(body).to_be_bytes());
+            bytes.extend_from_slice(body);
+            bytes
+        };
+        let tests = vec![
+            (vec![0, 0, 0, 0, 0, 0, 0, 0], "there is no program"),
+            (vec![0, 0, 0, 1, 0, 0, 0, 0, 0], "it ends in the middle of an item"),
+            (vec![0, 0, 0, 9], "a length is larger than the file"),
+            (vec![0, 0, 0, 1, 0, 0, 0, 1, 0xff], "a string is not UTF-8"),
+        ];
+        for (body, expected) in tests {
+            match Bytecode::from_bytes(&file(&body)) {
+                Err(LoadError::Malformed(message)) => assert_eq!(message, expected),
+                result => panic!("expected a malformed file. got={:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_invalid_code() {
+        let mut bytecode = compile("1").unwrap();
+        Rc::make_mut(&mut bytecode.main).instructions = make(Opcode::Pop, &[]);
+        assert_eq!(
+            Bytecode::from_bytes(&bytecode.to_bytes()),
+            Err(LoadError::Invalid("<program> at 0000: OpPop on a stack 0 deep".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_damaged_files_never_panic() {
+        // no calls, as damage could make one that never returns
+        let source = r#"let x = [1, 2 * 3, "a"]; let h = {"k": x[1]};
+if (x[0] < 2) { h["k"] - 1 } else { -x[2] }"#;
+        let bytes = compile(source).unwrap().to_bytes();
+        let engine = Engine::new();
+        // each opcode and each bit flipped in every byte of the body, under
+        // a checksum to match
+        for at in HEADER_LEN..bytes.len() {
+            let flips = (0..8).map(|bit| bytes[at] ^ 1 << bit);
+            for byte in (0..=Opcode::HashKey as u8 + 1).chain(flips) {
+                let mut damaged = bytes.clone();
+                damaged[at] = byte;
+                let checksum = crc32(&damaged[HEADER_LEN..]);
+                damaged[HEADER_LEN - 4..HEADER_LEN].copy_from_slice(&checksum.to_be_bytes());
+                if let Ok(bytecode) = Bytecode::from_bytes(&damaged) {
+                    let _ = engine.run(&bytecode);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+}
@@ -0,0 +1,229 @@
+use std::convert::TryFrom;
+
+/// Bytecode: each instruction is an opcode byte followed by its operands,
+/// big-endian.
+pub(crate) type Instructions = Vec<u8>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum Opcode {
+    Constant,
+    Pop,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    True,
+    False,
+    Null,
+    Equal,
+    NotEqual,
+    LessThan,
+    GreaterThan,
+    Minus,
+    Bang,
+    JumpNotTruthy,
+    Jump,
+    GetGlobal,
+    SetGlobal,
+    GetLocal,
+    SetLocal,
+    GetFree,
+    CurrentClosure,
+    Array,
+    Hash,
+    Index,
+    Call,
+    ReturnValue,
+    Return,
+    Closure,
+    MakeCell,
+    GetLocalCell,
+    GetFreeCell,
+    HashKey,
+}
+
+/// Every opcode, indexed by its byte.
+const OPCODES: [Opcode; 34] = [
+    Opcode::Constant,
+    Opcode::Pop,
+    Opcode::Add,
+    Opcode::Sub,
+    Opcode::Mul,
+    Opcode::Div,
+    Opcode::True,
+    Opcode::False,
+    Opcode::Null,
+    Opcode::Equal,
+    Opcode::NotEqual,
+    Opcode::LessThan,
+    Opcode::GreaterThan,
+    Opcode::Minus,
+    Opcode::Bang,
+    Opcode::JumpNotTruthy,
+    Opcode::Jump,
+    Opcode::GetGlobal,
+    Opcode::SetGlobal,
+    Opcode::GetLocal,
+    Opcode::SetLocal,
+    Opcode::GetFree,
+    Opcode::CurrentClosure,
+    Opcode::Array,
+    Opcode::Hash,
+    Opcode::Index,
+    Opcode::Call,
+    Opcode::ReturnValue,
+    Opcode::Return,
+    Opcode::Closure,
+    Opcode::MakeCell,
+    Opcode::GetLocalCell,
+    Opcode::GetFreeCell,
+    Opcode::HashKey,
+];
+
+impl TryFrom<u8> for Opcode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, u8> {
+        OPCODES.get(byte as usize).copied().ok_or(byte)
+    }
+}
+
+/// The name and operand widths, in bytes, of an opcode.
+#[derive(Debug)]
+pub(crate) struct Definition {
+    pub(crate) name: &'static str,
+    pub(crate) operand_widths: &'static [usize],
+}
+
+impl Opcode {
+    pub(crate) fn definition(self) -> Definition {
+        use Opcode::*;
+        let (name, operand_widths): (_, &[usize]) = match self {
+            Constant => ("OpConstant", &[2]),
+            Pop => ("OpPop", &[]),
+            Add => ("OpAdd", &[]),
+            Sub => ("OpSub", &[]),
+            Mul => ("OpMul", &[]),
+            Div => ("OpDiv", &[]),
+            True => ("OpTrue", &[]),
+            False => ("OpFalse", &[]),
+            Null => ("OpNull", &[]),
+            Equal => ("OpEqual", &[]),
+            NotEqual => ("OpNotEqual", &[]),
+            LessThan => ("OpLessThan", &[]),
+            GreaterThan => ("OpGreaterThan", &[]),
+            Minus => ("OpMinus", &[]),
+            Bang => ("OpBang", &[]),
+            JumpNotTruthy => ("OpJumpNotTruthy", &[2]),
+            Jump => ("OpJump", &[2]),
+            GetGlobal => ("OpGetGlobal", &[2]),
+            SetGlobal => ("OpSetGlobal", &[2]),
+            GetLocal => ("OpGetLocal", &[1]),
+            SetLocal => ("OpSetLocal", &[1]),
+            GetFree => ("OpGetFree", &[1]),
+            CurrentClosure => ("OpCurrentClosure", &[]),
+            Array => ("OpArray", &[2]),
+            Hash => ("OpHash", &[2]),
+            Index => ("OpIndex", &[]),
+            Call => ("OpCall", &[1]),
+            ReturnValue => ("OpReturnValue", &[]),
+            Return => ("OpReturn", &[]),
+            // the constant index of the function, then its number of free variables
+            Closure => ("OpClosure", &[2, 1]),
+            // the local, then the constant index of its name
+            MakeCell => ("OpMakeCell", &[1, 2]),
+            GetLocalCell => ("OpGetLocalCell", &[1]),
+            GetFreeCell => ("OpGetFreeCell", &[1]),
+            // checks the key on top of the stack before its value is made
+            HashKey => ("OpHashKey", &[]),
+        };
+        Definition {
+            name,
+            operand_widths,
+        }
+    }
+}
+
+/// Encodes one instruction. Operands too large for their width are
+/// truncated, so the compiler checks them first.
+pub(crate) fn make(op: Opcode, operands: &[usize]) -> Instructions {
+    let widths = op.definition().operand_widths;
+    let mut instruction = Vec::with_capacity(1 + widths.iter().sum::<usize>());
+    instruction.push(op as u8);
+    for (operand, width) in operands.iter().zip(widths) {
+        match width {
+            2 => instruction.extend_from_slice(&(*operand as u16).to_be_bytes()),
+            1 => instruction.push(*operand as u8),
+            _ => unreachable!("no operand is {} bytes wide", width),
+        }
+    }
+    instruction
+}
+
+/// Decodes the operands of an instruction from `ins`, which starts just
+/// after its opcode, returning them and how many bytes they took. Operands
+/// an opcode does not have are zero.
+pub(crate) fn read_operands(definition: &Definition, ins: &[u8]) -> ([usize; 2], usize) {
+    let mut operands = [0; 2];
+    let mut offset = 0;
+    for (operand, width) in operands.iter_mut().zip(definition.operand_widths) {
+        *operand = match width {
+            2 => read_u16(&ins[offset..]),
+            1 => ins[offset] as usize,
+            _ => unreachable!("no operand is {} bytes wide", width),
+        };
+        offset += width;
+    }
+    (operands, offset)
+}
+
+pub(crate) fn read_u16(ins: &[u8]) -> usize {
+    u16::from_be_bytes([ins[0], ins[1]]) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{make, read_operands, Opcode};
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_make() {
+        let tests = vec![
+            (Opcode::Constant, vec![65534], vec![Opcode::Constant as u8, 255, 254]),
+            (Opcode::Add, vec![], vec![Opcode::Add as u8]),
+            (Opcode::GetLocal, vec![255], vec![Opcode::GetLocal as u8, 255]),
+            (Opcode::Closure, vec![65534, 255], vec![Opcode::Closure as u8, 255, 254, 255]),
+        ];
+        for (op, operands, expected) in tests {
+            assert_eq!(make(op, &operands), expected, "{:?}", op);
+        }
+    }
+
+    #[test]
+    fn test_read_operands() {
+        let tests = vec![
+            (Opcode::Constant, vec![65535], [65535, 0], 2),
+            (Opcode::GetLocal, vec![255], [255, 0], 1),
+            (Opcode::Closure, vec![65535, 255], [65535, 255], 3),
+        ];
+        for (op, operands, expected, bytes_read) in tests {
+            let instruction = make(op, &operands);
+            let (read, n) = read_operands(&op.definition(), &instruction[1..]);
+            assert_eq!(n, bytes_read);
+            assert_eq!(read, expected);
+        }
+    }
+
+    #[test]
+    fn test_opcode_bytes() {
+        for byte in 0..=u8::MAX {
+            match Opcode::try_from(byte) {
+                Ok(op) => assert_eq!(op as u8, byte),
+                Err(err) => assert_eq!(err, byte),
+            }
+        }
+        assert_eq!(Opcode::try_from(Opcode::HashKey as u8), Ok(Opcode::HashKey));
+        assert!(Opcode::try_from(Opcode::HashKey as u8 + 1).is_err());
+    }
+}
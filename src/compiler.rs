@@ -0,0 +1,688 @@
+use crate::ast::{BlockStatement, Expression, FunctionLiteral, IfExpression, Program, Statement};
+use crate::bytecode_file::{self, LoadError};
+use crate::code::{make, Instructions, Opcode};
+use crate::disassembler;
+use crate::engine::{self, Error};
+use crate::object::{CompiledFunction, Object};
+use crate::symbol_table::{Symbol, SymbolScope, SymbolTable};
+use crate::token::Span;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::mem;
+use std::rc::Rc;
+
+/// The largest value of a two-byte operand.
+const MAX_U16: usize = u16::MAX as usize;
+/// The largest value of a one-byte operand.
+const MAX_U8: usize = u8::MAX as usize;
+
+/// A program compiled for the virtual machine: the instructions of its top
+/// level, and the constants and globals it and its functions refer to by
+/// index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bytecode {
+    pub(crate) main: Rc<CompiledFunction>,
+    pub(crate) constants: Vec<Object>,
+    // the name of each global, for looking up one never bound as a host
+    // function or builtin
+    pub(crate) globals: Vec<Rc<str>>,
+}
+
+impl Bytecode {
+    /// A listing of the instructions of the program and of each function
+    /// in it, with the lines of `source` they were compiled from if given.
+    pub fn disassemble(&self, source: Option<&str>) -> String {
+        disassembler::disassemble(self, source)
+    }
+
+    /// The bytecode in the file format `from_bytes` reads, to be run
+    /// without compiling again.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bytecode_file::write(self)
+    }
+
+    /// Reads what `to_bytes` wrote, rejecting anything else, including
+    /// code the VM could not run safely.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Bytecode, LoadError> {
+        bytecode_file::load(bytes)
+    }
+}
+
+/// Why a program that parsed could not be compiled: it is too large for
+/// the operands of some instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}", self.message, self.span)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// Compiles `source` for `Engine::run`.
+pub fn compile(source: &str) -> Result<Bytecode, Error> {
+    let program = engine::parse(source)?;
+    compile_program(&program).map_err(Error::Compile)
+}
+
+pub(crate) fn compile_program(program: &Program) -> Result<Bytecode, CompileError> {
+    let mut compiler = Compiler::new();
+    for stmt in &program.statements {
+        compiler.compile_statement(stmt)?;
+    }
+    let scope = compiler.scopes.pop().unwrap();
+    let main = CompiledFunction {
+        instructions: scope.instructions,
+        positions: scope.positions,
+        num_locals: 0,
+        num_parameters: 0,
+        name: None,
+        text: "<program>".into(),
+    };
+    Ok(Bytecode {
+        main: Rc::new(main),
+        constants: compiler.constants,
+        globals: compiler.symbol_table.definitions(),
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct EmittedInstruction {
+    opcode: Opcode,
+    position: usize,
+}
+
+/// The instructions of the function being compiled, or of the top level.
+#[derive(Debug, Default)]
+struct CompilationScope {
+    instructions: Instructions,
+    positions: Vec<(usize, Span)>,
+    last_instruction: Option<EmittedInstruction>,
+    previous_instruction: Option<EmittedInstruction>,
+}
+
+struct Compiler {
+    constants: Vec<Object>,
+    symbol_table: SymbolTable,
+    scopes: Vec<CompilationScope>,
+}
+
+impl Compiler {
+    fn new() -> Self {
+        Compiler {
+            constants: Vec::new(),
+            symbol_table: SymbolTable::new(),
+            scopes: vec![CompilationScope::default()],
+        }
+    }
+
+    fn compile_statement(&mut self, stmt: &Statement) -> Result<(), CompileError> {
+        match stmt {
+            Statement::Expression(stmt) => {
+                self.compile_expression(&stmt.expression)?;
+                self.emit(Opcode::Pop, &[], stmt.token.span);
+            }
+            Statement::Let(stmt) => {
+                let span = stmt.name.token.span;
+                // defined after the value is compiled, so that the value
+                // sees any outer binding of the same name
+                self.compile_expression(&stmt.value)?;
+                let symbol = self.symbol_table.define(&stmt.name.value);
+                match symbol.scope {
+                    SymbolScope::Global => {
+                        check(symbol.index + 1, MAX_U16 + 1, "globals", span)?;
+                        self.emit(Opcode::SetGlobal, &[symbol.index], span);
+                    }
+                    _ => {
+                        let what = "local bindings in one function";
+                        check(symbol.index + 1, MAX_U8 + 1, what, span)?;
+                        self.emit(Opcode::SetLocal, &[symbol.index], span);
+                    }
+                }
+            }
+            Statement::Return(stmt) => {
+                self.compile_expression(&stmt.return_value)?;
+                self.emit(Opcode::ReturnValue, &[], stmt.token.span);
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_expression(&mut self, exp: &Expression) -> Result<(), CompileError> {
+        let span = exp.span();
+        match exp {
+            Expression::Identifier(ident) => {
+                let symbol = match self.symbol_table.resolve(&ident.value) {
+                    Some(symbol) => symbol,
+                    // bound later, or a host function or builtin, which the
+                    // VM looks up by name while the global is unset
+                    None => self.symbol_table.global().define(&ident.value),
+                };
+                self.load_symbol(&symbol, span)?;
+            }
+            Expression::IntegerLiteral(literal) => self.integer_literal(&literal.value, span)?,
+            Expression::StringLiteral(literal) => {
+                let value = Object::String(literal.value.as_str().into());
+                let index = self.add_constant(value, span)?;
+                self.emit(Opcode::Constant, &[index], span);
+            }
+            Expression::Boolean(boolean) => {
+                let op = if boolean.value { Opcode::True } else { Opcode::False };
+                self.emit(op, &[], span);
+            }
+            Expression::Prefix(exp) => match (exp.operator.as_str(), &*exp.right) {
+                // `-9223372036854775808` is in range even though its digits are not
+                ("-", Expression::IntegerLiteral(literal)) => {
+                    self.integer_literal(&-&literal.value, span)?
+                }
+                (operator, right) => {
+                    self.compile_expression(right)?;
+                    let op = match operator {
+                        "!" => Opcode::Bang,
+                        "-" => Opcode::Minus,
+                        operator => unreachable!("the parser has no prefix operator {}", operator),
+                    };
+                    self.emit(op, &[], span);
+                }
+            },
+            Expression::Infix(exp) => {
+                self.compile_expression(&exp.left)?;
+                self.compile_expression(&exp.right)?;
+                let op = match exp.operator.as_str() {
+                    "+" => Opcode::Add,
+                    "-" => Opcode::Sub,
+                    "*" => Opcode::Mul,
+                    "/" => Opcode::Div,
+                    "==" => Opcode::Equal,
+                    "!=" => Opcode::NotEqual,
+                    "<" => Opcode::LessThan,
+                    ">" => Opcode::GreaterThan,
+                    operator => unreachable!("the parser has no infix operator {}", operator),
+                };
+                self.emit(op, &[], span);
+            }
+            Expression::If(exp) => self.compile_if_expression(exp)?,
+            Expression::FunctionLiteral(func) => self.compile_function_literal(func)?,
+            Expression::Call(call) => {
+                self.compile_expression(&call.function)?;
+                for arg in &call.arguments {
+                    self.compile_expression(arg)?;
+                }
+                check(call.arguments.len(), MAX_U8, "arguments", span)?;
+                self.emit(Opcode::Call, &[call.arguments.len()], span);
+            }
+            Expression::ArrayLiteral(array) => {
+                for element in &array.elements {
+                    self.compile_expression(element)?;
+                }
+                check(array.elements.len(), MAX_U16, "array elements", span)?;
+                self.emit(Opcode::Array, &[array.elements.len()], span);
+            }
+            Expression::HashLiteral(hash) => {
+                for (key, value) in &hash.pairs {
+                    self.compile_expression(key)?;
+                    // as the evaluator does, before the value
+                    self.emit(Opcode::HashKey, &[], key.span());
+                    self.compile_expression(value)?;
+                }
+                check(hash.pairs.len() * 2, MAX_U16, "hash keys and values", span)?;
+                self.emit(Opcode::Hash, &[hash.pairs.len() * 2], span);
+            }
+            Expression::Index(exp) => {
+                self.compile_expression(&exp.left)?;
+                self.compile_expression(&exp.index)?;
+                self.emit(Opcode::Index, &[], span);
+            }
+        }
+        Ok(())
+    }
+
+    /// Leaves the value of the branch taken on the stack, `null` for a
+    /// branch that ends in a `let` or is missing.
+    fn compile_if_expression(&mut self, exp: &IfExpression) -> Result<(), CompileError> {
+        let span = exp.token.span;
+        self.compile_expression(&exp.condition)?;
+        // the jump targets are patched in once the branches are compiled
+        let jump_not_truthy = self.emit(Opcode::JumpNotTruthy, &[MAX_U16], span);
+        self.compile_block_statement(&exp.consequence)?;
+        let jump = self.emit(Opcode::Jump, &[MAX_U16], span);
+
+        self.change_operand(jump_not_truthy, span)?;
+        match &exp.alternative {
+            Some(alternative) => self.compile_block_statement(alternative)?,
+            None => {
+                self.emit(Opcode::Null, &[], span);
+            }
+        }
+        self.change_operand(jump, span)
+    }
+
+    fn compile_block_statement(&mut self, block: &BlockStatement) -> Result<(), CompileError> {
+        for stmt in &block.statements {
+            self.compile_statement(stmt)?;
+        }
+        if self.last_instruction_is(Opcode::Pop) {
+            self.remove_last_pop();
+        } else {
+            self.emit(Opcode::Null, &[], block.end);
+        }
+        Ok(())
+    }
+
+    fn compile_function_literal(&mut self, func: &FunctionLiteral) -> Result<(), CompileError> {
+        let span = func.token.span;
+        self.enter_scope();
+        if let Some(name) = &func.name {
+            self.symbol_table.define_function_name(name);
+        }
+        for param in &func.parameters {
+            let symbol = self.symbol_table.define(&param.value);
+            let what = "local bindings in one function";
+            check(symbol.index + 1, MAX_U8 + 1, what, param.token.span)?;
+        }
+        // the variables closures made here capture live in cells they share
+        // with this function, bound or not yet bound
+        for name in captured_names(func) {
+            let symbol = match self.symbol_table.resolve(&name) {
+                Some(symbol) if symbol.scope == SymbolScope::Local => symbol,
+                _ => self.symbol_table.reserve(&name),
+            };
+            check(symbol.index + 1, MAX_U8 + 1, "local bindings in one function", span)?;
+            let constant = self.add_constant(Object::String(name.as_str().into()), span)?;
+            self.emit(Opcode::MakeCell, &[symbol.index, constant], span);
+            // until rebound, the function's name is the function itself
+            if func.name.as_deref() == Some(name.as_str()) {
+                self.emit(Opcode::CurrentClosure, &[], span);
+                self.emit(Opcode::SetLocal, &[symbol.index], span);
+            }
+        }
+        for stmt in &func.body.statements {
+            self.compile_statement(stmt)?;
+        }
+        // the value of the last expression is the function's
+        if self.last_instruction_is(Opcode::Pop) {
+            self.replace_last_pop_with_return();
+        }
+        if !self.last_instruction_is(Opcode::ReturnValue) {
+            self.emit(Opcode::Return, &[], func.body.end);
+        }
+
+        let free_symbols = mem::take(&mut self.symbol_table.free_symbols);
+        let num_locals = self.symbol_table.num_definitions;
+        let scope = self.leave_scope();
+        for symbol in &free_symbols {
+            self.capture_symbol(symbol, span);
+        }
+        check(free_symbols.len(), MAX_U8, "captured variables", span)?;
+
+        let params: Vec<_> = func.parameters.iter().map(|p| p.value.as_str()).collect();
+        let compiled = CompiledFunction {
+            instructions: scope.instructions,
+            positions: scope.positions,
+            num_locals,
+            num_parameters: func.parameters.len(),
+            name: func.name.clone(),
+            text: format!("fn({}) {{ {} }}", params.join(", "), func.body).into(),
+        };
+        let index = self.add_constant(Object::CompiledFunction(Rc::new(compiled)), span)?;
+        self.emit(Opcode::Closure, &[index, free_symbols.len()], span);
+        Ok(())
+    }
+
+    fn load_symbol(&mut self, symbol: &Symbol, span: Span) -> Result<(), CompileError> {
+        match symbol.scope {
+            SymbolScope::Global => {
+                check(symbol.index + 1, MAX_U16 + 1, "globals", span)?;
+                self.emit(Opcode::GetGlobal, &[symbol.index], span);
+            }
+            SymbolScope::Local => {
+                self.emit(Opcode::GetLocal, &[symbol.index], span);
+            }
+            SymbolScope::Free => {
+                check(symbol.index + 1, MAX_U8 + 1, "captured variables", span)?;
+                self.emit(Opcode::GetFree, &[symbol.index], span);
+            }
+            SymbolScope::Function => {
+                self.emit(Opcode::CurrentClosure, &[], span);
+            }
+        }
+        Ok(())
+    }
+
+    /// Pushes what a closure made here captures of a variable `load_symbol`
+    /// would push the value of: the cell holding it, or the function itself.
+    fn capture_symbol(&mut self, symbol: &Symbol, span: Span) {
+        match symbol.scope {
+            SymbolScope::Local => self.emit(Opcode::GetLocalCell, &[symbol.index], span),
+            SymbolScope::Free => self.emit(Opcode::GetFreeCell, &[symbol.index], span),
+            SymbolScope::Function => self.emit(Opcode::CurrentClosure, &[], span),
+            SymbolScope::Global => unreachable!("globals are not captured"),
+        };
+    }
+
+    /// A constant for an integer literal. The VM checks one too large for an
+    /// i64 against the integer mode each time it runs.
+    fn integer_literal(&mut self, value: &BigInt, span: Span) -> Result<(), CompileError> {
+        let value = match value.to_i64() {
+            Some(value) => Object::Integer(value),
+            None => Object::BigInteger(Rc::new(value.clone())),
+        };
+        let index = self.add_constant(value, span)?;
+        self.emit(Opcode::Constant, &[index], span);
+        Ok(())
+    }
+
+    fn add_constant(&mut self, obj: Object, span: Span) -> Result<usize, CompileError> {
+        check(self.constants.len() + 1, MAX_U16 + 1, "constants", span)?;
+        self.constants.push(obj);
+        Ok(self.constants.len() - 1)
+    }
+
+    /// Appends an instruction compiled from the code at `span`, returning
+    /// its offset.
+    fn emit(&mut self, op: Opcode, operands: &[usize], span: Span) -> usize {
+        let instruction = make(op, operands);
+        let scope = self.scope();
+        let position = scope.instructions.len();
+        if scope.positions.last().map(|(_, last)| *last) != Some(span) {
+            scope.positions.push((position, span));
+        }
+        scope.instructions.extend_from_slice(&instruction);
+        scope.previous_instruction = scope.last_instruction;
+        scope.last_instruction = Some(EmittedInstruction { opcode: op, position });
+        position
+    }
+
+    /// Points the jump at `position` to the end of the instructions so far.
+    fn change_operand(&mut self, position: usize, span: Span) -> Result<(), CompileError> {
+        let scope = self.scope();
+        let target = scope.instructions.len();
+        check(target, MAX_U16, "bytes of instructions in one function", span)?;
+        let operand = (target as u16).to_be_bytes();
+        scope.instructions[position + 1..position + 3].copy_from_slice(&operand);
+        Ok(())
+    }
+
+    fn last_instruction_is(&mut self, op: Opcode) -> bool {
+        matches!(self.scope().last_instruction, Some(last) if last.opcode == op)
+    }
+
+    fn remove_last_pop(&mut self) {
+        let scope = self.scope();
+        let last = scope.last_instruction.unwrap();
+        scope.instructions.truncate(last.position);
+        while matches!(scope.positions.last(), Some((start, _)) if *start >= last.position) {
+            scope.positions.pop();
+        }
+        scope.last_instruction = scope.previous_instruction;
+    }
+
+    fn replace_last_pop_with_return(&mut self) {
+        let scope = self.scope();
+        let last = scope.last_instruction.as_mut().unwrap();
+        scope.instructions[last.position] = Opcode::ReturnValue as u8;
+        last.opcode = Opcode::ReturnValue;
+    }
+
+    fn scope(&mut self) -> &mut CompilationScope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    fn enter_scope(&mut self) {
+        self.scopes.push(CompilationScope::default());
+        self.symbol_table = SymbolTable::new_enclosed(mem::take(&mut self.symbol_table));
+    }
+
+    fn leave_scope(&mut self) -> CompilationScope {
+        self.symbol_table = *self.symbol_table.outer.take().unwrap();
+        self.scopes.pop().unwrap()
+    }
+}
+
+/// The names `func` binds, by a parameter or a `let` of its own, that a
+/// function literal inside it mentions. Some may be shadowed there.
+fn captured_names(func: &FunctionLiteral) -> Vec<String> {
+    let mut bound: Vec<String> = func.parameters.iter().map(|p| p.value.clone()).collect();
+    let mut mentioned = HashSet::new();
+    for stmt in &func.body.statements {
+        scan_statement(stmt, false, &mut bound, &mut mentioned);
+    }
+    let mut names = Vec::new();
+    for name in bound {
+        if mentioned.contains(&name) && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Adds to `bound` the names a `let` outside any function literal binds,
+/// and to `mentioned` the names used inside one (`nested`).
+fn scan_statement(
+    stmt: &Statement,
+    nested: bool,
+    bound: &mut Vec<String>,
+    mentioned: &mut HashSet<String>,
+) {
+    match stmt {
+        Statement::Let(stmt) => {
+            scan_expression(&stmt.value, nested, bound, mentioned);
+            if !nested {
+                bound.push(stmt.name.value.clone());
+            }
+        }
+        Statement::Return(stmt) => scan_expression(&stmt.return_value, nested, bound, mentioned),
+        Statement::Expression(stmt) => scan_expression(&stmt.expression, nested, bound, mentioned),
+    }
+}
+
+fn scan_expression(
+    exp: &Expression,
+    nested: bool,
+    bound: &mut Vec<String>,
+    mentioned: &mut HashSet<String>,
+) {
+    let mut scan = |exp: &Expression| scan_expression(exp, nested, bound, mentioned);
+    match exp {
+        Expression::Identifier(ident) => {
+            if nested {
+                mentioned.insert(ident.value.clone());
+            }
+        }
+        Expression::IntegerLiteral(_) | Expression::StringLiteral(_) | Expression::Boolean(_) => {}
+        Expression::Prefix(exp) => scan(&exp.right),
+        Expression::Infix(exp) => {
+            scan(&exp.left);
+            scan(&exp.right);
+        }
+        Expression::If(exp) => {
+            scan(&exp.condition);
+            let blocks = Some(&exp.consequence).into_iter().chain(&exp.alternative);
+            for stmt in blocks.flat_map(|block| &block.statements) {
+                scan_statement(stmt, nested, bound, mentioned);
+            }
+        }
+        Expression::FunctionLiteral(func) => {
+            for stmt in &func.body.statements {
+                scan_statement(stmt, true, bound, mentioned);
+            }
+        }
+        Expression::Call(call) => {
+            scan(&call.function);
+            call.arguments.iter().for_each(scan);
+        }
+        Expression::ArrayLiteral(array) => array.elements.iter().for_each(scan),
+        Expression::HashLiteral(hash) => {
+            for (key, value) in &hash.pairs {
+                scan(key);
+                scan(value);
+            }
+        }
+        Expression::Index(exp) => {
+            scan(&exp.left);
+            scan(&exp.index);
+        }
+    }
+}
+
+/// Fails if there are more than `max` of something an operand counts or
+/// indexes.
+fn check(count: usize, max: usize, what: &str, span: Span) -> Result<(), CompileError> {
+    if count <= max {
+        return Ok(());
+    }
+    Err(CompileError {
+        message: format!("too many {} (at most {})", what, max),
+        span,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, Bytecode};
+    use crate::code::{make, Instructions, Opcode};
+    use crate::engine::Error;
+    use crate::object::Object;
+
+    fn concat(instructions: Vec<Instructions>) -> Instructions {
+        instructions.concat()
+    }
+
+    fn function(bytecode: &Bytecode, index: usize) -> Instructions {
+        match &bytecode.constants[index] {
+            Object::CompiledFunction(func) => func.instructions.clone(),
+            obj => panic!("not a compiled function: {:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_expressions() {
+        let bytecode = compile("1 + 2; -\"a\"; -3").unwrap();
+        let expected = concat(vec![
+            make(Opcode::Constant, &[0]),
+            make(Opcode::Constant, &[1]),
+            make(Opcode::Add, &[]),
+            make(Opcode::Pop, &[]),
+            make(Opcode::Constant, &[2]),
+            make(Opcode::Minus, &[]),
+            make(Opcode::Pop, &[]),
+            // a negated literal is a constant
+            make(Opcode::Constant, &[3]),
+            make(Opcode::Pop, &[]),
+        ]);
+        assert_eq!(bytecode.main.instructions, expected);
+        let a = Object::String("a".into());
+        assert_eq!(
+            bytecode.constants,
+            vec![Object::Integer(1), Object::Integer(2), a, Object::Integer(-3)]
+        );
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let bytecode = compile("if (true) { 10 }; 3333;").unwrap();
+        let expected = concat(vec![
+            make(Opcode::True, &[]),
+            make(Opcode::JumpNotTruthy, &[10]),
+            make(Opcode::Constant, &[0]),
+            make(Opcode::Jump, &[11]),
+            make(Opcode::Null, &[]),
+            make(Opcode::Pop, &[]),
+            make(Opcode::Constant, &[1]),
+            make(Opcode::Pop, &[]),
+        ]);
+        assert_eq!(bytecode.main.instructions, expected);
+    }
+
+    #[test]
+    fn test_closures() {
+        let bytecode = compile("let f = fn(a) { fn(b) { a + b } };\nf").unwrap();
+        let inner = concat(vec![
+            make(Opcode::GetFree, &[0]),
+            make(Opcode::GetLocal, &[0]),
+            make(Opcode::Add, &[]),
+            make(Opcode::ReturnValue, &[]),
+        ]);
+        // `a` is shared with the closure through a cell, named by constant 0
+        let outer = concat(vec![
+            make(Opcode::MakeCell, &[0, 0]),
+            make(Opcode::GetLocalCell, &[0]),
+            make(Opcode::Closure, &[1, 1]),
+            make(Opcode::ReturnValue, &[]),
+        ]);
+        let main = concat(vec![
+            make(Opcode::Closure, &[2, 0]),
+            make(Opcode::SetGlobal, &[0]),
+            make(Opcode::GetGlobal, &[0]),
+            make(Opcode::Pop, &[]),
+        ]);
+        assert_eq!(bytecode.constants[0], Object::String("a".into()));
+        assert_eq!(function(&bytecode, 1), inner);
+        assert_eq!(function(&bytecode, 2), outer);
+        assert_eq!(bytecode.main.instructions, main);
+        assert_eq!(bytecode.globals, vec!["f".into()]);
+        // each instruction maps back to the code it came from
+        let spans: Vec<_> = bytecode
+            .main
+            .positions
+            .iter()
+            .map(|(at, span)| (*at, span.to_string()))
+            .collect();
+        let expected = vec![(0, "1:9".to_string()), (4, "1:5".to_string()), (7, "2:1".to_string())];
+        assert_eq!(spans, expected);
+    }
+
+    #[test]
+    fn test_closures_see_later_bindings() {
+        let bytecode = compile("fn() { let g = fn() { y }; let y = 2; g }").unwrap();
+        let inner = concat(vec![make(Opcode::GetFree, &[0]), make(Opcode::ReturnValue, &[])]);
+        // `y` gets its slot, and its cell, before its `let`
+        let outer = concat(vec![
+            make(Opcode::MakeCell, &[0, 0]),
+            make(Opcode::GetLocalCell, &[0]),
+            make(Opcode::Closure, &[1, 1]),
+            make(Opcode::SetLocal, &[1]),
+            make(Opcode::Constant, &[2]),
+            make(Opcode::SetLocal, &[0]),
+            make(Opcode::GetLocal, &[1]),
+            make(Opcode::ReturnValue, &[]),
+        ]);
+        assert_eq!(function(&bytecode, 1), inner);
+        assert_eq!(function(&bytecode, 3), outer);
+        assert!(bytecode.globals.is_empty());
+    }
+
+    #[test]
+    fn test_recursive_functions_refer_to_themselves() {
+        let bytecode = compile("let f = fn(x) { f(x) }; let g = fn() { g };").unwrap();
+        let expected = concat(vec![
+            make(Opcode::CurrentClosure, &[]),
+            make(Opcode::GetLocal, &[0]),
+            make(Opcode::Call, &[1]),
+            make(Opcode::ReturnValue, &[]),
+        ]);
+        assert_eq!(function(&bytecode, 0), expected);
+        let expected =
+            concat(vec![make(Opcode::CurrentClosure, &[]), make(Opcode::ReturnValue, &[])]);
+        assert_eq!(function(&bytecode, 1), expected);
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let args = vec!["1"; 256].join(", ");
+        match compile(&format!("let f = fn() {{ 1 }};\nf({})", args)) {
+            Err(Error::Compile(err)) => {
+                assert_eq!(err.to_string(), "too many arguments (at most 255) at 2:1")
+            }
+            result => panic!("expected a compile error. got={:?}", result),
+        }
+        assert!(matches!(compile("let = 1;"), Err(Error::Parse(_))));
+    }
+}
@@ -0,0 +1,173 @@
+use crate::repl;
+use monkey_rs::{Debugger, Pause, Resume};
+use std::io::{BufRead, Write};
+
+static PROMPT: &str = "(debug) ";
+
+static HELP: &str = "\
+break LINE, b LINE     stop at statements starting on LINE
+delete LINE, d LINE    remove the breakpoint on LINE
+step, s                run to the next statement, entering calls
+next, n                run to the next statement, stepping over calls
+finish, f              run until the current function returns
+continue, c            run to the next breakpoint
+print EXPR, p EXPR     evaluate EXPR in the current scope
+set NAME = EXPR        bind NAME to the value of EXPR
+locals                 list the bindings of each scope
+backtrace, bt          print the call stack
+list, l                show the source around the current line
+quit, q                stop the program";
+
+/// A debugger driven by commands typed at a prompt.
+pub(crate) struct Console<I, O> {
+    input: I,
+    out: O,
+    lines: Vec<String>,
+}
+
+impl<I: BufRead, O: Write> Console<I, O> {
+    /// `source` is the program being debugged, for showing where it stopped.
+    pub(crate) fn new(input: I, out: O, source: &str) -> Self {
+        Console {
+            input,
+            out,
+            lines: source.lines().map(String::from).collect(),
+        }
+    }
+
+    /// Prints source lines `from..=to`, marking the current one.
+    fn show(&mut self, from: usize, to: usize, current: usize) {
+        for number in from.max(1)..=to.min(self.lines.len()) {
+            let marker = if number == current { "=>" } else { "  " };
+            let line = &self.lines[number - 1];
+            writeln!(self.out, "{} {:>4} | {}", marker, number, line).unwrap();
+        }
+    }
+
+    /// Carries out a command that does not resume evaluation.
+    fn command(&mut self, pause: &mut Pause<'_>, command: &str, arg: &str) {
+        match command {
+            "break" | "b" => match arg.parse::<usize>() {
+                Ok(line) => {
+                    pause.breakpoints().insert(line);
+                    writeln!(self.out, "breakpoint on line {}", line).unwrap();
+                }
+                Err(_) => writeln!(self.out, "usage: break LINE").unwrap(),
+            },
+            "delete" | "d" => match arg.parse::<usize>() {
+                Ok(line) if pause.breakpoints().remove(&line) => {}
+                Ok(line) => writeln!(self.out, "no breakpoint on line {}", line).unwrap(),
+                Err(_) => writeln!(self.out, "usage: delete LINE").unwrap(),
+            },
+            "print" | "p" => match pause.evaluate(arg) {
+                Ok(value) => writeln!(self.out, "{}", value).unwrap(),
+                Err(err) => repl::report(&mut self.out, &err),
+            },
+            "set" => match arg.split_once('=') {
+                Some((name, exp)) => match pause.evaluate(exp) {
+                    Ok(value) => pause.set(name.trim(), value),
+                    Err(err) => repl::report(&mut self.out, &err),
+                },
+                None => writeln!(self.out, "usage: set NAME = EXPR").unwrap(),
+            },
+            "locals" => {
+                let scopes = pause.scopes();
+                let globals = scopes.len() - 1;
+                for (i, scope) in scopes.into_iter().enumerate() {
+                    let title = match i {
+                        _ if i == globals => "globals",
+                        0 => "locals",
+                        _ => "enclosing",
+                    };
+                    writeln!(self.out, "{}:", title).unwrap();
+                    for (name, value) in scope {
+                        writeln!(self.out, "    {} = {}", name, value).unwrap();
+                    }
+                }
+            }
+            "backtrace" | "bt" => writeln!(self.out, "{}", pause.backtrace()).unwrap(),
+            "list" | "l" => {
+                let line = pause.span().line;
+                self.show(line.saturating_sub(3), line + 3, line);
+            }
+            "help" | "h" => writeln!(self.out, "{}", HELP).unwrap(),
+            "" => {}
+            _ => writeln!(self.out, "unknown command `{}`; try `help`", command).unwrap(),
+        }
+    }
+}
+
+impl<I: BufRead, O: Write> Debugger for Console<I, O> {
+    fn paused(&mut self, pause: &mut Pause<'_>) -> Resume {
+        let line = pause.span().line;
+        self.show(line, line, line);
+        loop {
+            write!(self.out, "{}", PROMPT).unwrap();
+            self.out.flush().unwrap();
+            let mut input = String::new();
+            match self.input.read_line(&mut input) {
+                Ok(0) | Err(_) => return Resume::Quit,
+                Ok(_) => {}
+            }
+            let input = input.trim();
+            let (command, arg) = input.split_once(' ').unwrap_or((input, ""));
+            match command {
+                "step" | "s" => return Resume::StepInto,
+                "next" | "n" => return Resume::StepOver,
+                "finish" | "f" => return Resume::StepOut,
+                "continue" | "c" => return Resume::Continue,
+                "quit" | "q" => return Resume::Quit,
+                _ => self.command(pause, command, arg.trim()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Console;
+    use monkey_rs::Engine;
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    /// Output the test can still read once the engine owns the console.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_console_session() {
+        let source = "let add = fn(a, b) {\n  a + b\n};\nlet x = add(1, 2);\nputs(x);";
+        let commands = "b 2\nc\nlocals\nset a = 10\nbt\nfinish\np x\nq\n";
+        let out = Shared::default();
+        let console = Console::new(commands.as_bytes(), out.clone(), source);
+        let result = Engine::new().with_debugger(console).eval(source);
+
+        assert_eq!(result.unwrap_err().to_string(), "stopped by the debugger at 5:1");
+        let expected = "\
+=>    1 | let add = fn(a, b) {
+(debug) breakpoint on line 2
+(debug) =>    2 |   a + b
+(debug) locals:
+    a = 1
+    b = 2
+globals:
+    add = fn(a, b) { (a + b) }
+(debug) (debug)     in add at 2:3
+    in <program> at 4:9
+(debug) =>    5 | puts(x);
+(debug) 12
+(debug) ";
+        assert_eq!(String::from_utf8(out.0.take()).unwrap(), expected);
+    }
+}
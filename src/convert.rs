@@ -0,0 +1,267 @@
+use crate::object::{HashPair, Object};
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::hash::Hash;
+use std::rc::Rc;
+
+/// Rust values that have a Monkey equivalent.
+pub trait ToMonkey {
+    fn to_monkey(self) -> Object;
+}
+
+/// Rust values that can be hash keys. Monkey only hashes integers, booleans
+/// and strings, so these are the only keys a `HashMap` can convert with.
+pub trait ToMonkeyKey: ToMonkey {}
+
+/// Rust values that can be read out of a Monkey object.
+pub trait FromMonkey: Sized {
+    fn from_monkey(obj: &Object) -> Result<Self, ConversionError>;
+}
+
+/// An object that does not have the type the Rust side asked for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionError {
+    pub expected: &'static str,
+    pub got: &'static str,
+}
+
+impl ConversionError {
+    fn new(expected: &'static str, obj: &Object) -> Self {
+        ConversionError {
+            expected,
+            got: obj.type_name(),
+        }
+    }
+}
+
+impl Display for ConversionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected {}, got {}", self.expected, self.got)
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+impl ToMonkey for Object {
+    fn to_monkey(self) -> Object {
+        self
+    }
+}
+
+impl FromMonkey for Object {
+    fn from_monkey(obj: &Object) -> Result<Self, ConversionError> {
+        Ok(obj.clone())
+    }
+}
+
+impl ToMonkey for () {
+    fn to_monkey(self) -> Object {
+        Object::Null
+    }
+}
+
+impl ToMonkey for i64 {
+    fn to_monkey(self) -> Object {
+        Object::Integer(self)
+    }
+}
+
+impl ToMonkeyKey for i64 {}
+
+impl FromMonkey for i64 {
+    fn from_monkey(obj: &Object) -> Result<Self, ConversionError> {
+        match obj {
+            Object::Integer(value) => Ok(*value),
+            Object::BigInteger(value) => value.to_i64().ok_or(ConversionError {
+                expected: "INTEGER that fits in an i64",
+                got: "INTEGER",
+            }),
+            obj => Err(ConversionError::new("INTEGER", obj)),
+        }
+    }
+}
+
+impl ToMonkey for BigInt {
+    fn to_monkey(self) -> Object {
+        match self.to_i64() {
+            Some(value) => Object::Integer(value),
+            None => Object::BigInteger(Rc::new(self)),
+        }
+    }
+}
+
+impl FromMonkey for BigInt {
+    fn from_monkey(obj: &Object) -> Result<Self, ConversionError> {
+        match obj {
+            Object::Integer(value) => Ok(BigInt::from(*value)),
+            Object::BigInteger(value) => Ok(BigInt::clone(value)),
+            obj => Err(ConversionError::new("INTEGER", obj)),
+        }
+    }
+}
+
+impl ToMonkey for bool {
+    fn to_monkey(self) -> Object {
+        Object::Boolean(self)
+    }
+}
+
+impl ToMonkeyKey for bool {}
+
+impl FromMonkey for bool {
+    fn from_monkey(obj: &Object) -> Result<Self, ConversionError> {
+        match obj {
+            Object::Boolean(value) => Ok(*value),
+            obj => Err(ConversionError::new("BOOLEAN", obj)),
+        }
+    }
+}
+
+impl ToMonkey for String {
+    fn to_monkey(self) -> Object {
+        Object::String(self.into())
+    }
+}
+
+impl ToMonkeyKey for String {}
+
+impl ToMonkey for &str {
+    fn to_monkey(self) -> Object {
+        Object::String(self.into())
+    }
+}
+
+impl ToMonkeyKey for &str {}
+
+impl FromMonkey for String {
+    fn from_monkey(obj: &Object) -> Result<Self, ConversionError> {
+        match obj {
+            Object::String(value) => Ok(value.to_string()),
+            obj => Err(ConversionError::new("STRING", obj)),
+        }
+    }
+}
+
+/// `None` is `null`.
+impl<T: ToMonkey> ToMonkey for Option<T> {
+    fn to_monkey(self) -> Object {
+        match self {
+            Some(value) => value.to_monkey(),
+            None => Object::Null,
+        }
+    }
+}
+
+impl<T: FromMonkey> FromMonkey for Option<T> {
+    fn from_monkey(obj: &Object) -> Result<Self, ConversionError> {
+        match obj {
+            Object::Null => Ok(None),
+            obj => T::from_monkey(obj).map(Some),
+        }
+    }
+}
+
+impl<T: ToMonkey> ToMonkey for Vec<T> {
+    fn to_monkey(self) -> Object {
+        Object::Array(Rc::new(self.into_iter().map(T::to_monkey).collect()))
+    }
+}
+
+impl<T: FromMonkey> FromMonkey for Vec<T> {
+    fn from_monkey(obj: &Object) -> Result<Self, ConversionError> {
+        match obj {
+            Object::Array(elements) => elements.iter().map(T::from_monkey).collect(),
+            obj => Err(ConversionError::new("ARRAY", obj)),
+        }
+    }
+}
+
+impl<K: ToMonkeyKey, V: ToMonkey> ToMonkey for HashMap<K, V> {
+    fn to_monkey(self) -> Object {
+        let mut pairs = BTreeMap::new();
+        for (key, value) in self {
+            let key = key.to_monkey();
+            let hash_key = key.hash_key().expect("ToMonkeyKey types are hashable");
+            let value = value.to_monkey();
+            pairs.insert(hash_key, HashPair { key, value });
+        }
+        Object::Hash(Rc::new(pairs))
+    }
+}
+
+impl<K: FromMonkey + Eq + Hash, V: FromMonkey> FromMonkey for HashMap<K, V> {
+    fn from_monkey(obj: &Object) -> Result<Self, ConversionError> {
+        match obj {
+            Object::Hash(pairs) => pairs
+                .values()
+                .map(|pair| Ok((K::from_monkey(&pair.key)?, V::from_monkey(&pair.value)?)))
+                .collect(),
+            obj => Err(ConversionError::new("HASH", obj)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConversionError, FromMonkey, ToMonkey};
+    use crate::engine::Engine;
+    use crate::object::{ErrorKind, Object};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_round_trips() {
+        let mut scores = HashMap::new();
+        scores.insert("ann".to_string(), vec![Some(1), None]);
+        scores.insert("bob".to_string(), vec![]);
+        let obj = scores.clone().to_monkey();
+        assert_eq!(obj.type_name(), "HASH");
+        assert_eq!(HashMap::<String, Vec<Option<i64>>>::from_monkey(&obj), Ok(scores));
+
+        assert_eq!(i64::from_monkey(&42.to_monkey()), Ok(42));
+        assert_eq!(bool::from_monkey(&true.to_monkey()), Ok(true));
+        assert_eq!(String::from_monkey(&"héllo".to_monkey()), Ok("héllo".to_string()));
+        assert_eq!(Option::<bool>::from_monkey(&Object::Null), Ok(None));
+        assert_eq!(().to_monkey(), Object::Null);
+    }
+
+    #[test]
+    fn test_conversion_errors() {
+        let err = Vec::<i64>::from_monkey(&vec!["1"].to_monkey()).unwrap_err();
+        assert_eq!(err, ConversionError { expected: "INTEGER", got: "STRING" });
+        assert_eq!(err.to_string(), "expected INTEGER, got STRING");
+        assert!(HashMap::<i64, i64>::from_monkey(&Object::Integer(1)).is_err());
+    }
+
+    #[test]
+    fn test_host_functions_and_results() {
+        let mut engine = Engine::new();
+        engine.register("sum", |args| {
+            let numbers: Vec<i64> = args.get_as(0)?;
+            Ok(numbers.iter().sum::<i64>().to_monkey())
+        });
+        engine.register("words", |args| {
+            let words: Vec<String> = args.string(0)?.split_whitespace().map(String::from).collect();
+            Ok(words.to_monkey())
+        });
+
+        let result = engine.eval(r#"[sum([1, 2, 3]), len(words("a b c d"))]"#).unwrap();
+        assert_eq!(Vec::<i64>::from_monkey(&result), Ok(vec![6, 4]));
+
+        let err = engine.eval(r#"sum(["1"])"#).unwrap_err();
+        match err {
+            crate::engine::Error::Runtime(err) => {
+                assert_eq!(err.kind, ErrorKind::InvalidArgument);
+                assert_eq!(err.message, "argument 1 to `sum` must be INTEGER, got STRING");
+            }
+            err => panic!("expected a runtime error. got={:?}", err),
+        }
+
+        // conversion errors work with `?` anywhere else
+        let counts = || -> Result<HashMap<String, i64>, Box<dyn std::error::Error>> {
+            Ok(HashMap::from_monkey(&engine.eval(r#"{"a": 1, "b": 2}"#)?)?)
+        };
+        assert_eq!(counts().unwrap()["b"], 2);
+    }
+}
@@ -0,0 +1,226 @@
+use crate::ast::{BlockStatement, Expression, Program, Statement};
+use crate::token::Span;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Counts how often each statement and each branch of an `if` runs, for
+/// the programs it was shown before they ran.
+#[derive(Debug, Default)]
+pub(crate) struct Recorder {
+    statements: BTreeMap<Span, u64>,
+    // the times the consequence and the alternative ran
+    branches: BTreeMap<Span, [u64; 2]>,
+}
+
+impl Recorder {
+    /// Adds the statements and `if`s of `program`, function bodies
+    /// included, so those that never run are reported too.
+    pub(crate) fn add_program(&mut self, program: &Program) {
+        self.add_statements(&program.statements);
+    }
+
+    /// Counts the statement at `span` if it belongs to an added program.
+    /// Code evaluated from elsewhere, like a debugger's, is not covered.
+    pub(crate) fn statement(&mut self, span: Span) {
+        if let Some(count) = self.statements.get_mut(&span) {
+            *count += 1;
+        }
+    }
+
+    pub(crate) fn branch(&mut self, span: Span, consequence: bool) {
+        if let Some(counts) = self.branches.get_mut(&span) {
+            counts[if consequence { 0 } else { 1 }] += 1;
+        }
+    }
+
+    pub(crate) fn coverage(&self) -> Coverage {
+        let mut lines = BTreeMap::new();
+        for (span, count) in &self.statements {
+            let line = lines.entry(span.line).or_insert(0);
+            *line = (*line).max(*count);
+        }
+        let branches = self
+            .branches
+            .iter()
+            .map(|(span, counts)| Branch {
+                span: *span,
+                consequence: counts[0],
+                alternative: counts[1],
+            })
+            .collect();
+        Coverage { lines, branches }
+    }
+
+    fn add_statements(&mut self, statements: &[Statement]) {
+        for stmt in statements {
+            self.statements.entry(stmt.span()).or_insert(0);
+            match stmt {
+                Statement::Let(stmt) => self.add_expression(&stmt.value),
+                Statement::Return(stmt) => self.add_expression(&stmt.return_value),
+                Statement::Expression(stmt) => self.add_expression(&stmt.expression),
+            }
+        }
+    }
+
+    fn add_block(&mut self, block: &BlockStatement) {
+        self.add_statements(&block.statements);
+    }
+
+    fn add_expression(&mut self, exp: &Expression) {
+        match exp {
+            Expression::Identifier(_)
+            | Expression::IntegerLiteral(_)
+            | Expression::StringLiteral(_)
+            | Expression::Boolean(_) => {}
+            Expression::Prefix(exp) => self.add_expression(&exp.right),
+            Expression::Infix(exp) => {
+                self.add_expression(&exp.left);
+                self.add_expression(&exp.right);
+            }
+            Expression::If(exp) => {
+                self.branches.entry(exp.token.span).or_insert([0, 0]);
+                self.add_expression(&exp.condition);
+                self.add_block(&exp.consequence);
+                if let Some(alternative) = &exp.alternative {
+                    self.add_block(alternative);
+                }
+            }
+            Expression::FunctionLiteral(func) => self.add_block(&func.body),
+            Expression::Call(exp) => {
+                self.add_expression(&exp.function);
+                exp.arguments.iter().for_each(|arg| self.add_expression(arg));
+            }
+            Expression::ArrayLiteral(array) => {
+                array.elements.iter().for_each(|element| self.add_expression(element));
+            }
+            Expression::HashLiteral(hash) => {
+                for (key, value) in &hash.pairs {
+                    self.add_expression(key);
+                    self.add_expression(value);
+                }
+            }
+            Expression::Index(exp) => {
+                self.add_expression(&exp.left);
+                self.add_expression(&exp.index);
+            }
+        }
+    }
+}
+
+/// Which statements and branches ran, and how often.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coverage {
+    /// The times the statements starting on each line ran, taking the most
+    /// run of a line's statements.
+    pub lines: BTreeMap<usize, u64>,
+    /// Every `if`, in source order.
+    pub branches: Vec<Branch>,
+}
+
+/// The times each way through an `if` was taken. The alternative of an
+/// `if` without `else` is taken when its condition is false.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    pub span: Span,
+    pub consequence: u64,
+    pub alternative: u64,
+}
+
+impl Coverage {
+    /// The number of lines with a statement that ran.
+    pub fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|count| **count > 0).count()
+    }
+
+    /// The number of ways through an `if` taken, out of two per `if`.
+    pub fn branches_hit(&self) -> usize {
+        self.branches
+            .iter()
+            .map(|branch| (branch.consequence > 0) as usize + (branch.alternative > 0) as usize)
+            .sum()
+    }
+
+    /// A record for the source file at `path` in the lcov tracefile format
+    /// `genhtml` and coverage services read.
+    pub fn lcov(&self, path: &str) -> String {
+        let mut lcov = format!("TN:\nSF:{}\n", path);
+        for (block, branch) in self.branches.iter().enumerate() {
+            for (i, count) in [branch.consequence, branch.alternative].iter().enumerate() {
+                // `-` marks a branch whose condition never ran
+                let taken = match branch.consequence + branch.alternative {
+                    0 => "-".to_string(),
+                    _ => count.to_string(),
+                };
+                writeln!(lcov, "BRDA:{},{},{},{}", branch.span.line, block, i, taken).unwrap();
+            }
+        }
+        writeln!(lcov, "BRF:{}\nBRH:{}", self.branches.len() * 2, self.branches_hit()).unwrap();
+        for (line, count) in &self.lines {
+            writeln!(lcov, "DA:{},{}", line, count).unwrap();
+        }
+        writeln!(lcov, "LF:{}\nLH:{}", self.lines.len(), self.lines_hit()).unwrap();
+        lcov.push_str("end_of_record\n");
+        lcov
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_coverage() {
+        let engine = Engine::new().with_coverage();
+        engine
+            .eval(
+                "let abs = fn(n) {
+  if (n < 0) {
+    return -n;
+  }
+  n
+};
+let unused = fn() {
+  if (true) { 1 } else { 2 }
+};
+abs(-2);
+abs(3);",
+            )
+            .unwrap();
+
+        let coverage = engine.coverage().unwrap();
+        let expected = "\
+TN:
+SF:abs.monkey
+BRDA:2,0,0,1
+BRDA:2,0,1,1
+BRDA:8,1,0,-
+BRDA:8,1,1,-
+BRF:4
+BRH:2
+DA:1,1
+DA:2,2
+DA:3,1
+DA:5,1
+DA:7,1
+DA:8,0
+DA:10,1
+DA:11,1
+LF:8
+LH:7
+end_of_record
+";
+        assert_eq!(coverage.lcov("abs.monkey"), expected);
+    }
+
+    #[test]
+    fn test_coverage_accumulates() {
+        let engine = Engine::new().with_coverage();
+        engine.eval("let f = fn(x) { if (x) { 1 } else { 2 } };").unwrap();
+        engine.eval("f(true); f(true); f(false)").unwrap();
+
+        let coverage = engine.coverage().unwrap();
+        assert_eq!(coverage.branches.len(), 1);
+        assert_eq!(coverage.branches[0].consequence, 2);
+        assert_eq!(coverage.branches[0].alternative, 1);
+    }
+}
@@ -0,0 +1,582 @@
+use monkey_rs::{Debugger, Engine, Error, Object, Pause, Resume};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+/// The only thread a Monkey program has.
+const THREAD_ID: i64 = 1;
+/// The largest message read from the client, in bytes.
+const MAX_MESSAGE_LEN: usize = 16 << 20;
+
+/// Serves the Debug Adapter Protocol on `input` and `out` for one program,
+/// launched by the client with `engine`. Returns the exit code.
+pub(crate) fn serve<I, O>(engine: Engine, input: I, out: O) -> i32
+where
+    I: BufRead + 'static,
+    O: Write + 'static,
+{
+    let conn = Rc::new(RefCell::new(Connection::new(input, out)));
+    let exit_code = session(engine, &conn);
+    // a client that stops reading ends the session as one that disconnects
+    let lost = conn.borrow_mut().lost.take();
+    match lost {
+        Some(err) => {
+            eprintln!("lost the client: {}", err);
+            1
+        }
+        None => exit_code,
+    }
+}
+
+fn session<I, O>(mut engine: Engine, conn: &Rc<RefCell<Connection<I, O>>>) -> i32
+where
+    I: BufRead + 'static,
+    O: Write + 'static,
+{
+    let mut breakpoints = BTreeSet::new();
+    let mut launch = None;
+    let mut configured = false;
+
+    // the client configures the session, then says it is done
+    while launch.is_none() || !configured {
+        let request = match conn.borrow_mut().read() {
+            Some(request) => request,
+            None => return 0,
+        };
+        let mut conn = conn.borrow_mut();
+        match command(&request) {
+            "initialize" => {
+                conn.respond(&request, json!({ "supportsConfigurationDoneRequest": true }));
+                conn.event("initialized", json!({}));
+            }
+            "setBreakpoints" => {
+                breakpoints = lines(&request);
+                conn.respond(&request, verified(&breakpoints));
+            }
+            "configurationDone" => {
+                configured = true;
+                conn.respond(&request, json!({}));
+            }
+            "launch" => {
+                let args = &request["arguments"];
+                let path = args["program"].as_str().unwrap_or_default().to_string();
+                match fs::read_to_string(&path) {
+                    Ok(source) => {
+                        let stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                        launch = Some((path, source, stop_on_entry));
+                        conn.respond(&request, json!({}));
+                    }
+                    Err(err) => conn.fail(&request, &format!("{}: {}", path, err)),
+                }
+            }
+            "threads" => conn.respond(&request, json!({ "threads": [] })),
+            "disconnect" => {
+                conn.respond(&request, json!({}));
+                return 0;
+            }
+            _ => conn.fail(&request, "not supported before launch"),
+        }
+    }
+    let (path, source, stop_on_entry) = launch.unwrap();
+
+    // program output goes to the client, as stdout carries the protocol
+    let output = Rc::clone(conn);
+    engine.register("puts", move |args| {
+        let mut output = output.borrow_mut();
+        for arg in args.values() {
+            output.event("output", json!({ "category": "stdout", "output": format!("{}\n", arg) }));
+        }
+        Ok(Object::Null)
+    });
+    let adapter = Adapter {
+        conn: Rc::clone(conn),
+        path,
+        breakpoints: Some(breakpoints),
+        stop_on_entry,
+    };
+    let exit_code = match engine.with_debugger(adapter).eval(&source) {
+        Ok(_) => 0,
+        Err(err) => {
+            let output = match &err {
+                Error::Runtime(err) if err.backtrace.frames.len() > 1 => {
+                    format!("ERROR: {}\n{}\n", err, err.backtrace)
+                }
+                _ => format!("ERROR: {}\n", err),
+            };
+            let body = json!({ "category": "stderr", "output": output });
+            conn.borrow_mut().event("output", body);
+            1
+        }
+    };
+
+    let mut conn = conn.borrow_mut();
+    if conn.closed {
+        return exit_code;
+    }
+    conn.event("exited", json!({ "exitCode": exit_code }));
+    conn.event("terminated", json!({}));
+    while let Some(request) = conn.read() {
+        match command(&request) {
+            "disconnect" => {
+                conn.respond(&request, json!({}));
+                break;
+            }
+            "threads" => conn.respond(&request, json!({ "threads": [] })),
+            _ => conn.fail(&request, "the program has ended"),
+        }
+    }
+    exit_code
+}
+
+/// Answers the client's requests whenever the program pauses.
+struct Adapter<I, O> {
+    conn: Rc<RefCell<Connection<I, O>>>,
+    path: String,
+    // set by the client before launch and handed over at the first pause
+    breakpoints: Option<BTreeSet<usize>>,
+    stop_on_entry: bool,
+}
+
+impl<I: BufRead, O: Write> Debugger for Adapter<I, O> {
+    fn paused(&mut self, pause: &mut Pause<'_>) -> Resume {
+        let line = pause.span().line;
+        let reason = match self.breakpoints.take() {
+            Some(breakpoints) => {
+                *pause.breakpoints() = breakpoints;
+                if self.stop_on_entry {
+                    "entry"
+                } else if pause.breakpoints().contains(&line) {
+                    "breakpoint"
+                } else {
+                    return Resume::Continue;
+                }
+            }
+            None if pause.breakpoints().contains(&line) => "breakpoint",
+            None => "step",
+        };
+
+        let mut conn = self.conn.borrow_mut();
+        let body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        conn.event("stopped", body);
+        loop {
+            let request = match conn.read() {
+                Some(request) => request,
+                None => return Resume::Quit,
+            };
+            let resume = match command(&request) {
+                "continue" => Resume::Continue,
+                "next" => Resume::StepOver,
+                "stepIn" => Resume::StepInto,
+                "stepOut" => Resume::StepOut,
+                "disconnect" | "terminate" => {
+                    conn.closed = true;
+                    Resume::Quit
+                }
+                "threads" => {
+                    let body = json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] });
+                    conn.respond(&request, body);
+                    continue;
+                }
+                "stackTrace" => {
+                    let frames: Vec<_> = pause
+                        .backtrace()
+                        .frames
+                        .iter()
+                        .enumerate()
+                        .map(|(id, frame)| {
+                            json!({
+                                "id": id,
+                                "name": frame.function,
+                                "line": frame.span.line,
+                                "column": frame.span.column,
+                                "source": { "path": self.path },
+                            })
+                        })
+                        .collect();
+                    let body = json!({ "stackFrames": frames, "totalFrames": frames.len() });
+                    conn.respond(&request, body);
+                    continue;
+                }
+                "scopes" => {
+                    conn.respond(&request, json!({ "scopes": scopes(pause, &request) }));
+                    continue;
+                }
+                "variables" => {
+                    conn.respond(&request, json!({ "variables": variables(pause, &request) }));
+                    continue;
+                }
+                "setBreakpoints" => {
+                    *pause.breakpoints() = lines(&request);
+                    conn.respond(&request, verified(pause.breakpoints()));
+                    continue;
+                }
+                _ => {
+                    conn.fail(&request, "not supported");
+                    continue;
+                }
+            };
+            let body = match resume {
+                Resume::Continue => json!({ "allThreadsContinued": true }),
+                _ => json!({}),
+            };
+            conn.respond(&request, body);
+            return resume;
+        }
+    }
+}
+
+/// Only the innermost frame's scopes can be inspected: the scope chain of
+/// the statement evaluation stopped before. Scope `i` is variables
+/// reference `i + 1`.
+fn scopes(pause: &Pause<'_>, request: &Value) -> Vec<Value> {
+    if request["arguments"]["frameId"].as_i64() != Some(0) {
+        return Vec::new();
+    }
+    let count = pause.scopes().len();
+    (0..count)
+        .map(|i| {
+            let name = match i {
+                _ if i == count - 1 => "Globals",
+                0 => "Locals",
+                _ => "Closure",
+            };
+            json!({ "name": name, "variablesReference": i + 1, "expensive": false })
+        })
+        .collect()
+}
+
+fn variables(pause: &Pause<'_>, request: &Value) -> Vec<Value> {
+    let reference = request["arguments"]["variablesReference"].as_u64().unwrap_or(0) as usize;
+    let mut scopes = pause.scopes();
+    if reference == 0 || reference > scopes.len() {
+        return Vec::new();
+    }
+    scopes
+        .swap_remove(reference - 1)
+        .into_iter()
+        .map(|(name, value)| {
+            json!({
+                "name": name,
+                "value": value.to_string(),
+                "type": value.type_name(),
+                "variablesReference": 0,
+            })
+        })
+        .collect()
+}
+
+/// The lines of a `setBreakpoints` request. Monkey programs are a single
+/// file, so its source is not checked.
+fn lines(request: &Value) -> BTreeSet<usize> {
+    request["arguments"]["breakpoints"]
+        .as_array()
+        .map(|breakpoints| {
+            breakpoints
+                .iter()
+                .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                .map(|line| line as usize)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn verified(breakpoints: &BTreeSet<usize>) -> Value {
+    let breakpoints: Vec<_> = breakpoints
+        .iter()
+        .map(|line| json!({ "verified": true, "line": line }))
+        .collect();
+    json!({ "breakpoints": breakpoints })
+}
+
+fn command(request: &Value) -> &str {
+    request["command"].as_str().unwrap_or_default()
+}
+
+/// Messages framed by a `Content-Length` header, as DAP sends them.
+struct Connection<I, O> {
+    input: I,
+    out: O,
+    seq: i64,
+    // the client disconnected, the input ended or the output failed
+    closed: bool,
+    // why the output failed or the input was refused, after which nothing
+    // more is sent
+    lost: Option<io::Error>,
+}
+
+impl<I: BufRead, O: Write> Connection<I, O> {
+    fn new(input: I, out: O) -> Self {
+        Connection {
+            input,
+            out,
+            seq: 0,
+            closed: false,
+            lost: None,
+        }
+    }
+
+    /// The next message, or `None` once the input ends or is not DAP.
+    fn read(&mut self) -> Option<Value> {
+        if self.closed {
+            return None;
+        }
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            match self.input.read_line(&mut header) {
+                Ok(0) | Err(_) => {
+                    self.closed = true;
+                    return None;
+                }
+                Ok(_) => {}
+            }
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+        let length = length?;
+        if length > MAX_MESSAGE_LEN {
+            let message = format!(
+                "a message of {} bytes, more than {} allowed",
+                length, MAX_MESSAGE_LEN
+            );
+            self.closed = true;
+            self.lost = Some(io::Error::new(io::ErrorKind::InvalidData, message));
+            return None;
+        }
+        let mut body = vec![0; length];
+        self.input.read_exact(&mut body).ok()?;
+        serde_json::from_slice(&body).ok()
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": true,
+            "command": request["command"],
+            "body": body,
+        }));
+    }
+
+    fn fail(&mut self, request: &Value, message: &str) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": false,
+            "command": request["command"],
+            "message": message,
+        }));
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    fn send(&mut self, mut message: Value) {
+        if self.lost.is_some() {
+            return;
+        }
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let message = message.to_string();
+        let sent = write!(self.out, "Content-Length: {}\r\n\r\n{}", message.len(), message)
+            .and_then(|_| self.out.flush());
+        if let Err(err) = sent {
+            self.closed = true;
+            self.lost = Some(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::serve;
+    use monkey_rs::Engine;
+    use serde_json::{json, Value};
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+    use std::{env, fs, process};
+
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame(requests: &[Value]) -> Vec<u8> {
+        let mut framed = Vec::new();
+        for (seq, request) in requests.iter().enumerate() {
+            let mut request = request.clone();
+            request["seq"] = json!(seq + 1);
+            request["type"] = json!("request");
+            let request = request.to_string();
+            write!(framed, "Content-Length: {}\r\n\r\n{}", request.len(), request).unwrap();
+        }
+        framed
+    }
+
+    fn unframe(mut output: &str) -> Vec<Value> {
+        let mut messages = Vec::new();
+        while let Some(rest) = output.strip_prefix("Content-Length: ") {
+            let (length, rest) = rest.split_once("\r\n\r\n").unwrap();
+            let length: usize = length.parse().unwrap();
+            messages.push(serde_json::from_str(&rest[..length]).unwrap());
+            output = &rest[length..];
+        }
+        assert!(output.is_empty(), "unframed output: {:?}", output);
+        messages
+    }
+
+    #[test]
+    fn test_scripted_session() {
+        let path = env::temp_dir().join(format!("dap-{}.monkey", process::id()));
+        let source = "let f = fn(n) {\n  let m = n * 2;\n  m + 1\n};\nputs(f(20));\n";
+        fs::write(&path, source).unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let requests = [
+            json!({ "command": "initialize", "arguments": { "adapterID": "monkey" } }),
+            json!({ "command": "launch", "arguments": { "program": path } }),
+            json!({
+                "command": "setBreakpoints",
+                "arguments": { "source": { "path": path }, "breakpoints": [{ "line": 2 }] },
+            }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "scopes", "arguments": { "frameId": 0 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+            json!({ "command": "next", "arguments": { "threadId": 1 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "disconnect" }),
+        ];
+        let out = Shared::default();
+        let code = serve(Engine::new(), io::Cursor::new(frame(&requests)), out.clone());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(code, 0);
+
+        let messages = unframe(&String::from_utf8(out.0.take()).unwrap());
+        let summary: Vec<String> = messages
+            .iter()
+            .map(|message| match message["type"].as_str().unwrap() {
+                "response" => format!("{} {}", message["command"], message["success"]),
+                _ => format!("event {}", message["event"]),
+            })
+            .collect();
+        let expected = [
+            r#""initialize" true"#,
+            r#"event "initialized""#,
+            r#""launch" true"#,
+            r#""setBreakpoints" true"#,
+            r#""configurationDone" true"#,
+            r#"event "stopped""#,
+            r#""stackTrace" true"#,
+            r#""scopes" true"#,
+            r#""variables" true"#,
+            r#""next" true"#,
+            r#"event "stopped""#,
+            r#""variables" true"#,
+            r#""continue" true"#,
+            r#"event "output""#,
+            r#"event "exited""#,
+            r#"event "terminated""#,
+            r#""disconnect" true"#,
+        ];
+        assert_eq!(summary, expected);
+
+        assert_eq!(messages[5]["body"]["reason"], "breakpoint");
+        let frames = &messages[6]["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"], "f");
+        assert_eq!((frames[0]["line"].clone(), frames[0]["column"].clone()), (json!(2), json!(3)));
+        assert_eq!(frames[1]["name"], "<program>");
+        assert_eq!(frames[1]["line"], 5);
+        let scopes: Vec<_> = messages[7]["body"]["scopes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|scope| scope["name"].clone())
+            .collect();
+        assert_eq!(scopes, vec![json!("Locals"), json!("Globals")]);
+        assert_eq!(
+            messages[8]["body"]["variables"],
+            json!([{ "name": "n", "value": "20", "type": "INTEGER", "variablesReference": 0 }])
+        );
+        assert_eq!(messages[10]["body"]["reason"], "step");
+        assert_eq!(messages[11]["body"]["variables"][1]["name"], "n");
+        assert_eq!(messages[11]["body"]["variables"][0]["value"], "40");
+        assert_eq!(messages[13]["body"]["output"], "41\n");
+        assert_eq!(messages[14]["body"]["exitCode"], 0);
+    }
+
+    /// Fails every write after the first `left`.
+    struct Closing {
+        left: usize,
+    }
+
+    impl Write for Closing {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.left == 0 {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "pipe closed"));
+            }
+            self.left -= 1;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_client_going_away() {
+        let path = env::temp_dir().join(format!("dap-closing-{}.monkey", process::id()));
+        fs::write(&path, "let f = fn(n) {\n  n + 1\n};\nputs(f(1));\n").unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let requests = [
+            json!({ "command": "initialize", "arguments": { "adapterID": "monkey" } }),
+            json!({ "command": "launch", "arguments": { "program": path, "stopOnEntry": true } }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "next", "arguments": { "threadId": 1 } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "disconnect" }),
+        ];
+        // the pipe closes at each write in turn, until it stays open long
+        // enough for the whole session
+        let mut left = 0;
+        let code = loop {
+            let input = io::Cursor::new(frame(&requests));
+            match serve(Engine::new(), input, Closing { left }) {
+                1 if left < 1000 => left += 1,
+                code => break code,
+            }
+        };
+        fs::remove_file(&path).unwrap();
+        assert_eq!(code, 0);
+        assert!(left > 10, "only {} writes", left);
+    }
+
+    #[test]
+    fn test_oversized_message() {
+        let input = io::Cursor::new(b"Content-Length: 1000000000000\r\n\r\n{}".to_vec());
+        let out = Shared::default();
+        assert_eq!(serve(Engine::new(), input, out.clone()), 1);
+        assert!(out.0.borrow().is_empty());
+    }
+}
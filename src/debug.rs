@@ -0,0 +1,285 @@
+use crate::engine::{self, Error};
+use crate::environment::Env;
+use crate::evaluator::Evaluator;
+use crate::object::{Backtrace, Object};
+use crate::token::Span;
+use std::collections::BTreeSet;
+
+/// A front end that decides what happens whenever evaluation stops before a
+/// statement: at a breakpoint, after a step, or at the first statement of
+/// the first program run once the debugger is attached.
+pub trait Debugger {
+    fn paused(&mut self, pause: &mut Pause<'_>) -> Resume;
+}
+
+/// How evaluation goes on after a pause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    /// Run until a breakpoint.
+    Continue,
+    /// Stop at the next statement, even one in a function being called.
+    StepInto,
+    /// Stop at the next statement of the current function or its callers.
+    StepOver,
+    /// Stop at the next statement after the current function returns.
+    StepOut,
+    /// Stop evaluating with a `Cancelled` error.
+    Quit,
+}
+
+/// Where the next pause is wanted, in terms of the call depth at the time
+/// the stepping command was given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Run,
+    StepInto,
+    StepOver(usize),
+    StepOut(usize),
+}
+
+/// The debugger attached to an evaluator and the state it steps with.
+pub(crate) struct Session {
+    debugger: Box<dyn Debugger>,
+    breakpoints: BTreeSet<usize>,
+    mode: Mode,
+}
+
+impl Session {
+    pub(crate) fn new(debugger: Box<dyn Debugger>) -> Self {
+        Session {
+            debugger,
+            breakpoints: BTreeSet::new(),
+            mode: Mode::StepInto,
+        }
+    }
+
+    /// Hands a pause before the statement at `span` to the debugger if one
+    /// is due, returning how to go on, or `None` to go on without a pause.
+    pub(crate) fn pause(
+        &mut self,
+        evaluator: &Evaluator,
+        env: &Env,
+        span: Span,
+        depth: usize,
+    ) -> Option<Resume> {
+        let due = match self.mode {
+            Mode::Run => false,
+            Mode::StepInto => true,
+            Mode::StepOver(from) => depth <= from,
+            Mode::StepOut(from) => depth < from,
+        };
+        if !due && !self.breakpoints.contains(&span.line) {
+            return None;
+        }
+        let mut pause = Pause {
+            evaluator,
+            env,
+            span,
+            breakpoints: &mut self.breakpoints,
+        };
+        let resume = self.debugger.paused(&mut pause);
+        self.mode = match resume {
+            Resume::Continue | Resume::Quit => Mode::Run,
+            Resume::StepInto => Mode::StepInto,
+            Resume::StepOver => Mode::StepOver(depth),
+            Resume::StepOut => Mode::StepOut(depth),
+        };
+        Some(resume)
+    }
+}
+
+/// Evaluation stopped before a statement, with the scope it runs in.
+pub struct Pause<'a> {
+    evaluator: &'a Evaluator,
+    env: &'a Env,
+    span: Span,
+    breakpoints: &'a mut BTreeSet<usize>,
+}
+
+impl Pause<'_> {
+    /// Where the statement about to run starts.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The calls in progress, innermost first.
+    pub fn backtrace(&self) -> Backtrace {
+        self.evaluator.backtrace(self.span)
+    }
+
+    /// The bindings visible here, one list per scope from the innermost to
+    /// the global scope, each sorted by name.
+    pub fn scopes(&self) -> Vec<Vec<(String, Object)>> {
+        let mut scopes = Vec::new();
+        let mut env = Some(Env::clone(self.env));
+        while let Some(scope) = env {
+            let scope = scope.borrow();
+            let mut bindings: Vec<_> = scope
+                .bindings()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect();
+            bindings.sort_by(|a, b| a.0.cmp(&b.0));
+            scopes.push(bindings);
+            env = scope.outer().cloned();
+        }
+        scopes
+    }
+
+    /// The value of the variable `name`, as the paused statement would see it.
+    pub fn get(&self, name: &str) -> Option<Object> {
+        self.evaluator.lookup(name, self.env)
+    }
+
+    /// Rebinds `name` in the scope it is bound in, or binds it in the
+    /// innermost scope if it is not bound yet.
+    pub fn set(&mut self, name: &str, value: Object) {
+        if !self.env.borrow_mut().assign(name, value.clone()) {
+            self.env.borrow_mut().set(name, value);
+        }
+    }
+
+    /// Evaluates `source` in the paused scope, without pausing in it. Any
+    /// `let` it contains binds in the innermost scope.
+    pub fn evaluate(&mut self, source: &str) -> Result<Object, Error> {
+        let program = engine::parse(source)?;
+        engine::result(self.evaluator.eval_nested(&program, self.env))
+    }
+
+    /// The lines evaluation stops at when it reaches a statement starting
+    /// on one of them.
+    pub fn breakpoints(&mut self) -> &mut BTreeSet<usize> {
+        self.breakpoints
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Debugger, Pause, Resume};
+    use crate::engine::{Engine, Error};
+    use crate::object::{ErrorKind, Object};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Gives the scripted commands in turn, noting the line and depth of
+    /// each pause.
+    struct Script {
+        commands: Vec<Resume>,
+        pauses: Rc<RefCell<Vec<(usize, usize)>>>,
+    }
+
+    impl Debugger for Script {
+        fn paused(&mut self, pause: &mut Pause<'_>) -> Resume {
+            let depth = pause.backtrace().frames.len() - 1;
+            self.pauses.borrow_mut().push((pause.span().line, depth));
+            if self.commands.is_empty() {
+                Resume::Continue
+            } else {
+                self.commands.remove(0)
+            }
+        }
+    }
+
+    const PROGRAM: &str = "let add = fn(a, b) {
+  let sum = a + b;
+  sum
+};
+let x = add(1, 2);
+let y = add(x, 3);
+y";
+
+    fn pauses(commands: Vec<Resume>) -> Vec<(usize, usize)> {
+        let pauses = Rc::new(RefCell::new(Vec::new()));
+        let script = Script {
+            commands,
+            pauses: Rc::clone(&pauses),
+        };
+        let engine = Engine::new().with_debugger(script);
+        engine.eval(PROGRAM).unwrap();
+        let pauses = pauses.borrow().clone();
+        pauses
+    }
+
+    #[test]
+    fn test_stepping() {
+        use Resume::*;
+
+        assert_eq!(pauses(vec![Continue]), vec![(1, 0)]);
+        assert_eq!(
+            pauses(vec![StepOver, StepOver, StepOver, StepOver]),
+            vec![(1, 0), (5, 0), (6, 0), (7, 0)]
+        );
+        assert_eq!(
+            pauses(vec![StepOver, StepInto, StepInto, StepInto, Continue]),
+            vec![(1, 0), (5, 0), (2, 1), (3, 1), (6, 0)]
+        );
+        assert_eq!(
+            pauses(vec![StepOver, StepInto, StepOut, Continue]),
+            vec![(1, 0), (5, 0), (2, 1), (6, 0)]
+        );
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        struct Breaks(Rc<RefCell<Vec<usize>>>);
+
+        impl Debugger for Breaks {
+            fn paused(&mut self, pause: &mut Pause<'_>) -> Resume {
+                self.0.borrow_mut().push(pause.span().line);
+                pause.breakpoints().insert(3);
+                Resume::Continue
+            }
+        }
+
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let engine = Engine::new().with_debugger(Breaks(Rc::clone(&lines)));
+        engine.eval(PROGRAM).unwrap();
+        assert_eq!(*lines.borrow(), vec![1, 3, 3]);
+    }
+
+    #[test]
+    fn test_inspecting_and_modifying_bindings() {
+        struct Inspect(usize);
+
+        impl Debugger for Inspect {
+            fn paused(&mut self, pause: &mut Pause<'_>) -> Resume {
+                self.0 += 1;
+                match self.0 {
+                    1 => {
+                        pause.breakpoints().insert(3);
+                        return Resume::Continue;
+                    }
+                    2 => {}
+                    _ => return Resume::Continue,
+                }
+                let scopes = pause.scopes();
+                assert_eq!(scopes.len(), 2);
+                let locals: Vec<_> = scopes[0].iter().map(|(name, _)| name.as_str()).collect();
+                assert_eq!(locals, vec!["a", "b", "sum"]);
+                assert_eq!(pause.get("sum"), Some(Object::Integer(3)));
+                assert_eq!(pause.evaluate("sum * a + b").unwrap(), Object::Integer(5));
+
+                pause.set("sum", Object::Integer(40));
+                Resume::Continue
+            }
+        }
+
+        let engine = Engine::new().with_debugger(Inspect(0));
+        assert_eq!(engine.eval(PROGRAM).unwrap(), Object::Integer(43));
+    }
+
+    #[test]
+    fn test_quit() {
+        let engine = Engine::new().with_debugger(Script {
+            commands: vec![Resume::StepOver, Resume::Quit],
+            pauses: Rc::new(RefCell::new(Vec::new())),
+        });
+        match engine.eval(PROGRAM) {
+            Err(Error::Runtime(err)) => {
+                assert_eq!(err.kind, ErrorKind::Cancelled);
+                assert_eq!(err.message, "stopped by the debugger");
+                assert_eq!((err.span.line, err.span.column), (5, 1));
+            }
+            result => panic!("expected the debugger to stop evaluation. got={:?}", result),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use crate::code::{read_operands, Opcode};
+use crate::compiler::Bytecode;
+use crate::object::{CompiledFunction, Object};
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+/// Lists the instructions of the program and then of each function in its
+/// constants, one per line with its offset, position, operands and what
+/// they refer to. Given the `source` compiled, each line of it is shown
+/// above the instructions compiled from it.
+pub(crate) fn disassemble(bytecode: &Bytecode, source: Option<&str>) -> String {
+    let lines: Option<Vec<&str>> = source.map(|source| source.lines().collect());
+    let mut out = String::new();
+    function(&mut out, "<program>", &bytecode.main, bytecode, lines.as_deref());
+    for (i, constant) in bytecode.constants.iter().enumerate() {
+        if let Object::CompiledFunction(func) = constant {
+            writeln!(out).unwrap();
+            let title = format!("{} (constant {})", name(func), i);
+            function(&mut out, &title, func, bytecode, lines.as_deref());
+        }
+    }
+    out
+}
+
+fn function(
+    out: &mut String,
+    title: &str,
+    func: &CompiledFunction,
+    bytecode: &Bytecode,
+    lines: Option<&[&str]>,
+) {
+    writeln!(out, "{}:", title).unwrap();
+    let ins = &func.instructions;
+    let mut line = 0;
+    let mut offset = 0;
+    while offset < ins.len() {
+        let span = func.position(offset);
+        let at = format!("{:04} {:<7}", offset, span.to_string());
+        if span.line != line {
+            line = span.line;
+            if let Some(text) = lines.and_then(|lines| lines.get(line.wrapping_sub(1))) {
+                writeln!(out, "{:>9} | {}", line, text.trim()).unwrap();
+            }
+        }
+        let op = match Opcode::try_from(ins[offset]) {
+            Ok(op) => op,
+            Err(byte) => {
+                writeln!(out, "{} ERROR: unknown opcode {}", at, byte).unwrap();
+                offset += 1;
+                continue;
+            }
+        };
+        let definition = op.definition();
+        let width: usize = definition.operand_widths.iter().sum();
+        if offset + width >= ins.len() {
+            writeln!(out, "{} ERROR: {} is cut short", at, definition.name).unwrap();
+            break;
+        }
+        let (operands, _) = read_operands(&definition, &ins[offset + 1..]);
+        let mut instruction = definition.name.to_string();
+        for operand in &operands[..definition.operand_widths.len()] {
+            write!(instruction, " {}", operand).unwrap();
+        }
+        match comment(op, operands, bytecode) {
+            Some(comment) => writeln!(out, "{} {:<22} ; {}", at, instruction, comment).unwrap(),
+            None => writeln!(out, "{} {}", at, instruction).unwrap(),
+        }
+        offset += 1 + width;
+    }
+}
+
+/// What the operands of `op` refer to, if anything but numbers.
+fn comment(op: Opcode, operands: [usize; 2], bytecode: &Bytecode) -> Option<String> {
+    let [operand, constant] = operands;
+    let comment = match op {
+        Opcode::Constant => match bytecode.constants.get(operand) {
+            Some(Object::String(value)) => format!("{:?}", value),
+            Some(constant) => constant.to_string(),
+            None => "no such constant".to_string(),
+        },
+        Opcode::Closure => match bytecode.constants.get(operand) {
+            Some(Object::CompiledFunction(func)) => name(func).to_string(),
+            _ => "no such function".to_string(),
+        },
+        Opcode::MakeCell => match bytecode.constants.get(constant) {
+            Some(Object::String(name)) => name.to_string(),
+            _ => "no such name".to_string(),
+        },
+        Opcode::GetGlobal | Opcode::SetGlobal => match bytecode.globals.get(operand) {
+            Some(name) => name.to_string(),
+            None => "no such global".to_string(),
+        },
+        _ => return None,
+    };
+    Some(comment)
+}
+
+fn name(func: &CompiledFunction) -> &str {
+    func.name.as_deref().unwrap_or("<anonymous>")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::code::Opcode;
+    use crate::compiler::compile;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_disassemble() {
+        let source = "let add = fn(a, b) {\n  a + b\n};\nadd(1, \"two\")";
+        let expected = "\
+<program>:
+        1 | let add = fn(a, b) {
+0000 1:11    OpClosure 0 0          ; add
+0004 1:5     OpSetGlobal 0          ; add
+        4 | add(1, \"two\")
+0007 4:1     OpGetGlobal 0          ; add
+0010 4:5     OpConstant 1           ; 1
+0013 4:8     OpConstant 2           ; \"two\"
+0016 4:1     OpCall 2
+0018 4:1     OpPop
+
+add (constant 0):
+        2 | a + b
+0000 2:3     OpGetLocal 0
+0002 2:7     OpGetLocal 1
+0004 2:3     OpAdd
+0005 2:3     OpReturnValue
+";
+        let bytecode = compile(source).unwrap();
+        assert_eq!(bytecode.disassemble(Some(source)), expected);
+    }
+
+    #[test]
+    fn test_disassemble_malformed_instructions() {
+        let mut bytecode = compile("1").unwrap();
+        let main = Rc::make_mut(&mut bytecode.main);
+        main.instructions = vec![255, Opcode::Constant as u8, 0, 9, Opcode::Jump as u8, 0];
+        let expected = "\
+<program>:
+0000 1:1     ERROR: unknown opcode 255
+0001 1:1     OpConstant 9           ; no such constant
+0004 1:1     ERROR: OpJump is cut short
+";
+        assert_eq!(bytecode.disassemble(None), expected);
+    }
+}
@@ -0,0 +1,267 @@
+use crate::ast::Program;
+use crate::compiler::{Bytecode, CompileError};
+use crate::convert::ToMonkey;
+use crate::coverage::Coverage;
+use crate::debug::Debugger;
+use crate::environment::{Env, Environment};
+use crate::evaluator::{CancelHandle, Evaluator, IntegerMode, Limits};
+use crate::host::{Arguments, HostError};
+use crate::lexer::Lexer;
+use crate::object::{ErrorKind, Object, RuntimeError};
+use crate::parser::{Parser, ParserError};
+use crate::profiler::Profile;
+use crate::token::Span;
+use crate::trace::Observer;
+use crate::vm::Vm;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+/// An interpreter instance: its settings, the host functions registered
+/// with it, and the global scope that successive calls to `eval` share.
+///
+/// Calls in `eval` and `call` nest on the native stack. The engine stops a
+/// program with a `LimitExceeded` error before it uses more than it was
+/// told the thread has, which is 2 MiB unless `with_stack_size` says more.
+pub struct Engine {
+    evaluator: Evaluator,
+    globals: Env,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine {
+            evaluator: Evaluator::new(),
+            globals: Environment::new(),
+        }
+    }
+
+    /// Checked 64-bit arithmetic is the default.
+    pub fn with_integer_mode(mut self, integers: IntegerMode) -> Self {
+        self.evaluator = self.evaluator.with_integer_mode(integers);
+        self
+    }
+
+    /// Applies to each call to `eval` or `call` separately.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.evaluator = self.evaluator.with_limits(limits);
+        self
+    }
+
+    /// Applies to each call to `eval` or `call` separately.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.evaluator = self.evaluator.with_timeout(timeout);
+        self
+    }
+
+    /// The native stack of the thread that evaluates, such as the size it
+    /// was spawned with. Saying more than there is lets a deep recursion
+    /// overflow the stack and abort the process.
+    pub fn with_stack_size(mut self, size: usize) -> Self {
+        self.evaluator = self.evaluator.with_stack_size(size);
+        self
+    }
+
+    /// Pauses evaluation before the first statement, and from then on
+    /// wherever `debugger` asks to.
+    pub fn with_debugger<D: Debugger + 'static>(mut self, debugger: D) -> Self {
+        self.evaluator = self.evaluator.with_debugger(Box::new(debugger));
+        self
+    }
+
+    /// Records the call count and time of every function called from now
+    /// on, at some cost in speed.
+    pub fn with_profiler(mut self) -> Self {
+        self.evaluator = self.evaluator.with_profiler();
+        self
+    }
+
+    /// What the profiler recorded over every evaluation so far, or `None`
+    /// if there is no profiler.
+    pub fn profile(&self) -> Option<Profile> {
+        self.evaluator.profile()
+    }
+
+    /// Records which statements and `if` branches run from now on.
+    pub fn with_coverage(mut self) -> Self {
+        self.evaluator = self.evaluator.with_coverage();
+        self
+    }
+
+    /// What ran of the programs evaluated so far, or `None` without
+    /// coverage. Positions are those in each program's source, so programs
+    /// from different files need an engine each.
+    pub fn coverage(&self) -> Option<Coverage> {
+        self.evaluator.coverage()
+    }
+
+    /// Tells `observer` about each statement, expression, call, return and
+    /// error from now on. Any number of observers can be added.
+    pub fn with_observer<O: Observer + 'static>(mut self, observer: O) -> Self {
+        self.evaluator = self.evaluator.with_observer(Box::new(observer));
+        self
+    }
+
+    /// A handle that stops the evaluation in progress, or else the next one,
+    /// from any thread.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.evaluator.cancel_handle()
+    }
+
+    /// Makes `func` callable from scripts as the global `name`.
+    pub fn register<F>(&mut self, name: &str, func: F)
+    where
+        F: Fn(&Arguments) -> Result<Object, HostError> + 'static,
+    {
+        self.evaluator.register(name, func);
+    }
+
+    /// Runs `source` in the global scope and returns the value of its last
+    /// statement. Bindings it makes stay for later calls. Recursion as deep
+    /// as the call depth limit needs `STACK_SIZE` of stack; with less, it
+    /// stops at the stack limit first.
+    pub fn eval(&self, source: &str) -> Result<Object, Error> {
+        let program = parse(source)?;
+        result(self.evaluator.eval_program(&program, &self.globals))
+    }
+
+    /// Runs `bytecode` on the virtual machine, which is faster than `eval`.
+    /// It has its own globals, starting with none bound. It uses the integer
+    /// mode, host functions, limits, timeout and cancel handle of the
+    /// engine, but no debugger, profiler, coverage or observers. A function
+    /// it returns can be displayed but not called through `set` or `call`.
+    pub fn run(&self, bytecode: &Bytecode) -> Result<Object, Error> {
+        result(Vm::new(bytecode, &self.evaluator).run())
+    }
+
+    /// Calls the function bound to the global `name`.
+    pub fn call(&self, name: &str, args: Vec<Object>) -> Result<Object, Error> {
+        match self.evaluator.lookup(name, &self.globals) {
+            Some(function) => result(self.evaluator.call(function, args)),
+            None => Err(Error::Runtime(RuntimeError::new(
+                ErrorKind::UnknownIdentifier,
+                format!("identifier not found: {}", name),
+                Span::default(),
+            ))),
+        }
+    }
+
+    /// The value bound to the global `name`, if any. Host functions and
+    /// builtins are not bindings.
+    pub fn get(&self, name: &str) -> Option<Object> {
+        self.globals.borrow().get(name)
+    }
+
+    /// Binds `name` in the global scope, as a top-level `let` would.
+    pub fn set<T: ToMonkey>(&self, name: &str, value: T) {
+        self.globals.borrow_mut().set(name, value.to_monkey());
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+pub(crate) fn parse(source: &str) -> Result<Program, Error> {
+    let mut parser = Parser::new(Lexer::new(source.to_string()));
+    let program = parser.parse_program().map_err(|err| Error::Parse(vec![err]))?;
+    if !parser.errors().is_empty() {
+        return Err(Error::Parse(parser.errors().to_vec()));
+    }
+    Ok(program)
+}
+
+pub(crate) fn result(evaluated: Object) -> Result<Object, Error> {
+    match evaluated {
+        Object::Error(err) => Err(Error::Runtime(*err)),
+        value => Ok(value),
+    }
+}
+
+/// Why `Engine::eval`, `Engine::call`, `Engine::run` or `compile` failed.
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// The source did not parse; nothing was evaluated.
+    Parse(Vec<ParserError>),
+    /// The source parsed but is too large to compile.
+    Compile(CompileError),
+    Runtime(RuntimeError),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Parse(errors) => {
+                let errors: Vec<_> = errors.iter().map(|err| err.to_string()).collect();
+                write!(f, "{}", errors.join("\n"))
+            }
+            Error::Compile(err) => write!(f, "{}", err),
+            Error::Runtime(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<RuntimeError> for Error {
+    fn from(err: RuntimeError) -> Self {
+        Error::Runtime(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Engine, Error};
+    use crate::object::{ErrorKind, Object};
+
+    #[test]
+    fn test_globals_persist_between_evals() {
+        let engine = Engine::new();
+        engine.eval("let add = fn(a, b) { a + b }; let x = 40;").unwrap();
+        assert_eq!(engine.eval("add(x, 2)").unwrap(), Object::Integer(42));
+
+        engine.set("y", 8);
+        assert_eq!(engine.eval("let z = add(x, y);").unwrap(), Object::Null);
+        assert_eq!(engine.get("z"), Some(Object::Integer(48)));
+        assert_eq!(engine.get("missing"), None);
+    }
+
+    #[test]
+    fn test_call_and_register() {
+        let mut engine = Engine::new();
+        engine.register("double", |args| Ok(Object::Integer(args.integer(0)? * 2)));
+        engine.eval("let inc = fn(n) { double(n) + 1 };").unwrap();
+        assert_eq!(engine.call("inc", vec![Object::Integer(20)]).unwrap(), Object::Integer(41));
+        assert_eq!(engine.call("double", vec![Object::Integer(4)]).unwrap(), Object::Integer(8));
+        let abc = Object::String("abc".into());
+        assert_eq!(engine.call("len", vec![abc]).unwrap(), Object::Integer(3));
+
+        match engine.call("nope", vec![]) {
+            Err(Error::Runtime(err)) => assert_eq!(err.kind, ErrorKind::UnknownIdentifier),
+            result => panic!("expected a runtime error. got={:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let engine = Engine::new();
+        match engine.eval("let = 1;") {
+            Err(err @ Error::Parse(_)) => assert_eq!(
+                err.to_string(),
+                "ParserError: Expected: IDENT, Got: ASSIGN\n\
+                 ParserError: no prefix parse function for ASSIGN found"
+            ),
+            result => panic!("expected a parse error. got={:?}", result),
+        }
+        match engine.eval("let a = 1;\n1 + true") {
+            Err(Error::Runtime(err)) => {
+                assert_eq!(err.kind, ErrorKind::TypeMismatch);
+                assert_eq!(err.to_string(), "type mismatch: INTEGER + BOOLEAN at 2:1");
+            }
+            result => panic!("expected a runtime error. got={:?}", result),
+        }
+        // bindings made before the error are kept
+        assert_eq!(engine.eval("a").unwrap(), Object::Integer(1));
+    }
+}
@@ -0,0 +1,91 @@
+use crate::gc::{self, Garbage};
+use crate::object::Object;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub(crate) type Env = Rc<RefCell<Environment>>;
+
+/// A scope of `let` bindings. Lookups that miss fall through to the
+/// enclosing scope, so a function body sees the bindings of the scope the
+/// function was defined in.
+///
+/// Closures make these reference cycles (`let f = fn() { f }` stores `f` in
+/// the scope it captures), so every environment is tracked by `gc`, which
+/// frees the cycles that are no longer reachable.
+#[derive(Debug)]
+pub(crate) struct Environment {
+    store: HashMap<String, Object>,
+    outer: Option<Env>,
+}
+
+impl Environment {
+    pub(crate) fn new() -> Env {
+        Environment::tracked(None)
+    }
+
+    pub(crate) fn new_enclosed(outer: &Env) -> Env {
+        Environment::tracked(Some(Rc::clone(outer)))
+    }
+
+    fn tracked(outer: Option<Env>) -> Env {
+        let env = Rc::new(RefCell::new(Environment {
+            store: HashMap::new(),
+            outer,
+        }));
+        gc::track(&env);
+        env
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<Object> {
+        match self.store.get(name) {
+            Some(obj) => Some(obj.clone()),
+            None => self.outer.as_ref().and_then(|outer| outer.borrow().get(name)),
+        }
+    }
+
+    pub(crate) fn set<P: Into<String>>(&mut self, name: P, value: Object) {
+        self.store.insert(name.into(), value);
+    }
+
+    /// Rebinds `name` in the nearest scope that binds it. Returns false,
+    /// binding nothing, if none does.
+    pub(crate) fn assign(&mut self, name: &str, value: Object) -> bool {
+        match self.store.get_mut(name) {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => match &self.outer {
+                Some(outer) => outer.borrow_mut().assign(name, value),
+                None => false,
+            },
+        }
+    }
+
+    pub(crate) fn bindings(&self) -> impl Iterator<Item = (&String, &Object)> {
+        self.store.iter()
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &Object> {
+        self.store.values()
+    }
+
+    pub(crate) fn outer(&self) -> Option<&Env> {
+        self.outer.as_ref()
+    }
+
+    /// Drops every binding and the link to the enclosing scope, returning
+    /// them so the caller decides when they are freed.
+    pub(crate) fn clear(&mut self) -> (HashMap<String, Object>, Option<Env>) {
+        (std::mem::take(&mut self.store), self.outer.take())
+    }
+}
+
+impl Drop for Environment {
+    fn drop(&mut self) {
+        gc::untrack();
+        let values = self.store.drain().map(|(_, value)| Garbage::Value(value));
+        gc::release(values.chain(self.outer.take().map(Garbage::Env)));
+    }
+}
@@ -0,0 +1,1683 @@
+use crate::ast::{
+    BlockStatement, CallExpression, Expression, HashLiteral, Identifier, IfExpression, Program,
+    Statement,
+};
+use crate::builtins;
+use crate::coverage::{Coverage, Recorder};
+use crate::debug::{Debugger, Resume, Session};
+use crate::environment::{Env, Environment};
+use crate::host::{Arguments, HostError, HostFunction};
+use crate::object::{
+    Backtrace, ErrorKind, Function, HashKey, HashPair, Object, RuntimeError, TailCall, TraceFrame,
+};
+use crate::profiler::{Profile, Profiler};
+use crate::token::Span;
+use crate::trace::Observer;
+use num_bigint::BigInt;
+use num_traits::{ToPrimitive, Zero};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::mem;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Steps between checks for cancellation and the timeout.
+const CHECK_INTERVAL: u64 = 1024;
+
+/// The most calls that can be in progress at once, whatever `Limits` says,
+/// as each one nests several frames on the native stack.
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+/// The native stack a thread needs for calls `MAX_CALL_DEPTH` deep, with
+/// room to spare even in a debug build. An engine told of a smaller stack
+/// with `Engine::with_stack_size` stops nearer the surface.
+pub const STACK_SIZE: usize = 256 << 20;
+
+/// The stack an engine assumes it has unless told otherwise: that of a
+/// thread spawned by the standard library, the smallest it is likely to be.
+const DEFAULT_STACK_SIZE: usize = 2 << 20;
+
+/// Stack left unused by evaluation for what runs between two steps: a
+/// builtin or host function, say, or the frames of the embedder.
+const STACK_RESERVE: usize = 256 << 10;
+
+/// What integer arithmetic does with a result that does not fit in an i64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntegerMode {
+    /// The operation fails with an `IntegerOverflow` error.
+    #[default]
+    Checked,
+    /// The result becomes a big integer, and big integers that shrink back
+    /// into range become ordinary integers again.
+    Arbitrary,
+}
+
+/// Bounds on the resources one call to `eval_program` may use, so that a
+/// script cannot hang or exhaust its host. `None` is unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Limits {
+    /// Statements and expressions evaluated, or instructions executed on
+    /// the virtual machine.
+    pub steps: Option<u64>,
+    /// Function calls in progress at once, never more than `MAX_CALL_DEPTH`.
+    /// Tail calls replace the caller rather than nesting inside it. Each
+    /// call takes several kilobytes of native stack, so a program can also
+    /// run out of the stack the engine was given before it reaches this.
+    pub call_depth: Option<usize>,
+    /// Bytes of strings, arrays, hashes, big integers, functions and the
+    /// scopes of calls created. Every allocation counts, whether or not it
+    /// is still live.
+    pub memory: Option<usize>,
+}
+
+/// Stops the evaluation in progress from another thread, or else the next
+/// one. Clones share one flag, which is cleared when an evaluation ends.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A call to a Monkey function that has not returned yet.
+#[derive(Debug)]
+struct Frame {
+    function: Option<Rc<str>>,
+    call_site: Span,
+}
+
+/// Evaluates programs with the settings of one interpreter instance.
+#[derive(Default)]
+pub(crate) struct Evaluator {
+    integers: IntegerMode,
+    limits: Limits,
+    timeout: Option<Duration>,
+    cancel: CancelHandle,
+    host: HashMap<String, HostFunction>,
+    steps: Cell<u64>,
+    stack: RefCell<Vec<Frame>>,
+    allocated: Cell<usize>,
+    deadline: Cell<Option<Instant>>,
+    // `DEFAULT_STACK_SIZE` unless set, and where the stack stood at the start
+    stack_size: Option<usize>,
+    stack_base: Cell<usize>,
+    debug: Option<RefCell<Session>>,
+    // set while the debugger evaluates an expression in a paused scope
+    suspended: Cell<bool>,
+    profiler: Option<RefCell<Profiler>>,
+    coverage: Option<RefCell<Recorder>>,
+    observers: Vec<RefCell<Box<dyn Observer>>>,
+    // calls reported to the observers that have not returned yet
+    open_calls: Cell<usize>,
+}
+
+impl Evaluator {
+    pub(crate) fn new() -> Self {
+        Evaluator::default()
+    }
+
+    pub(crate) fn with_integer_mode(mut self, integers: IntegerMode) -> Self {
+        self.integers = integers;
+        self
+    }
+
+    pub(crate) fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Stops each program that runs longer than `timeout`.
+    pub(crate) fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// How much native stack the thread that evaluates has.
+    pub(crate) fn with_stack_size(mut self, size: usize) -> Self {
+        self.stack_size = Some(size);
+        self
+    }
+
+    pub(crate) fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    /// Makes `func` callable as the global `name`. Like builtins, it can be
+    /// shadowed by a `let`; it shadows a builtin of the same name.
+    pub(crate) fn register<F>(&mut self, name: &str, func: F)
+    where
+        F: Fn(&Arguments) -> Result<Object, HostError> + 'static,
+    {
+        self.host.insert(name.to_string(), HostFunction::new(name, func));
+    }
+
+    /// Pauses before the first statement evaluated, then wherever
+    /// `debugger` asks to.
+    pub(crate) fn with_debugger(mut self, debugger: Box<dyn Debugger>) -> Self {
+        self.debug = Some(RefCell::new(Session::new(debugger)));
+        self
+    }
+
+    /// Times every call made from now on.
+    pub(crate) fn with_profiler(mut self) -> Self {
+        self.profiler = Some(RefCell::new(Profiler::default()));
+        self
+    }
+
+    pub(crate) fn profile(&self) -> Option<Profile> {
+        self.profiler.as_ref().map(|profiler| profiler.borrow().profile())
+    }
+
+    /// Records which statements and branches of each program run.
+    pub(crate) fn with_coverage(mut self) -> Self {
+        self.coverage = Some(RefCell::new(Recorder::default()));
+        self
+    }
+
+    pub(crate) fn coverage(&self) -> Option<Coverage> {
+        self.coverage.as_ref().map(|coverage| coverage.borrow().coverage())
+    }
+
+    /// Adds `observer` to those told about every step of evaluation.
+    pub(crate) fn with_observer(mut self, observer: Box<dyn Observer>) -> Self {
+        self.observers.push(RefCell::new(observer));
+        self
+    }
+
+    pub(crate) fn eval_program(&self, program: &Program, env: &Env) -> Object {
+        self.start();
+        self.covering(|coverage| coverage.add_program(program));
+        let depth = self.profile_depth();
+        self.profiling(|profiler| profiler.enter("<program>".to_string()));
+        let result = self.run_program(program, env);
+        self.profiling(|profiler| profiler.exit_to(depth));
+        self.finish();
+        result
+    }
+
+    /// Evaluates `program` in the middle of another evaluation, on its
+    /// budget and without pausing.
+    pub(crate) fn eval_nested(&self, program: &Program, env: &Env) -> Object {
+        self.suspended.set(true);
+        let result = self.run_program(program, env);
+        self.suspended.set(false);
+        result
+    }
+
+    fn run_program(&self, program: &Program, env: &Env) -> Object {
+        let mut result = Object::Null;
+        for stmt in &program.statements {
+            if let Err(err) = self.before_statement(stmt.span(), env) {
+                return self.traced(err);
+            }
+            result = self.eval_statement(stmt, env);
+            match result {
+                Object::ReturnValue(value) => return *value,
+                Object::TailCall(call) => {
+                    return self.apply_function(call.function, call.args, call.span)
+                }
+                Object::Error(_) => return self.traced(result),
+                _ => {}
+            }
+        }
+        result
+    }
+
+    /// Calls `function` from outside any program, with a budget of its own.
+    pub(crate) fn call(&self, function: Object, args: Vec<Object>) -> Object {
+        self.start();
+        let result = self.apply_function(function, args, Span::default());
+        self.finish();
+        self.traced(result)
+    }
+
+    /// Resets the limits for a new evaluation.
+    pub(crate) fn start(&self) {
+        self.steps.set(0);
+        self.allocated.set(0);
+        self.deadline.set(self.timeout.map(|timeout| Instant::now() + timeout));
+        self.stack_base.set(stack_position());
+    }
+
+    /// Clears the cancellation flag once an evaluation ends, whether or not
+    /// it was cancelled, so that a cancellation made before an evaluation
+    /// starts is not lost.
+    pub(crate) fn finish(&self) {
+        self.cancel.0.store(false, Ordering::Relaxed);
+    }
+
+    /// The call depth limit, which is `MAX_CALL_DEPTH` at most.
+    pub(crate) fn max_call_depth(&self) -> usize {
+        self.limits.call_depth.map_or(MAX_CALL_DEPTH, |max| max.min(MAX_CALL_DEPTH))
+    }
+
+    /// The value of the variable `name`: a binding in `env`, else a host
+    /// function, else a builtin.
+    pub(crate) fn lookup(&self, name: &str, env: &Env) -> Option<Object> {
+        match env.borrow().get(name) {
+            Some(value) => Some(value),
+            None => self.predefined(name),
+        }
+    }
+
+    /// The host function or else builtin called `name`, if any.
+    pub(crate) fn predefined(&self, name: &str) -> Option<Object> {
+        match self.host.get(name) {
+            Some(host) => Some(Object::Host(host.clone())),
+            None => builtins::lookup(name),
+        }
+    }
+
+    /// Unlike `eval_program`, a `ReturnValue` or `TailCall` is passed up as
+    /// is so that an enclosing block or function body stops evaluating too.
+    ///
+    /// In `tail` position the value of the last statement is the value of the
+    /// function being called, so a call there is not made but returned as a
+    /// `TailCall` for `apply_function` to run without growing the stack.
+    fn eval_block_statement(&self, block: &BlockStatement, env: &Env, tail: bool) -> Object {
+        let mut result = Object::Null;
+        let last = block.statements.len().saturating_sub(1);
+        for (i, stmt) in block.statements.iter().enumerate() {
+            if let Err(err) = self.before_statement(stmt.span(), env) {
+                return err;
+            }
+            result = match stmt {
+                Statement::Expression(stmt) if tail && i == last => {
+                    self.eval_tail_expression(&stmt.expression, env)
+                }
+                _ => self.eval_statement(stmt, env),
+            };
+            if result.is_abrupt() {
+                return result;
+            }
+        }
+        result
+    }
+
+    fn eval_statement(&self, stmt: &Statement, env: &Env) -> Object {
+        if let Err(err) = self.step(stmt.span()) {
+            return err;
+        }
+        match stmt {
+            Statement::Expression(stmt) => self.eval_expression(&stmt.expression, env),
+            Statement::Return(stmt) => {
+                // the function ends here, so the returned expression is in tail
+                // position wherever the statement is
+                let value = self.eval_tail_expression(&stmt.return_value, env);
+                if value.is_abrupt() {
+                    return value;
+                }
+                Object::ReturnValue(Box::new(value))
+            }
+            Statement::Let(stmt) => {
+                let value = self.eval_expression(&stmt.value, env);
+                if value.is_abrupt() {
+                    return value;
+                }
+                env.borrow_mut().set(stmt.name.value.clone(), value);
+                Object::Null
+            }
+        }
+    }
+
+    fn eval_tail_expression(&self, exp: &Expression, env: &Env) -> Object {
+        match exp {
+            Expression::Call(call) => {
+                let function = self.eval_expression(&call.function, env);
+                if function.is_abrupt() {
+                    return function;
+                }
+                match self.eval_expressions(&call.arguments, env) {
+                    Ok(args) => Object::TailCall(Box::new(TailCall {
+                        function,
+                        args,
+                        span: call.function.span(),
+                    })),
+                    Err(err) => err,
+                }
+            }
+            Expression::If(exp) => self.eval_if_expression(exp, env, true),
+            _ => self.eval_expression(exp, env),
+        }
+    }
+
+    fn eval_expression(&self, exp: &Expression, env: &Env) -> Object {
+        if let Err(err) = self.step(exp.span()) {
+            return err;
+        }
+        let value = match exp {
+            Expression::IntegerLiteral(int) => self.eval_integer_literal(&int.value, exp.span()),
+            Expression::StringLiteral(string) => {
+                self.allocate(Object::String(string.value.as_str().into()), exp.span())
+            }
+            Expression::Boolean(boolean) => Object::Boolean(boolean.value),
+            Expression::Prefix(prefix) => match (prefix.operator.as_str(), &*prefix.right) {
+                // `-9223372036854775808` is in range even though its digits are not
+                ("-", Expression::IntegerLiteral(int)) => {
+                    self.eval_integer_literal(&-&int.value, exp.span())
+                }
+                _ => {
+                    let right = self.eval_expression(&prefix.right, env);
+                    if right.is_abrupt() {
+                        return right;
+                    }
+                    self.eval_prefix_expression(&prefix.operator, right, exp.span())
+                }
+            },
+            Expression::Infix(infix) => {
+                let left = self.eval_expression(&infix.left, env);
+                if left.is_abrupt() {
+                    return left;
+                }
+                let right = self.eval_expression(&infix.right, env);
+                if right.is_abrupt() {
+                    return right;
+                }
+                self.eval_infix_expression(&infix.operator, left, right, exp.span())
+            }
+            Expression::If(exp) => self.eval_if_expression(exp, env, false),
+            Expression::Identifier(ident) => {
+                self.lookup(&ident.value, env).unwrap_or_else(|| error(
+                    ErrorKind::UnknownIdentifier,
+                    format!("identifier not found: {}", ident.value),
+                    ident.token.span,
+                ))
+            }
+            Expression::FunctionLiteral(func) => {
+                let size = mem::size_of::<Function>()
+                    + func.parameters.len() * mem::size_of::<Identifier>();
+                if let Err(err) = self.charge(size, exp.span()) {
+                    return err;
+                }
+                Object::Function(Function {
+                    name: func.name.clone(),
+                    parameters: func.parameters.clone(),
+                    body: Rc::clone(&func.body),
+                    env: Rc::clone(env),
+                })
+            }
+            Expression::Call(call) => self.eval_call_expression(call, env),
+            Expression::ArrayLiteral(array) => match self.eval_expressions(&array.elements, env) {
+                Ok(elements) => self.allocate(Object::Array(Rc::new(elements)), exp.span()),
+                Err(err) => err,
+            },
+            Expression::HashLiteral(hash) => self.eval_hash_literal(hash, env),
+            Expression::Index(index) => {
+                let left = self.eval_expression(&index.left, env);
+                if left.is_abrupt() {
+                    return left;
+                }
+                let idx = self.eval_expression(&index.index, env);
+                if idx.is_abrupt() {
+                    return idx;
+                }
+                // indexing a string makes a new one
+                self.allocate(eval_index_expression(left, idx, exp.span()), exp.span())
+            }
+        };
+        if !self.observers.is_empty() && !value.is_abrupt() {
+            let source = exp.to_string();
+            self.observe(|observer| observer.expression(&source, exp.span(), &value));
+        }
+        value
+    }
+
+    pub(crate) fn eval_integer_literal(&self, value: &BigInt, span: Span) -> Object {
+        match self.integer(value.clone()) {
+            Some(int) => self.allocate(int, span),
+            None => error(
+                ErrorKind::IntegerOverflow,
+                format!("integer literal out of range: {}", value),
+                span,
+            ),
+        }
+    }
+
+    /// Evaluates left to right, stopping at the first error or `return`.
+    fn eval_expressions(&self, exps: &[Expression], env: &Env) -> Result<Vec<Object>, Object> {
+        let mut result = Vec::with_capacity(exps.len());
+        for exp in exps {
+            let evaluated = self.eval_expression(exp, env);
+            if evaluated.is_abrupt() {
+                return Err(evaluated);
+            }
+            result.push(evaluated);
+        }
+        Ok(result)
+    }
+
+    fn eval_hash_literal(&self, hash: &HashLiteral, env: &Env) -> Object {
+        let mut pairs = BTreeMap::new();
+        for (key_exp, value_exp) in &hash.pairs {
+            let key = self.eval_expression(key_exp, env);
+            if key.is_abrupt() {
+                return key;
+            }
+            let hash_key = match key.hash_key() {
+                Some(hash_key) => hash_key,
+                None => return unusable_as_hash_key(&key, key_exp.span()),
+            };
+            let value = self.eval_expression(value_exp, env);
+            if value.is_abrupt() {
+                return value;
+            }
+            pairs.insert(hash_key, HashPair { key, value });
+        }
+        self.allocate(Object::Hash(Rc::new(pairs)), hash.token.span)
+    }
+
+    fn eval_call_expression(&self, call: &CallExpression, env: &Env) -> Object {
+        let function = self.eval_expression(&call.function, env);
+        if function.is_abrupt() {
+            return function;
+        }
+
+        let args = match self.eval_expressions(&call.arguments, env) {
+            Ok(args) => args,
+            Err(err) => return err,
+        };
+
+        self.apply_function(function, args, call.function.span())
+    }
+
+    fn apply_function(&self, function: Object, args: Vec<Object>, span: Span) -> Object {
+        let depth = self.stack.borrow().len();
+        let max = self.max_call_depth();
+        if depth >= max {
+            return limit_exceeded(format!("call depth limit of {} exceeded", max), span);
+        }
+        let profiled = self.profile_depth();
+        let observed = self.open_calls.get();
+        let result = self.run_function(function, args, span);
+        self.profiling(|profiler| profiler.exit_to(profiled));
+        // the frames of the calls an error unwinds are still on the stack
+        let result = self.traced(result);
+        while self.open_calls.get() > observed {
+            self.open_calls.set(self.open_calls.get() - 1);
+            self.observe(|observer| observer.returned(&result));
+        }
+        self.stack.borrow_mut().truncate(depth);
+        result
+    }
+
+    /// Calls `function`, then keeps making the tail calls it returns, so a
+    /// chain of tail calls runs in one native stack frame.
+    fn run_function(&self, mut function: Object, mut args: Vec<Object>, mut span: Span) -> Object {
+        let mut entered = false;
+        loop {
+            self.profiling(|profiler| {
+                // a tail call ends the call making it
+                if entered {
+                    profiler.exit();
+                }
+                if let Some(name) = call_name(&function) {
+                    profiler.enter(name);
+                }
+            });
+            if !self.observers.is_empty() {
+                if let Some(name) = call_name(&function) {
+                    self.open_calls.set(self.open_calls.get() + 1);
+                    self.observe(|observer| observer.call(&name, &args, span));
+                }
+            }
+            let func = match function {
+                Object::Function(func) => func,
+                Object::Builtin(builtin) => {
+                    return self.allocate((builtin.func)(&args, span), span)
+                }
+                Object::Host(host) => return self.allocate(host.call(&args, span), span),
+                other => {
+                    return error(
+                        ErrorKind::NotCallable,
+                        format!("not a function: {}", other.type_name()),
+                        span,
+                    )
+                }
+            };
+            if func.parameters.len() != args.len() {
+                return error(
+                    ErrorKind::WrongArguments,
+                    format!(
+                        "wrong number of arguments: want={}, got={}",
+                        func.parameters.len(),
+                        args.len()
+                    ),
+                    span,
+                );
+            }
+
+            // the scope of the call, with the arguments bound in it
+            let size = mem::size_of::<Environment>()
+                + args.len() * mem::size_of::<(String, Object)>();
+            if let Err(err) = self.charge(size, span) {
+                return err;
+            }
+            let extended_env = Environment::new_enclosed(&func.env);
+            for (param, arg) in func.parameters.iter().zip(args) {
+                extended_env.borrow_mut().set(param.value.clone(), arg);
+            }
+
+            let mut stack = self.stack.borrow_mut();
+            if entered {
+                // a tail call takes the place of its caller, returning to
+                // where the caller would have
+                stack.last_mut().unwrap().function = func.name.clone();
+            } else {
+                stack.push(Frame {
+                    function: func.name.clone(),
+                    call_site: span,
+                });
+                entered = true;
+            }
+            drop(stack);
+
+            match self.eval_block_statement(&func.body, &extended_env, true) {
+                Object::TailCall(call) => {
+                    function = call.function;
+                    args = call.args;
+                    span = call.span;
+                }
+                Object::ReturnValue(value) => return *value,
+                result => return result,
+            }
+        }
+    }
+
+    pub(crate) fn eval_prefix_expression(&self, operator: &str, right: Object, span: Span) -> Object {
+        match (operator, &right) {
+            ("!", _) => Object::Boolean(!right.is_truthy()),
+            ("-", Object::Integer(value)) => match value.checked_neg() {
+                Some(value) => Object::Integer(value),
+                None => self.overflow(-BigInt::from(*value), span, || format!("-({})", value)),
+            },
+            ("-", Object::BigInteger(value)) => {
+                self.overflow(-BigInt::clone(value), span, || format!("-({})", value))
+            }
+            _ => error(
+                ErrorKind::UnknownOperator,
+                format!("unknown operator: {}{}", operator, right.type_name()),
+                span,
+            ),
+        }
+    }
+
+    pub(crate) fn eval_infix_expression(
+        &self,
+        operator: &str,
+        left: Object,
+        right: Object,
+        span: Span,
+    ) -> Object {
+        match (&left, &right) {
+            (Object::Integer(l), Object::Integer(r)) => {
+                self.eval_integer_infix_expression(operator, *l, *r, span)
+            }
+            (
+                Object::Integer(_) | Object::BigInteger(_),
+                Object::Integer(_) | Object::BigInteger(_),
+            ) => self.eval_big_integer_infix_expression(operator, big(&left), big(&right), span),
+            (Object::String(l), Object::String(r)) => {
+                self.allocate(eval_string_infix_expression(operator, l, r, span), span)
+            }
+            (Object::Boolean(l), Object::Boolean(r)) if operator == "==" => Object::Boolean(l == r),
+            (Object::Boolean(l), Object::Boolean(r)) if operator == "!=" => Object::Boolean(l != r),
+            _ if left.type_name() != right.type_name() => error(
+                ErrorKind::TypeMismatch,
+                format!(
+                    "type mismatch: {} {} {}",
+                    left.type_name(),
+                    operator,
+                    right.type_name()
+                ),
+                span,
+            ),
+            _ => error(
+                ErrorKind::UnknownOperator,
+                format!(
+                    "unknown operator: {} {} {}",
+                    left.type_name(),
+                    operator,
+                    right.type_name()
+                ),
+                span,
+            ),
+        }
+    }
+
+    fn eval_integer_infix_expression(
+        &self,
+        operator: &str,
+        left: i64,
+        right: i64,
+        span: Span,
+    ) -> Object {
+        let result = match operator {
+            "+" => left.checked_add(right),
+            "-" => left.checked_sub(right),
+            "*" => left.checked_mul(right),
+            "/" if right == 0 => return error(ErrorKind::DivisionByZero, "division by zero", span),
+            "/" => left.checked_div(right),
+            "<" => return Object::Boolean(left < right),
+            ">" => return Object::Boolean(left > right),
+            "==" => return Object::Boolean(left == right),
+            "!=" => return Object::Boolean(left != right),
+            _ => return unknown_integer_operator(operator, span),
+        };
+        match result {
+            Some(value) => Object::Integer(value),
+            None => {
+                self.eval_big_integer_infix_expression(operator, left.into(), right.into(), span)
+            }
+        }
+    }
+
+    fn eval_big_integer_infix_expression(
+        &self,
+        operator: &str,
+        left: BigInt,
+        right: BigInt,
+        span: Span,
+    ) -> Object {
+        let value = match operator {
+            "+" => &left + &right,
+            "-" => &left - &right,
+            "*" => &left * &right,
+            "/" if right.is_zero() => {
+                return error(ErrorKind::DivisionByZero, "division by zero", span)
+            }
+            // truncates toward zero, like i64 division
+            "/" => &left / &right,
+            "<" => return Object::Boolean(left < right),
+            ">" => return Object::Boolean(left > right),
+            "==" => return Object::Boolean(left == right),
+            "!=" => return Object::Boolean(left != right),
+            _ => return unknown_integer_operator(operator, span),
+        };
+        self.overflow(value, span, || format!("{} {} {}", left, operator, right))
+    }
+
+    fn eval_if_expression(&self, exp: &IfExpression, env: &Env, tail: bool) -> Object {
+        let condition = self.eval_expression(&exp.condition, env);
+        if condition.is_abrupt() {
+            return condition;
+        }
+
+        self.covering(|coverage| coverage.branch(exp.token.span, condition.is_truthy()));
+        if condition.is_truthy() {
+            self.eval_block_statement(&exp.consequence, env, tail)
+        } else if let Some(alternative) = &exp.alternative {
+            self.eval_block_statement(alternative, env, tail)
+        } else {
+            Object::Null
+        }
+    }
+
+    /// The object for an integer result, or `None` if it is out of range in
+    /// checked mode. Values that fit in an i64 are always `Object::Integer`,
+    /// so each number has one representation.
+    fn integer(&self, value: BigInt) -> Option<Object> {
+        match value.to_i64() {
+            Some(value) => Some(Object::Integer(value)),
+            None if self.integers == IntegerMode::Arbitrary => {
+                Some(Object::BigInteger(Rc::new(value)))
+            }
+            None => None,
+        }
+    }
+
+    /// Like `integer`, but reports an out of range result of `operation` as
+    /// an error.
+    fn overflow<F: FnOnce() -> String>(&self, value: BigInt, span: Span, operation: F) -> Object {
+        match self.integer(value) {
+            Some(int) => self.allocate(int, span),
+            None => error(
+                ErrorKind::IntegerOverflow,
+                format!("integer overflow: {}", operation()),
+                span,
+            ),
+        }
+    }
+
+    /// Attaches the current call stack to an error that does not have one
+    /// yet, which is where it was raised.
+    fn traced(&self, mut result: Object) -> Object {
+        if let Object::Error(err) = &mut result {
+            if err.backtrace.frames.is_empty() {
+                err.backtrace = self.backtrace(err.span);
+                self.observe(|observer| observer.error(err));
+            }
+        }
+        result
+    }
+
+    pub(crate) fn backtrace(&self, span: Span) -> Backtrace {
+        let stack = self.stack.borrow();
+        let mut frames = Vec::with_capacity(stack.len() + 1);
+        let mut span = span;
+        for frame in stack.iter().rev() {
+            let function = frame.function.as_deref().unwrap_or("<anonymous>");
+            frames.push(TraceFrame {
+                function: function.to_string(),
+                span,
+            });
+            span = frame.call_site;
+        }
+        frames.push(TraceFrame {
+            function: "<program>".to_string(),
+            span,
+        });
+        Backtrace { frames }
+    }
+
+    /// Counts the statement at `span` as covered and lets an attached
+    /// debugger stop before it.
+    fn before_statement(&self, span: Span, env: &Env) -> Result<(), Object> {
+        self.covering(|coverage| coverage.statement(span));
+        self.observe(|observer| observer.statement(span));
+        let debug = match &self.debug {
+            Some(debug) if !self.suspended.get() => debug,
+            _ => return Ok(()),
+        };
+        let depth = self.stack.borrow().len();
+        match debug.borrow_mut().pause(self, env, span, depth) {
+            Some(Resume::Quit) => Err(error(ErrorKind::Cancelled, "stopped by the debugger", span)),
+            _ => Ok(()),
+        }
+    }
+
+    fn profiling<F: FnOnce(&mut Profiler)>(&self, f: F) {
+        if let Some(profiler) = &self.profiler {
+            f(&mut profiler.borrow_mut());
+        }
+    }
+
+    fn observe<F: FnMut(&mut dyn Observer)>(&self, mut f: F) {
+        for observer in &self.observers {
+            f(&mut **observer.borrow_mut());
+        }
+    }
+
+    fn covering<F: FnOnce(&mut Recorder)>(&self, f: F) {
+        if let Some(coverage) = &self.coverage {
+            f(&mut coverage.borrow_mut());
+        }
+    }
+
+    fn profile_depth(&self) -> usize {
+        self.profiler.as_ref().map_or(0, |profiler| profiler.borrow().depth())
+    }
+
+    /// Counts one evaluation step against the step limit, and at the first
+    /// step and every so often after checks whether the evaluation was
+    /// cancelled or is out of time.
+    pub(crate) fn step(&self, span: Span) -> Result<(), Object> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+        if let Some(max) = self.limits.steps {
+            if steps > max {
+                return Err(limit_exceeded(format!("step limit of {} exceeded", max), span));
+            }
+        }
+        let size = self.stack_size.unwrap_or(DEFAULT_STACK_SIZE);
+        if self.stack_base.get().abs_diff(stack_position()) > size.saturating_sub(STACK_RESERVE) {
+            return Err(limit_exceeded(format!("stack limit of {} bytes exceeded", size), span));
+        }
+        // at the first step too, so that a cancellation made before an
+        // evaluation shorter than the interval is not lost
+        if steps != 1 && !steps.is_multiple_of(CHECK_INTERVAL) {
+            return Ok(());
+        }
+        if self.cancel.is_cancelled() {
+            return Err(error(ErrorKind::Cancelled, "evaluation cancelled", span));
+        }
+        match (self.deadline.get(), self.timeout) {
+            (Some(deadline), Some(timeout)) if Instant::now() >= deadline => Err(error(
+                ErrorKind::Cancelled,
+                format!("evaluation timed out after {:?}", timeout),
+                span,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Charges the memory limit for `obj` if it was just created, and passes
+    /// it on. Objects that share their contents with another (an element
+    /// taken out of an array, say) were paid for when they were made.
+    pub(crate) fn allocate(&self, obj: Object, span: Span) -> Object {
+        let size = match &obj {
+            Object::String(value) if Rc::strong_count(value) == 1 => value.len(),
+            Object::Array(elements) if Rc::strong_count(elements) == 1 => {
+                elements.len() * mem::size_of::<Object>()
+            }
+            Object::Hash(pairs) if Rc::strong_count(pairs) == 1 => {
+                pairs.len() * mem::size_of::<(HashKey, HashPair)>()
+            }
+            Object::BigInteger(value) if Rc::strong_count(value) == 1 => {
+                (value.bits() / 8) as usize
+            }
+            _ => return obj,
+        };
+        match self.charge(size, span) {
+            Ok(()) => obj,
+            Err(err) => err,
+        }
+    }
+
+    /// Counts `size` bytes just allocated against the memory limit.
+    pub(crate) fn charge(&self, size: usize, span: Span) -> Result<(), Object> {
+        let allocated = self.allocated.get().saturating_add(size);
+        self.allocated.set(allocated);
+        match self.limits.memory {
+            Some(max) if allocated > max => Err(limit_exceeded(
+                format!("memory limit of {} bytes exceeded", max),
+                span,
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub(crate) fn eval_index_expression(left: Object, index: Object, span: Span) -> Object {
+    match (&left, &index) {
+        (Object::Array(elements), Object::Integer(i)) => usize::try_from(*i)
+            .ok()
+            .and_then(|i| elements.get(i))
+            .cloned()
+            .unwrap_or(Object::Null),
+        // indexes count characters, not bytes
+        (Object::String(value), Object::Integer(i)) => usize::try_from(*i)
+            .ok()
+            .and_then(|i| value.chars().nth(i))
+            .map(|ch| Object::String(ch.to_string().into()))
+            .unwrap_or(Object::Null),
+        // too large (or too negative) for any array or string
+        (Object::Array(_) | Object::String(_), Object::BigInteger(_)) => Object::Null,
+        (Object::Hash(pairs), _) => match index.hash_key() {
+            Some(key) => pairs
+                .get(&key)
+                .map(|pair| pair.value.clone())
+                .unwrap_or(Object::Null),
+            None => unusable_as_hash_key(&index, span),
+        },
+        _ => error(
+            ErrorKind::UnknownOperator,
+            format!("index operator not supported: {}", left.type_name()),
+            span,
+        ),
+    }
+}
+
+pub(crate) fn unusable_as_hash_key(key: &Object, span: Span) -> Object {
+    error(
+        ErrorKind::UnhashableKey,
+        format!("unusable as hash key: {}", key.type_name()),
+        span,
+    )
+}
+
+fn eval_string_infix_expression(operator: &str, left: &str, right: &str, span: Span) -> Object {
+    match operator {
+        "+" => Object::String([left, right].concat().into()),
+        "==" => Object::Boolean(left == right),
+        "!=" => Object::Boolean(left != right),
+        _ => error(
+            ErrorKind::UnknownOperator,
+            format!("unknown operator: STRING {} STRING", operator),
+            span,
+        ),
+    }
+}
+
+fn unknown_integer_operator(operator: &str, span: Span) -> Object {
+    error(
+        ErrorKind::UnknownOperator,
+        format!("unknown operator: INTEGER {} INTEGER", operator),
+        span,
+    )
+}
+
+fn big(integer: &Object) -> BigInt {
+    match integer {
+        Object::Integer(value) => BigInt::from(*value),
+        Object::BigInteger(value) => BigInt::clone(value),
+        _ => unreachable!("not an integer: {}", integer.type_name()),
+    }
+}
+
+/// How the profiler and observers name a call to `function`, or `None` if
+/// it is not callable.
+fn call_name(function: &Object) -> Option<String> {
+    match function {
+        Object::Function(func) => Some(func.name.as_deref().unwrap_or("<anonymous>").to_string()),
+        Object::Builtin(builtin) => Some(format!("builtin {}", builtin.name)),
+        Object::Host(host) => Some(format!("host {}", host.name)),
+        _ => None,
+    }
+}
+
+/// Where the native stack stands, as the address of a local in a frame of
+/// its own. Only the distance between two positions means anything.
+#[inline(never)]
+fn stack_position() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+fn limit_exceeded(message: String, span: Span) -> Object {
+    error(ErrorKind::LimitExceeded, message, span)
+}
+
+pub(crate) fn error<P: Into<String>>(kind: ErrorKind, message: P, span: Span) -> Object {
+    Object::Error(Box::new(RuntimeError::new(kind, message, span)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Evaluator, IntegerMode, Limits, MAX_CALL_DEPTH, STACK_SIZE};
+    use crate::environment::Environment;
+    use crate::lexer::Lexer;
+    use crate::object::{ErrorKind, HashKey, Object};
+    use std::rc::Rc;
+    use crate::parser::Parser;
+    use std::thread;
+    use std::time::Duration;
+
+    fn eval(input: &str) -> Object {
+        eval_with(Evaluator::new(), input)
+    }
+
+    fn eval_with(evaluator: Evaluator, input: &str) -> Object {
+        let mut p = Parser::new(Lexer::new(input.into()));
+        let program = p.parse_program().unwrap();
+        assert!(p.errors().is_empty(), "parser errors: {:?}", p.errors());
+        evaluator.eval_program(&program, &Environment::new())
+    }
+
+    fn error_message(obj: Object) -> String {
+        match obj {
+            Object::Error(err) => err.message,
+            obj => panic!("no error object returned. got={:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_eval_integer_expression() {
+        let tests = vec![
+            ("5", 5),
+            ("10", 10),
+            ("-5", -5),
+            ("-10", -10),
+            ("5 + 5 + 5 + 5 - 10", 10),
+            ("2 * 2 * 2 * 2 * 2", 32),
+            ("-50 + 100 + -50", 0),
+            ("5 * 2 + 10", 20),
+            ("5 + 2 * 10", 25),
+            ("20 + 2 * -10", 0),
+            ("50 / 2 * 2 + 10", 60),
+            ("2 * (5 + 10)", 30),
+            ("3 * 3 * 3 + 10", 37),
+            ("3 * (3 * 3) + 10", 37),
+            ("(5 + 10 * 2 + 15 / 3) * 2 + -10", 50),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Object::Integer(expected), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_checked_integer_overflow() {
+        let tests = vec![
+            ("9223372036854775807 + 1", "integer overflow: 9223372036854775807 + 1"),
+            ("-9223372036854775807 - 2", "integer overflow: -9223372036854775807 - 2"),
+            ("4294967296 * 4294967296", "integer overflow: 4294967296 * 4294967296"),
+            ("-9223372036854775808 / -1", "integer overflow: -9223372036854775808 / -1"),
+            ("let min = -9223372036854775808; -min", "integer overflow: -(-9223372036854775808)"),
+            ("9223372036854775808", "integer literal out of range: 9223372036854775808"),
+        ];
+        for (input, expected) in tests {
+            match eval(input) {
+                Object::Error(err) => {
+                    assert_eq!(err.kind, ErrorKind::IntegerOverflow, "input: {}", input);
+                    assert_eq!(err.message, expected, "input: {}", input);
+                }
+                obj => panic!("no error object returned for {}. got={:?}", input, obj),
+            }
+        }
+        assert_eq!(eval("-9223372036854775808"), Object::Integer(i64::MIN));
+        assert_eq!(eval("9223372036854775807"), Object::Integer(i64::MAX));
+    }
+
+    #[test]
+    fn test_arbitrary_precision_integers() {
+        let tests = vec![
+            ("9223372036854775807 + 1", "9223372036854775808"),
+            ("-9223372036854775808 / -1", "9223372036854775808"),
+            ("123456789012345678901234567890 * 10", "1234567890123456789012345678900"),
+            (
+                "let f = fn(n, acc) { if (n < 2) { acc } else { f(n - 1, acc * n) } }; f(25, 1)",
+                "15511210043330985984000000",
+            ),
+            ("-(-9223372036854775808)", "9223372036854775808"),
+            ("99999999999999999999 > 9", "true"),
+            ("{18446744073709551616: 1}[18446744073709551616]", "1"),
+            ("[1][18446744073709551616]", "null"),
+            ("1 / (99999999999999999999 - 99999999999999999999)", "ERROR: division by zero at 1:1"),
+        ];
+        let arbitrary = || Evaluator::new().with_integer_mode(IntegerMode::Arbitrary);
+        for (input, expected) in tests {
+            let evaluated = eval_with(arbitrary(), input);
+            assert_eq!(evaluated.to_string(), expected, "input: {}", input);
+        }
+
+        // results back in range are ordinary integers
+        let evaluated = eval_with(arbitrary(), "(9223372036854775807 + 10) - 10");
+        assert_eq!(evaluated, Object::Integer(i64::MAX));
+    }
+
+    #[test]
+    fn test_eval_boolean_expression() {
+        let tests = vec![
+            ("true", true),
+            ("false", false),
+            ("1 < 2", true),
+            ("1 > 2", false),
+            ("1 == 1", true),
+            ("1 != 1", false),
+            ("1 != 2", true),
+            ("true == true", true),
+            ("true != false", true),
+            ("false == true", false),
+            ("(1 < 2) == true", true),
+            ("(1 > 2) == true", false),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Object::Boolean(expected), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_bang_operator() {
+        let tests = vec![
+            ("!true", false),
+            ("!false", true),
+            ("!5", false),
+            ("!!true", true),
+            ("!!false", false),
+            ("!!5", true),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Object::Boolean(expected), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_if_else_expressions() {
+        let tests = vec![
+            ("if (true) { 10 }", Object::Integer(10)),
+            ("if (false) { 10 }", Object::Null),
+            ("if (1) { 10 }", Object::Integer(10)),
+            ("if (1 < 2) { 10 }", Object::Integer(10)),
+            ("if (1 > 2) { 10 }", Object::Null),
+            ("if (1 > 2) { 10 } else { 20 }", Object::Integer(20)),
+            ("if (1 < 2) { 10 } else { 20 }", Object::Integer(10)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_return_statements() {
+        let tests = vec![
+            ("return 10;", 10),
+            ("return 10; 9;", 10),
+            ("return 2 * 5; 9;", 10),
+            ("9; return 2 * 5; 9;", 10),
+            ("if (10 > 1) { if (10 > 1) { return 10; } return 1; }", 10),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Object::Integer(expected), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_error_handling() {
+        let tests = vec![
+            ("5 + true;", "type mismatch: INTEGER + BOOLEAN"),
+            ("5 + true; 5;", "type mismatch: INTEGER + BOOLEAN"),
+            ("-true", "unknown operator: -BOOLEAN"),
+            ("true + false;", "unknown operator: BOOLEAN + BOOLEAN"),
+            ("5; true + false; 5", "unknown operator: BOOLEAN + BOOLEAN"),
+            ("if (10 > 1) { true + false; }", "unknown operator: BOOLEAN + BOOLEAN"),
+            (
+                "if (10 > 1) { if (10 > 1) { return true + false; } return 1; }",
+                "unknown operator: BOOLEAN + BOOLEAN",
+            ),
+            ("1 / 0", "division by zero"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(error_message(eval(input)), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_let_statements() {
+        let tests = vec![
+            ("let a = 5; a;", 5),
+            ("let a = 5 * 5; a;", 25),
+            ("let a = 5; let b = a; b;", 5),
+            ("let a = 5; let b = a; let c = a + b + 5; c;", 15),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Object::Integer(expected), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_function_object() {
+        match eval("fn(x) { x + 2; };") {
+            Object::Function(func) => {
+                assert_eq!(func.parameters.len(), 1);
+                assert_eq!(func.parameters[0].value, "x");
+                assert_eq!(func.body.to_string(), "(x + 2)");
+            }
+            obj => panic!("object is not Function. got={:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_function_application() {
+        let tests = vec![
+            ("let identity = fn(x) { x; }; identity(5);", 5),
+            ("let identity = fn(x) { return x; }; identity(5);", 5),
+            ("let double = fn(x) { x * 2; }; double(5);", 10),
+            ("let add = fn(x, y) { x + y; }; add(5, 5);", 10),
+            ("let add = fn(x, y) { x + y; }; add(5 + 5, add(5, 5));", 20),
+            ("fn(x) { x; }(5)", 5),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Object::Integer(expected), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_closures() {
+        let input = r#"
+let newAdder = fn(x) {
+  fn(y) { x + y };
+};
+
+let addTwo = newAdder(2);
+addTwo(2);"#;
+        assert_eq!(eval(input), Object::Integer(4));
+    }
+
+    #[test]
+    fn test_scopes_do_not_leak() {
+        let input = "let x = 1; let f = fn(x) { let y = x * 10; y }; f(5) + x;";
+        assert_eq!(eval(input), Object::Integer(51));
+        assert_eq!(
+            error_message(eval("let f = fn() { let inner = 1; inner }; f(); inner")),
+            "identifier not found: inner"
+        );
+    }
+
+    #[test]
+    fn test_recursive_functions() {
+        let input = r#"
+let fib = fn(n) {
+  if (n < 2) { return n; }
+  fib(n - 1) + fib(n - 2)
+};
+fib(15);"#;
+        assert_eq!(eval(input), Object::Integer(610));
+    }
+
+    #[test]
+    fn test_higher_order_functions() {
+        let input = r#"
+let twice = fn(f, x) { f(f(x)) };
+let compose = fn(f, g) { fn(x) { g(f(x)) } };
+let inc = fn(x) { x + 1 };
+let double = fn(x) { x * 2 };
+twice(compose(inc, double), 3);"#;
+        assert_eq!(eval(input), Object::Integer(18));
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let tests = vec![
+            ("foobar", "identifier not found: foobar"),
+            ("let x = 5; x(1)", "not a function: INTEGER"),
+            ("fn(x, y) { x }(1)", "wrong number of arguments: want=2, got=1"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(error_message(eval(input)), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_error_kinds_and_spans() {
+        let tests = vec![
+            ("true + 1", ErrorKind::TypeMismatch, "type mismatch: BOOLEAN + INTEGER", (1, 1)),
+            ("let x = 1;\n  -true", ErrorKind::UnknownOperator, "unknown operator: -BOOLEAN", (2, 3)),
+            ("1 + foo", ErrorKind::UnknownIdentifier, "identifier not found: foo", (1, 5)),
+            ("let x = 5;\nx(1)", ErrorKind::NotCallable, "not a function: INTEGER", (2, 1)),
+            ("10 / (5 - 5)", ErrorKind::DivisionByZero, "division by zero", (1, 1)),
+        ];
+
+        for (input, kind, message, (line, column)) in tests {
+            match eval(input) {
+                Object::Error(err) => {
+                    assert_eq!(err.kind, kind, "input: {}", input);
+                    assert_eq!(err.message, message, "input: {}", input);
+                    assert_eq!((err.span.line, err.span.column), (line, column), "input: {}", input);
+                }
+                obj => panic!("no error object returned. got={:?}", obj),
+            }
+        }
+    }
+
+    #[test]
+    fn test_errors_propagate_through_calls() {
+        let input = r#"let inner = fn(x) {
+  x + true
+};
+let outer = fn(x) { let y = inner(x); y * 100 };
+let result = outer(1);
+result"#;
+        match eval(input) {
+            Object::Error(err) => {
+                assert_eq!(err.message, "type mismatch: INTEGER + BOOLEAN");
+                assert_eq!((err.span.line, err.span.column), (2, 3));
+                assert_eq!(err.to_string(), "type mismatch: INTEGER + BOOLEAN at 2:3");
+            }
+            obj => panic!("no error object returned. got={:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_backtraces() {
+        let input = r#"let inner = fn(x) {
+  x + true
+};
+let outer = fn(x) { let y = inner(x); y * 100 };
+let viaTail = fn(x) { outer(x) };
+fn(f) { f(1) + 1 }(viaTail)"#;
+        // `viaTail` made a tail call, so `outer` took its frame
+        match eval(input) {
+            Object::Error(err) => assert_eq!(
+                err.backtrace.to_string(),
+                "    in inner at 2:3\n\
+                 \x20   in outer at 4:29\n\
+                 \x20   in <anonymous> at 6:9\n\
+                 \x20   in <program> at 6:1"
+            ),
+            obj => panic!("no error object returned. got={:?}", obj),
+        }
+
+        match eval("let x = 1;\n-true") {
+            Object::Error(err) => assert_eq!(err.backtrace.to_string(), "    in <program> at 2:1"),
+            obj => panic!("no error object returned. got={:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_builtin_functions() {
+        let tests = vec![
+            ("puts(1, true)", Object::Null),
+            ("let len = fn(x) { 42 }; len(1)", Object::Integer(42)),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+
+        let errors = vec![
+            ("len(1)", ErrorKind::InvalidArgument, "argument to `len` not supported, got INTEGER"),
+            ("first(true)", ErrorKind::InvalidArgument, "argument to `first` not supported, got BOOLEAN"),
+            ("len()", ErrorKind::WrongArguments, "wrong number of arguments to `len`: want=1, got=0"),
+            ("push(1)", ErrorKind::WrongArguments, "wrong number of arguments to `push`: want=2, got=1"),
+        ];
+        for (input, kind, message) in errors {
+            match eval(input) {
+                Object::Error(err) => {
+                    assert_eq!(err.kind, kind, "input: {}", input);
+                    assert_eq!(err.message, message, "input: {}", input);
+                }
+                obj => panic!("no error object returned. got={:?}", obj),
+            }
+        }
+    }
+
+    #[test]
+    fn test_array_literals() {
+        let expected = vec![Object::Integer(1), Object::Integer(4), Object::Integer(6)];
+        assert_eq!(eval("[1, 2 * 2, 3 + 3]"), Object::Array(Rc::new(expected)));
+    }
+
+    #[test]
+    fn test_array_index_expressions() {
+        let tests = vec![
+            ("[1, 2, 3][0]", Object::Integer(1)),
+            ("[1, 2, 3][2]", Object::Integer(3)),
+            ("let i = 0; [1][i];", Object::Integer(1)),
+            ("[1, 2, 3][1 + 1];", Object::Integer(3)),
+            ("let myArray = [1, 2, 3]; myArray[0] + myArray[1] + myArray[2];", Object::Integer(6)),
+            ("[1, 2, 3][3]", Object::Null),
+            ("[1, 2, 3][-1]", Object::Null),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_hash_literals() {
+        let input = "let two = 2; {1: 10 - 9, two: 1 + 1, true: 3, false: 4, 1: 5}";
+        match eval(input) {
+            Object::Hash(pairs) => {
+                let got: Vec<_> = pairs.iter().map(|(k, pair)| (k.clone(), pair.value.clone())).collect();
+                assert_eq!(
+                    got,
+                    vec![
+                        (HashKey::Integer(1), Object::Integer(5)),
+                        (HashKey::Integer(2), Object::Integer(2)),
+                        (HashKey::Boolean(false), Object::Integer(4)),
+                        (HashKey::Boolean(true), Object::Integer(3)),
+                    ]
+                );
+            }
+            obj => panic!("object is not Hash. got={:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_hash_index_expressions() {
+        let tests = vec![
+            ("{1: 5}[1]", Object::Integer(5)),
+            ("{1: 5}[2]", Object::Null),
+            ("let key = 5; {5: 5}[key]", Object::Integer(5)),
+            ("{}[5]", Object::Null),
+            ("{true: 5}[true]", Object::Integer(5)),
+            ("{false: 5}[false]", Object::Integer(5)),
+            ("{1: [1, 2]}[1][1]", Object::Integer(2)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_array_builtins() {
+        let tests = vec![
+            ("len([1, 2, 3])", Object::Integer(3)),
+            ("len([])", Object::Integer(0)),
+            ("first([1, 2, 3])", Object::Integer(1)),
+            ("first([])", Object::Null),
+            ("last([1, 2, 3])", Object::Integer(3)),
+            ("last([])", Object::Null),
+            ("rest([])", Object::Null),
+            ("let a = [1]; let b = push(a, 2); len(a) + len(b)", Object::Integer(3)),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+
+        assert_eq!(eval("rest([1, 2, 3])").to_string(), "[2, 3]");
+        assert_eq!(eval("push([], 1)").to_string(), "[1]");
+        let input = r#"
+let map = fn(arr, f) {
+  let iter = fn(arr, acc) {
+    if (len(arr) == 0) { acc } else { iter(rest(arr), push(acc, f(first(arr)))) }
+  };
+  iter(arr, []);
+};
+map([1, 2, 3], fn(x) { x * 2 })"#;
+        assert_eq!(eval(input).to_string(), "[2, 4, 6]");
+    }
+
+    #[test]
+    fn test_hash_and_index_errors() {
+        let tests = vec![
+            ("{fn(x) { x }: 1}", ErrorKind::UnhashableKey, "unusable as hash key: FUNCTION"),
+            ("{1: 2}[fn(x) { x }]", ErrorKind::UnhashableKey, "unusable as hash key: FUNCTION"),
+            ("{[1]: 2}", ErrorKind::UnhashableKey, "unusable as hash key: ARRAY"),
+            (r#""a" - "b""#, ErrorKind::UnknownOperator, "unknown operator: STRING - STRING"),
+            (r#""a" + 1"#, ErrorKind::TypeMismatch, "type mismatch: STRING + INTEGER"),
+            ("1[0]", ErrorKind::UnknownOperator, "index operator not supported: INTEGER"),
+        ];
+
+        for (input, kind, message) in tests {
+            match eval(input) {
+                Object::Error(err) => {
+                    assert_eq!(err.kind, kind, "input: {}", input);
+                    assert_eq!(err.message, message, "input: {}", input);
+                }
+                obj => panic!("no error object returned. got={:?}", obj),
+            }
+        }
+    }
+
+    #[test]
+    fn test_string_expressions() {
+        let tests = vec![
+            (r#""Hello World!""#, Object::String("Hello World!".into())),
+            (r#""Hello" + " " + "World!""#, Object::String("Hello World!".into())),
+            (r#""a" == "a""#, Object::Boolean(true)),
+            (r#""a" == "b""#, Object::Boolean(false)),
+            (r#""a" != "b""#, Object::Boolean(true)),
+            (r#"len("")"#, Object::Integer(0)),
+            (r#"len("four")"#, Object::Integer(4)),
+            (r#"len("héllo wörld")"#, Object::Integer(11)),
+            (r#"len("日本語")"#, Object::Integer(3)),
+            (r#""héllo"[1]"#, Object::String("é".into())),
+            (r#""日本語"[2]"#, Object::String("語".into())),
+            (r#""abc"[3]"#, Object::Null),
+            (r#"{"name": "monkey"}["name"]"#, Object::String("monkey".into())),
+            (r#"let greet = fn(name) { "hi " + name }; greet("bob")"#, Object::String("hi bob".into())),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        let tests = vec![
+            (
+                "let loop = fn(n) { if (n == 0) { 0 } else { loop(n - 1) } }; loop(1000000)",
+                Object::Integer(0),
+            ),
+            (
+                "let count = fn(n, acc) { if (n == 0) { return acc; } return count(n - 1, acc + 1); }; count(100000, 0)",
+                Object::Integer(100000),
+            ),
+            (
+                r#"
+let isEven = fn(n) { if (n == 0) { true } else { isOdd(n - 1) } };
+let isOdd = fn(n) { if (n == 0) { false } else { isEven(n - 1) } };
+isEven(100001)"#,
+                Object::Boolean(false),
+            ),
+            ("let size = fn(a) { len(a) }; size([1, 2])", Object::Integer(2)),
+            ("return len([1]);", Object::Integer(1)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_return_inside_expression() {
+        let input = r#"
+let f = fn(x) {
+  let y = if (x) { return first([1]); } else { 2 };
+  y + 10
+};
+[f(true), f(false), 100 + f(true)]"#;
+        assert_eq!(eval(input).to_string(), "[1, 12, 101]");
+    }
+
+    #[test]
+    fn test_limits() {
+        let limited = |limits| Evaluator::new().with_limits(limits);
+        let steps = Limits { steps: Some(10_000), ..Limits::default() };
+        let depth = Limits { call_depth: Some(100), ..Limits::default() };
+        let memory = Limits { memory: Some(1 << 20), ..Limits::default() };
+        let out_of_memory = "memory limit of 1048576 bytes exceeded";
+        let chain = "let build = fn(n, acc) {\n\
+                       if (n == 0) { 0 } else { build(n - 1, fn() { acc }) }\n\
+                     };\n\
+                     build(2000000, 0)";
+        let tests = vec![
+            (steps, "let spin = fn() { spin() }; spin()", "step limit of 10000 exceeded"),
+            (depth, "let deep = fn(n) { 1 + deep(n) }; deep(1)", "call depth limit of 100 exceeded"),
+            (memory, r#"let grow = fn(s) { grow(s + s) }; grow("ab")"#, out_of_memory),
+            (memory, "let grow = fn(a) { grow(push(a, 1)) }; grow([])", out_of_memory),
+            // each closure keeps the one before, and the scope it was made in
+            (memory, chain, out_of_memory),
+        ];
+        for (limits, input, expected) in tests {
+            match eval_with(limited(limits), input) {
+                Object::Error(err) => {
+                    assert_eq!(err.kind, ErrorKind::LimitExceeded, "input: {}", input);
+                    assert_eq!(err.message, expected, "input: {}", input);
+                }
+                obj => panic!("no error object returned for {}. got={:?}", input, obj),
+            }
+        }
+
+        // tail calls do not nest
+        let input = "let count = fn(n) { if (n == 0) { 0 } else { count(n - 1) } }; count(1000)";
+        let limits = Limits { steps: Some(100_000), call_depth: Some(60), memory: None };
+        assert_eq!(eval_with(limited(limits), input), Object::Integer(0));
+
+        // the scopes of the calls are allocations, taking an element out is not
+        let input = r#"
+let big = [1, 2, 3, 4, 5, 6, 7, 8];
+let take = fn(n) { if (n == 0) { first(big) } else { first([big])[0] + take(n - 1) } };
+take(50)"#;
+        let limits = Limits { memory: Some(12 << 10), ..Limits::default() };
+        assert_eq!(eval_with(limited(limits), input), Object::Integer(51));
+        let limits = Limits { memory: Some(4 << 10), ..Limits::default() };
+        let message = error_message(eval_with(limited(limits), input));
+        assert_eq!(message, "memory limit of 4096 bytes exceeded");
+
+        // each program gets a fresh budget
+        let evaluator = limited(steps);
+        let env = Environment::new();
+        let input = "let f = fn(n) { if (n == 0) { 0 } else { f(n - 1) } }; f(500)";
+        let mut p = Parser::new(Lexer::new(input.into()));
+        let program = p.parse_program().unwrap();
+        for _ in 0..3 {
+            assert_eq!(evaluator.eval_program(&program, &env), Object::Integer(0));
+        }
+    }
+
+    #[test]
+    fn test_call_depth_is_capped() {
+        // deep enough to overflow the stack a test runs on by default
+        let deep = thread::Builder::new().stack_size(STACK_SIZE).spawn(|| {
+            let input = "let deep = fn(n) { 1 + deep(n) }; deep(1)";
+            let unlimited = Limits { call_depth: Some(usize::MAX), ..Limits::default() };
+            for limits in [Limits::default(), unlimited].iter() {
+                let evaluator = Evaluator::new().with_limits(*limits).with_stack_size(STACK_SIZE);
+                match eval_with(evaluator, input) {
+                    Object::Error(err) => {
+                        assert_eq!(err.kind, ErrorKind::LimitExceeded);
+                        let expected = format!("call depth limit of {} exceeded", MAX_CALL_DEPTH);
+                        assert_eq!(err.message, expected);
+                    }
+                    obj => panic!("no error object returned. got={:?}", obj),
+                }
+            }
+        });
+        deep.unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn test_stack_is_not_overflowed() {
+        // on the 2 MiB stack a test runs on, which is also the default
+        let nested = format!("{}f(n + 1){}", "-(".repeat(60), ")".repeat(60));
+        let tests = vec![
+            "let f = fn(n) { 1 + f(n + 1) }; f(0)".to_string(),
+            format!("let f = fn(n) {{ {} }}; f(0)", nested),
+            "let f = fn(n) { [f(n + 1)] }; f(0)".to_string(),
+        ];
+        for input in tests {
+            match eval(&input) {
+                Object::Error(err) => {
+                    assert_eq!(err.kind, ErrorKind::LimitExceeded);
+                    assert_eq!(err.message, "stack limit of 2097152 bytes exceeded");
+                }
+                obj => panic!("no error object returned. got={:?}", obj),
+            }
+        }
+    }
+
+    #[test]
+    fn test_cancellation_and_timeout() {
+        let parse = |input: &str| {
+            let mut p = Parser::new(Lexer::new(input.into()));
+            p.parse_program().unwrap()
+        };
+        let spin = parse("let n = 0; let spin = fn() { spin() }; spin()");
+        let env = Environment::new();
+        let cancelled = |obj| match obj {
+            Object::Error(err) => {
+                assert_eq!(err.kind, ErrorKind::Cancelled);
+                assert_eq!(err.message, "evaluation cancelled");
+            }
+            obj => panic!("no error object returned. got={:?}", obj),
+        };
+
+        // the timeout is a backstop, should a cancellation be lost
+        let evaluator = Evaluator::new().with_timeout(Duration::from_secs(10));
+        let cancel = evaluator.cancel_handle();
+        // a cancellation before the program starts stops it too
+        cancel.cancel();
+        cancelled(evaluator.eval_program(&spin, &env));
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            cancel.cancel();
+        });
+        cancelled(evaluator.eval_program(&spin, &env));
+        canceller.join().unwrap();
+        // the cancellation does not carry over to the next program
+        assert_eq!(evaluator.eval_program(&parse("n + 1"), &env), Object::Integer(1));
+        // and stops one too short to reach the next check
+        evaluator.cancel_handle().cancel();
+        cancelled(evaluator.eval_program(&parse("n + 1"), &env));
+        assert_eq!(evaluator.eval_program(&parse("n + 1"), &env), Object::Integer(1));
+
+        let evaluator = Evaluator::new().with_timeout(Duration::from_millis(20));
+        assert_eq!(
+            error_message(evaluator.eval_program(&spin, &env)),
+            "evaluation timed out after 20ms"
+        );
+        assert_eq!(evaluator.eval_program(&parse("n + 2"), &env), Object::Integer(2));
+    }
+}
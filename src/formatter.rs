@@ -0,0 +1,336 @@
+use crate::ast::{BlockStatement, Expression, Program, Statement};
+use crate::lexer::Lexer;
+use crate::parser::{precedence_of, Parser, ParserError, Precedence};
+use crate::token::Token;
+use std::collections::VecDeque;
+
+static INDENT: &str = "    ";
+
+/// Formats Monkey source into its canonical layout: one statement per line,
+/// blocks indented by four spaces, single spaces around infix operators and
+/// after commas, and only the parentheses needed to keep the parse. Comments
+/// are kept, and a single blank line between statements is preserved.
+///
+/// Source that does not parse is returned as the list of parser errors.
+pub fn format(source: &str) -> Result<String, Vec<ParserError>> {
+    let mut parser = Parser::new(Lexer::new(source.to_string()));
+    let program = parser.parse_program().map_err(|err| vec![err])?;
+    if !parser.errors().is_empty() {
+        return Err(parser.errors().to_vec());
+    }
+
+    let mut printer = Printer {
+        lines: source.lines().collect(),
+        comments: parser.comments().iter().cloned().collect(),
+        out: String::new(),
+        depth: 0,
+    };
+    printer.print_program(&program);
+    Ok(printer.out)
+}
+
+struct Printer<'a> {
+    lines: Vec<&'a str>,
+    comments: VecDeque<Token>,
+    out: String,
+    depth: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn print_program(&mut self, program: &Program) {
+        self.print_statements(&program.statements, usize::MAX);
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    /// Prints each statement on its own line, followed by any comments that
+    /// appear before `end_line`.
+    fn print_statements(&mut self, statements: &[Statement], end_line: usize) {
+        for stmt in statements {
+            let line = stmt.span().line;
+            self.flush_comments(line);
+            self.start_line(line);
+            self.print_statement(stmt);
+        }
+        self.flush_comments(end_line);
+    }
+
+    fn print_statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::Let(stmt) => {
+                self.out.push_str("let ");
+                self.out.push_str(&stmt.name.value);
+                self.out.push_str(" = ");
+                self.print_expression(&stmt.value);
+                self.out.push(';');
+            }
+            Statement::Return(stmt) => {
+                self.out.push_str("return ");
+                self.print_expression(&stmt.return_value);
+                self.out.push(';');
+            }
+            Statement::Expression(stmt) => {
+                self.print_expression(&stmt.expression);
+                if !matches!(stmt.expression, Expression::If(_)) {
+                    self.out.push(';');
+                }
+            }
+        }
+    }
+
+    fn print_block(&mut self, block: &BlockStatement) {
+        let has_comments = self
+            .comments
+            .front()
+            .is_some_and(|c| c.span.line < block.end.line);
+        if block.statements.is_empty() && !has_comments {
+            self.out.push_str("{}");
+            return;
+        }
+
+        self.out.push('{');
+        self.depth += 1;
+        self.print_statements(&block.statements, block.end.line);
+        self.depth -= 1;
+        self.out.push('\n');
+        self.push_indent();
+        self.out.push('}');
+    }
+
+    fn print_expression(&mut self, exp: &Expression) {
+        match exp {
+            Expression::Identifier(ident) => self.out.push_str(&ident.value),
+            Expression::IntegerLiteral(int) => self.out.push_str(&int.value.to_string()),
+            Expression::StringLiteral(string) => {
+                self.out.push('"');
+                for ch in string.value.chars() {
+                    match ch {
+                        '"' => self.out.push_str("\\\""),
+                        '\\' => self.out.push_str("\\\\"),
+                        '\n' => self.out.push_str("\\n"),
+                        '\t' => self.out.push_str("\\t"),
+                        ch => self.out.push(ch),
+                    }
+                }
+                self.out.push('"');
+            }
+            Expression::Boolean(boolean) => self.out.push_str(&boolean.value.to_string()),
+            Expression::Prefix(prefix) => {
+                self.out.push_str(&prefix.operator);
+                self.print_operand(&prefix.right, Precedence::Prefix, false);
+            }
+            Expression::Infix(infix) => {
+                let precedence = precedence_of(&infix.token.t_type);
+                self.print_operand(&infix.left, precedence, false);
+                self.out.push(' ');
+                self.out.push_str(&infix.operator);
+                self.out.push(' ');
+                self.print_operand(&infix.right, precedence, true);
+            }
+            Expression::If(exp) => {
+                self.out.push_str("if (");
+                self.print_expression(&exp.condition);
+                self.out.push_str(") ");
+                self.print_block(&exp.consequence);
+                if let Some(alternative) = &exp.alternative {
+                    self.out.push_str(" else ");
+                    self.print_block(alternative);
+                }
+            }
+            Expression::FunctionLiteral(func) => {
+                let params: Vec<_> = func.parameters.iter().map(|p| p.value.as_str()).collect();
+                self.out.push_str("fn(");
+                self.out.push_str(&params.join(", "));
+                self.out.push_str(") ");
+                self.print_block(&func.body);
+            }
+            Expression::Call(call) => {
+                self.print_operand(&call.function, Precedence::Call, false);
+                self.print_list(&call.arguments, '(', ')');
+            }
+            Expression::ArrayLiteral(array) => self.print_list(&array.elements, '[', ']'),
+            Expression::HashLiteral(hash) => {
+                self.out.push('{');
+                for (i, (key, value)) in hash.pairs.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.print_expression(key);
+                    self.out.push_str(": ");
+                    self.print_expression(value);
+                }
+                self.out.push('}');
+            }
+            Expression::Index(index) => {
+                // calls and index expressions chain without parentheses
+                self.print_operand(&index.left, Precedence::Call, false);
+                self.out.push('[');
+                self.print_expression(&index.index);
+                self.out.push(']');
+            }
+        }
+    }
+
+    fn print_list(&mut self, items: &[Expression], open: char, close: char) {
+        self.out.push(open);
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.print_expression(item);
+        }
+        self.out.push(close);
+    }
+
+    /// Prints an operand of an operator binding with `precedence`, wrapping
+    /// it in parentheses when it binds more loosely. Operators are left
+    /// associative, so a right operand of equal precedence is wrapped too.
+    fn print_operand(&mut self, exp: &Expression, precedence: Precedence, right: bool) {
+        let inner = binding_of(exp);
+        let wrap = inner < precedence || (right && inner == precedence);
+        if wrap {
+            self.out.push('(');
+        }
+        self.print_expression(exp);
+        if wrap {
+            self.out.push(')');
+        }
+    }
+
+    /// Prints the comments that start before `line`. A comment that shared
+    /// its line with code stays at the end of the current output line.
+    fn flush_comments(&mut self, line: usize) {
+        while self.comments.front().is_some_and(|c| c.span.line < line) {
+            let comment = self.comments.pop_front().unwrap();
+            let mid_line = !self.out.is_empty() && !self.out.ends_with('\n');
+            if mid_line && self.is_trailing(&comment) {
+                self.out.push(' ');
+            } else {
+                self.start_line(comment.span.line);
+            }
+            self.out.push_str(&comment.literal);
+        }
+    }
+
+    fn is_trailing(&self, comment: &Token) -> bool {
+        let line = self.lines[comment.span.line - 1];
+        !line[..comment.span.column - 1].trim().is_empty()
+    }
+
+    /// Starts a new output line for source that began on `line`, keeping a
+    /// blank line above it if the source had one.
+    fn start_line(&mut self, line: usize) {
+        let at_block_start = self.out.is_empty() || self.out.ends_with('{');
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+        let blank_above = line >= 2 && self.lines[line - 2].trim().is_empty();
+        if blank_above && !at_block_start {
+            self.out.push('\n');
+        }
+        self.push_indent();
+    }
+
+    fn push_indent(&mut self) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+    }
+}
+
+/// How tightly an expression binds when it appears as an operand.
+fn binding_of(exp: &Expression) -> Precedence {
+    match exp {
+        Expression::Infix(infix) => precedence_of(&infix.token.t_type),
+        Expression::Prefix(_) => Precedence::Prefix,
+        Expression::Call(_) => Precedence::Call,
+        _ => Precedence::Index,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format;
+
+    fn check(input: &str, expected: &str) {
+        let formatted = format(input).expect("input should parse");
+        assert_eq!(formatted, expected, "input: {}", input);
+        let again = format(&formatted).expect("output should parse");
+        assert_eq!(again, formatted, "formatting is not idempotent");
+    }
+
+    #[test]
+    fn test_spacing() {
+        check("let x=5*(2+y);", "let x = 5 * (2 + y);\n");
+        check("add( 1,2 ,  3 )", "add(1, 2, 3);\n");
+        check("-a*b", "-a * b;\n");
+        check("-(a*b)", "-(a * b);\n");
+        check("a-(b-c)", "a - (b - c);\n");
+        check("(a-b)-c", "a - b - c;\n");
+        check("(fn(x){x})(1)", "fn(x) {\n    x;\n}(1);\n");
+        check("[1,2 ,3][ 0 ]", "[1, 2, 3][0];\n");
+        check("{1:2,true :[3]}", "{1: 2, true: [3]};\n");
+        check(r#"let s="a\"b"+"\t\\ é""#, "let s = \"a\\\"b\" + \"\\t\\\\ é\";\n");
+        check("(f(1))[0]+(-a)[1]", "f(1)[0] + (-a)[1];\n");
+    }
+
+    #[test]
+    fn test_one_statement_per_line() {
+        check("let a = 1; let b = 2\nreturn a", "let a = 1;\nlet b = 2;\nreturn a;\n");
+    }
+
+    #[test]
+    fn test_block_indentation() {
+        check(
+            "let max = fn(a, b) { if (a > b) { a } else { if (b > 0) { b } else { 0 } } };",
+            r#"let max = fn(a, b) {
+    if (a > b) {
+        a;
+    } else {
+        if (b > 0) {
+            b;
+        } else {
+            0;
+        }
+    }
+};
+"#,
+        );
+        check("let noop = fn() {  };", "let noop = fn() {};\n");
+    }
+
+    #[test]
+    fn test_comments_and_blank_lines() {
+        let input = r#"// adds numbers
+let add = fn(x, y) { // two args
+  // the sum
+  x + y
+
+
+  // done
+};
+
+let three = add(1, 2); // call
+// end
+"#;
+        let expected = r#"// adds numbers
+let add = fn(x, y) { // two args
+    // the sum
+    x + y;
+
+    // done
+};
+
+let three = add(1, 2); // call
+// end
+"#;
+        check(input, expected);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let errors = format("let = 5;").unwrap_err();
+        assert_eq!(errors[0].to_string(), "ParserError: Expected: IDENT, Got: ASSIGN");
+    }
+}
@@ -0,0 +1,453 @@
+use crate::environment::{Env, Environment};
+use crate::object::{Closure, HashKey, HashPair, Object, Variable};
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+/// Fewest new environments and cells between two collections.
+const MIN_THRESHOLD: usize = 1024;
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap::default());
+    static LIVE: Cell<usize> = const { Cell::new(0) };
+    // what is left to free while `release` runs on this thread
+    static RELEASING: RefCell<Option<Vec<Garbage>>> = const { RefCell::new(None) };
+}
+
+#[derive(Default)]
+struct Heap {
+    environments: Vec<Weak<RefCell<Environment>>>,
+    // the variables the VM's closures share
+    cells: Vec<Weak<Variable>>,
+    allocated: usize, // since the last collection
+    threshold: usize,
+}
+
+/// Registers a new environment, collecting cycles once enough environments
+/// have been created since the last collection.
+pub(crate) fn track(env: &Env) {
+    LIVE.with(|live| live.set(live.get() + 1));
+    let due = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.environments.push(Rc::downgrade(env));
+        heap.allocate()
+    });
+    if due {
+        collect();
+    }
+}
+
+/// Registers a new cell of the VM, as `track` does an environment.
+pub(crate) fn track_cell(cell: &Rc<Variable>) {
+    let due = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.cells.push(Rc::downgrade(cell));
+        heap.allocate()
+    });
+    if due {
+        collect();
+    }
+}
+
+impl Heap {
+    /// Counts a new object, returning whether a collection is due.
+    fn allocate(&mut self) -> bool {
+        self.allocated += 1;
+        self.allocated >= self.threshold.max(MIN_THRESHOLD)
+    }
+}
+
+pub(crate) fn untrack() {
+    // environments can outlive the counter while the thread shuts down
+    let _ = LIVE.try_with(|live| live.set(live.get() - 1));
+}
+
+/// Number of environments on this thread that have not been freed.
+#[cfg(test)]
+pub(crate) fn live_environments() -> usize {
+    LIVE.with(|live| live.get())
+}
+
+/// A value or scope that is being dropped.
+pub(crate) enum Garbage {
+    Value(Object),
+    Env(Env),
+}
+
+impl Garbage {
+    /// Whether dropping this frees an environment, closure, cell, array or
+    /// hash, which can hold a long chain of others.
+    fn frees_links(&self) -> bool {
+        match self {
+            Garbage::Env(env) => Rc::strong_count(env) == 1,
+            Garbage::Value(Object::Function(func)) => Rc::strong_count(&func.env) == 1,
+            Garbage::Value(Object::Closure(closure)) => Rc::strong_count(closure) == 1,
+            Garbage::Value(Object::Cell(cell)) => Rc::strong_count(cell) == 1,
+            Garbage::Value(Object::Array(array)) => Rc::strong_count(array) == 1,
+            Garbage::Value(Object::Hash(hash)) => Rc::strong_count(hash) == 1,
+            Garbage::Value(_) => false,
+        }
+    }
+}
+
+/// Drops what an environment, closure or cell held one link at a time. A
+/// chain of them, each captured by the next, would otherwise be freed by
+/// nested drops, one native stack frame per link, and could overflow it.
+pub(crate) fn release(garbage: impl IntoIterator<Item = Garbage>) {
+    let links: Vec<Garbage> = garbage.into_iter().filter(Garbage::frees_links).collect();
+    if links.is_empty() {
+        return;
+    }
+    let outermost = RELEASING.try_with(|releasing| {
+        let mut releasing = releasing.borrow_mut();
+        match &mut *releasing {
+            // a drop in the loop below: the loop frees these too
+            Some(pending) => {
+                pending.extend(links);
+                false
+            }
+            None => {
+                *releasing = Some(links);
+                true
+            }
+        }
+    });
+    if outermost != Ok(true) {
+        return;
+    }
+    while let Some(next) = RELEASING.with(|releasing| releasing.borrow_mut().as_mut()?.pop()) {
+        drop(next);
+    }
+    RELEASING.with(|releasing| *releasing.borrow_mut() = None);
+}
+
+/// Frees environments and cells that are only kept alive by reference
+/// cycles and returns how many were cleared.
+///
+/// This is trial deletion: any environment, array or hash with more strong
+/// references than the tracked objects account for is held from outside the
+/// heap (the evaluator's stack, the REPL, an embedder), so it and everything
+/// it reaches is live. That makes it safe to run in the middle of an
+/// evaluation without knowing the evaluator's roots.
+pub(crate) fn collect() -> usize {
+    let roots: Vec<Node> = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.environments.retain(|env| env.strong_count() > 0);
+        heap.cells.retain(|cell| cell.strong_count() > 0);
+        let environments = heap.environments.iter().filter_map(Weak::upgrade).map(Node::Env);
+        let cells = heap.cells.iter().filter_map(Weak::upgrade).map(Node::Cell);
+        environments.chain(cells).collect()
+    });
+
+    let mut graph = Graph::default();
+    for node in roots {
+        graph.insert(node);
+    }
+    graph.scan();
+    let garbage = graph.unreachable();
+
+    // Only free after every borrow taken while scanning has ended, as
+    // dropping the bindings can drop other environments.
+    let mut cleared = Vec::new();
+    let mut taken = Vec::new();
+    for node in &garbage {
+        match node {
+            Node::Env(env) => {
+                if let Ok(mut env) = env.try_borrow_mut() {
+                    cleared.push(env.clear());
+                }
+            }
+            Node::Cell(cell) => {
+                if let Ok(mut value) = cell.value.try_borrow_mut() {
+                    taken.push(value.take());
+                }
+            }
+            _ => {}
+        }
+    }
+    let freed = cleared.len() + taken.len();
+    drop(graph);
+    drop(garbage);
+    drop(cleared);
+    drop(taken);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.environments.retain(|env| env.strong_count() > 0);
+        heap.cells.retain(|cell| cell.strong_count() > 0);
+        heap.allocated = 0;
+        heap.threshold = heap.environments.len() + heap.cells.len();
+    });
+    freed
+}
+
+enum Node {
+    Env(Env),
+    Array(Rc<Vec<Object>>),
+    Hash(Rc<BTreeMap<HashKey, HashPair>>),
+    Cell(Rc<Variable>),
+    Closure(Rc<Closure>),
+}
+
+impl Node {
+    fn id(&self) -> usize {
+        match self {
+            Node::Env(env) => Rc::as_ptr(env) as *const u8 as usize,
+            Node::Array(array) => Rc::as_ptr(array) as *const u8 as usize,
+            Node::Hash(hash) => Rc::as_ptr(hash) as *const u8 as usize,
+            Node::Cell(cell) => Rc::as_ptr(cell) as *const u8 as usize,
+            Node::Closure(closure) => Rc::as_ptr(closure) as *const u8 as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Env(env) => Rc::strong_count(env),
+            Node::Array(array) => Rc::strong_count(array),
+            Node::Hash(hash) => Rc::strong_count(hash),
+            Node::Cell(cell) => Rc::strong_count(cell),
+            Node::Closure(closure) => Rc::strong_count(closure),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Graph {
+    nodes: HashMap<usize, Node>,
+    edges: HashMap<usize, Vec<usize>>,
+    // references to each node from other nodes in the graph
+    internal: HashMap<usize, usize>,
+    pending: Vec<usize>,
+}
+
+impl Graph {
+    fn insert(&mut self, node: Node) -> usize {
+        let id = node.id();
+        if let Entry::Vacant(entry) = self.nodes.entry(id) {
+            entry.insert(node);
+            self.pending.push(id);
+        }
+        id
+    }
+
+    /// Follows every reference out of the pending nodes until the graph is
+    /// closed. An environment or cell that is mutably borrowed cannot be
+    /// read, so it gets no outgoing edges and anything it references looks
+    /// external.
+    fn scan(&mut self) {
+        while let Some(id) = self.pending.pop() {
+            let mut children = Vec::new();
+            match &self.nodes[&id] {
+                Node::Env(env) => {
+                    if let Ok(env) = env.try_borrow() {
+                        env.values().for_each(|value| references(value, &mut children));
+                        if let Some(outer) = env.outer() {
+                            children.push(Node::Env(Rc::clone(outer)));
+                        }
+                    }
+                }
+                Node::Array(array) => {
+                    array.iter().for_each(|value| references(value, &mut children));
+                }
+                Node::Hash(hash) => hash.values().for_each(|pair| {
+                    references(&pair.key, &mut children);
+                    references(&pair.value, &mut children);
+                }),
+                Node::Cell(cell) => {
+                    if let Ok(Some(value)) = cell.value.try_borrow().as_deref() {
+                        references(value, &mut children);
+                    }
+                }
+                Node::Closure(closure) => {
+                    closure.free.iter().for_each(|value| references(value, &mut children));
+                }
+            }
+
+            let mut edges = Vec::with_capacity(children.len());
+            for child in children {
+                let child = self.insert(child);
+                *self.internal.entry(child).or_insert(0) += 1;
+                edges.push(child);
+            }
+            self.edges.insert(id, edges);
+        }
+    }
+
+    /// Environments and cells not reachable from any externally referenced
+    /// node.
+    fn unreachable(&self) -> Vec<Node> {
+        let mut reachable = HashSet::new();
+        let mut stack: Vec<usize> = self
+            .nodes
+            .iter()
+            .filter(|(id, node)| {
+                // one reference is the graph's own
+                let internal = self.internal.get(id).copied().unwrap_or(0);
+                node.strong_count() - 1 > internal
+            })
+            .map(|(id, _)| *id)
+            .collect();
+
+        while let Some(id) = stack.pop() {
+            if !reachable.insert(id) {
+                continue;
+            }
+            if let Some(edges) = self.edges.get(&id) {
+                stack.extend(edges.iter().filter(|child| !reachable.contains(child)));
+            }
+        }
+
+        self.nodes
+            .iter()
+            .filter(|(id, _)| !reachable.contains(id))
+            .filter_map(|(_, node)| match node {
+                Node::Env(env) => Some(Node::Env(Rc::clone(env))),
+                Node::Cell(cell) => Some(Node::Cell(Rc::clone(cell))),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Collects the heap objects `value` refers to directly.
+fn references(value: &Object, out: &mut Vec<Node>) {
+    match value {
+        Object::Function(func) => out.push(Node::Env(Rc::clone(&func.env))),
+        Object::Array(array) => out.push(Node::Array(Rc::clone(array))),
+        Object::Hash(hash) => out.push(Node::Hash(Rc::clone(hash))),
+        Object::Cell(cell) => out.push(Node::Cell(Rc::clone(cell))),
+        Object::Closure(closure) => out.push(Node::Closure(Rc::clone(closure))),
+        Object::ReturnValue(value) => references(value, out),
+        Object::TailCall(call) => {
+            references(&call.function, out);
+            call.args.iter().for_each(|arg| references(arg, out));
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collect, live_environments};
+    use crate::environment::Environment;
+    use crate::environment::Env;
+    use crate::compiler::compile;
+    use crate::engine::Engine;
+    use crate::evaluator::Evaluator;
+    use crate::lexer::Lexer;
+    use crate::object::Object;
+    use crate::parser::Parser;
+
+    fn parse(input: &str) -> crate::ast::Program {
+        let mut p = Parser::new(Lexer::new(input.into()));
+        let program = p.parse_program().unwrap();
+        assert!(p.errors().is_empty(), "parser errors: {:?}", p.errors());
+        program
+    }
+
+    fn eval_program(program: &crate::ast::Program, env: &Env) -> Object {
+        Evaluator::new().eval_program(program, env)
+    }
+
+    #[test]
+    fn test_collects_self_referencing_closures() {
+        let before = live_environments();
+        {
+            let env = Environment::new();
+            let program = parse("let f = fn(n) { if (n == 0) { 0 } else { f(n - 1) } }; f(3)");
+            assert_eq!(eval_program(&program, &env), Object::Integer(0));
+        }
+        // the global scope holds `f`, which captures the global scope
+        assert!(live_environments() > before);
+        collect();
+        assert_eq!(live_environments(), before);
+    }
+
+    #[test]
+    fn test_keeps_reachable_environments() {
+        let env = Environment::new();
+        let setup = parse(
+            r#"
+let newAdder = fn(x) { fn(y) { x + y } };
+let adders = [newAdder(1), newAdder(2)];
+let table = {"three": newAdder(3)};
+let counter = fn() { let self = fn() { self }; self };
+let kept = counter();
+"#,
+        );
+        eval_program(&setup, &env);
+        collect();
+        let program = parse(r#"adders[0](10) + adders[1](10) + table["three"](10)"#);
+        assert_eq!(eval_program(&program, &env), Object::Integer(36));
+        assert_eq!(eval_program(&parse("kept()()()"), &env).type_name(), "FUNCTION");
+
+        // values held only by the host are roots too
+        let adder = eval_program(&parse("[newAdder(5)]"), &env);
+        eval_program(&parse("let adders = 0; let table = 0;"), &env);
+        collect();
+        let call = parse("f(1)");
+        let scope = Environment::new_enclosed(&env);
+        if let Object::Array(elements) = adder {
+            scope.borrow_mut().set("f", elements[0].clone());
+        }
+        assert_eq!(eval_program(&call, &scope), Object::Integer(6));
+    }
+
+    #[test]
+    fn test_recursive_closure_a_million_times() {
+        let env = Environment::new();
+        let setup = parse(
+            r#"
+let run = fn() {
+  let countdown = fn(n) { if (n == 0) { 0 } else { countdown(n - 1) } };
+  countdown(1)
+};
+"#,
+        );
+        eval_program(&setup, &env);
+        let call = parse("run()");
+
+        let baseline = live_environments();
+        let mut peak = 0;
+        for _ in 0..1_000_000 {
+            assert_eq!(eval_program(&call, &env), Object::Integer(0));
+            peak = peak.max(live_environments());
+        }
+        // each call leaves one cycle behind until the next collection
+        assert!(peak - baseline < 4 * 1024, "peak={}", peak);
+    }
+
+    #[test]
+    fn test_cells_in_cycles_are_freed() {
+        let source = "
+let f = fn() {
+  let even = fn(n) { if (n == 0) { true } else { odd(n - 1) } };
+  let odd = fn(n) { if (n == 0) { false } else { even(n - 1) } };
+  [even, odd]
+};
+let kept = f();
+f();
+kept";
+        let bytecode = compile(source).unwrap();
+        let kept = Engine::new().run(&bytecode).unwrap();
+        // the two cells of the second call, not those of the value returned
+        assert_eq!(collect(), 2);
+        drop(kept);
+        assert_eq!(collect(), 2);
+        assert_eq!(collect(), 0);
+    }
+
+    #[test]
+    fn test_long_chains_are_freed_without_recursion() {
+        // each closure keeps the one before, too many to free by nested drops
+        // on the stack a test runs on
+        let source = "
+let build = fn(n, acc) { if (n == 0) { 0 } else { build(n - 1, fn() { acc }) } };
+let keep = fn(n, acc) { if (n == 0) { acc } else { keep(n - 1, fn() { acc }) } };
+build(100000, 0) + len([keep(100000, 0)])";
+        let engine = Engine::new();
+        assert_eq!(engine.eval(source).unwrap(), Object::Integer(1));
+        assert_eq!(engine.run(&compile(source).unwrap()).unwrap(), Object::Integer(1));
+    }
+}
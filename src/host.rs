@@ -0,0 +1,303 @@
+use crate::convert::FromMonkey;
+use crate::object::{ErrorKind, Object, RuntimeError};
+use crate::token::Span;
+use num_traits::ToPrimitive;
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
+
+pub(crate) type HostFn = dyn Fn(&Arguments) -> Result<Object, HostError>;
+
+/// A Rust closure registered by the embedder under a global name.
+#[derive(Clone)]
+pub struct HostFunction {
+    pub(crate) name: Rc<str>,
+    func: Rc<HostFn>,
+}
+
+impl HostFunction {
+    pub(crate) fn new<F>(name: &str, func: F) -> Self
+    where
+        F: Fn(&Arguments) -> Result<Object, HostError> + 'static,
+    {
+        HostFunction {
+            name: name.into(),
+            func: Rc::new(func),
+        }
+    }
+
+    /// Runs the closure, turning a `HostError` into a runtime error at the
+    /// call site.
+    pub(crate) fn call(&self, args: &[Object], span: Span) -> Object {
+        let args = Arguments {
+            name: &self.name,
+            values: args,
+        };
+        match (self.func)(&args) {
+            Ok(value) => value,
+            Err(err) => Object::Error(Box::new(RuntimeError::new(err.kind, err.message, span))),
+        }
+    }
+}
+
+impl PartialEq for HostFunction {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.func, &other.func)
+    }
+}
+
+impl Debug for HostFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HostFunction({})", self.name)
+    }
+}
+
+/// The arguments of a host function call, with accessors that check their
+/// types and report mismatches in the function's name.
+pub struct Arguments<'a> {
+    name: &'a str,
+    values: &'a [Object],
+}
+
+impl<'a> Arguments<'a> {
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn values(&self) -> &'a [Object] {
+        self.values
+    }
+
+    /// Fails unless exactly `want` arguments were passed.
+    pub fn expect(&self, want: usize) -> Result<(), HostError> {
+        if self.values.len() == want {
+            return Ok(());
+        }
+        Err(HostError::with_kind(
+            ErrorKind::WrongArguments,
+            format!(
+                "wrong number of arguments to `{}`: want={}, got={}",
+                self.name,
+                want,
+                self.values.len()
+            ),
+        ))
+    }
+
+    pub fn get(&self, index: usize) -> Result<&'a Object, HostError> {
+        self.values.get(index).ok_or_else(|| {
+            HostError::with_kind(
+                ErrorKind::WrongArguments,
+                format!("missing argument {} to `{}`", index + 1, self.name),
+            )
+        })
+    }
+
+    /// Converts argument `index` to any type that implements `FromMonkey`.
+    pub fn get_as<T: FromMonkey>(&self, index: usize) -> Result<T, HostError> {
+        T::from_monkey(self.get(index)?).map_err(|err| self.mismatch(index, err.expected, err.got))
+    }
+
+    /// Argument `index` as an i64, which a big integer is only if it fits.
+    pub fn integer(&self, index: usize) -> Result<i64, HostError> {
+        match self.get(index)? {
+            Object::Integer(value) => Ok(*value),
+            Object::BigInteger(value) => value.to_i64().ok_or_else(|| {
+                HostError::with_kind(
+                    ErrorKind::IntegerOverflow,
+                    format!("argument {} to `{}` does not fit in 64 bits", index + 1, self.name),
+                )
+            }),
+            arg => Err(self.mismatch(index, "INTEGER", arg.type_name())),
+        }
+    }
+
+    pub fn boolean(&self, index: usize) -> Result<bool, HostError> {
+        match self.get(index)? {
+            Object::Boolean(value) => Ok(*value),
+            arg => Err(self.mismatch(index, "BOOLEAN", arg.type_name())),
+        }
+    }
+
+    pub fn string(&self, index: usize) -> Result<&'a str, HostError> {
+        match self.get(index)? {
+            Object::String(value) => Ok(value),
+            arg => Err(self.mismatch(index, "STRING", arg.type_name())),
+        }
+    }
+
+    pub fn array(&self, index: usize) -> Result<&'a [Object], HostError> {
+        match self.get(index)? {
+            Object::Array(elements) => Ok(elements),
+            arg => Err(self.mismatch(index, "ARRAY", arg.type_name())),
+        }
+    }
+
+    fn mismatch(&self, index: usize, want: &str, got: &str) -> HostError {
+        HostError::with_kind(
+            ErrorKind::InvalidArgument,
+            format!(
+                "argument {} to `{}` must be {}, got {}",
+                index + 1,
+                self.name,
+                want,
+                got
+            ),
+        )
+    }
+}
+
+/// Why a host function failed. Any `std::error::Error` converts into one, so
+/// closures can use `?` on the results of the Rust code they call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl HostError {
+    pub fn new<P: Into<String>>(message: P) -> Self {
+        HostError::with_kind(ErrorKind::Host, message)
+    }
+
+    pub fn with_kind<P: Into<String>>(kind: ErrorKind, message: P) -> Self {
+        HostError {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for HostError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+// HostError is deliberately not an Error itself, which would make this
+// conflict with the reflexive `From<T> for T`.
+impl<E: std::error::Error> From<E> for HostError {
+    fn from(err: E) -> Self {
+        HostError::new(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HostError, HostFunction};
+    use crate::environment::Environment;
+    use crate::evaluator::{Evaluator, IntegerMode};
+    use crate::lexer::Lexer;
+    use crate::object::{ErrorKind, Object};
+    use crate::parser::Parser;
+    use crate::token::Span;
+    use num_bigint::BigInt;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn eval(evaluator: &Evaluator, input: &str) -> Object {
+        let mut p = Parser::new(Lexer::new(input.into()));
+        let program = p.parse_program().unwrap();
+        assert!(p.errors().is_empty(), "parser errors: {:?}", p.errors());
+        evaluator.eval_program(&program, &Environment::new())
+    }
+
+    #[test]
+    fn test_registered_functions() {
+        let calls = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&calls);
+
+        let mut evaluator = Evaluator::new();
+        evaluator.register("add", |args| {
+            args.expect(2)?;
+            Ok(Object::Integer(args.integer(0)? + args.integer(1)?))
+        });
+        evaluator.register("count", move |_| {
+            counter.set(counter.get() + 1);
+            Ok(Object::Integer(counter.get()))
+        });
+        evaluator.register("shout", |args| {
+            Ok(Object::String(args.string(0)?.to_uppercase().into()))
+        });
+        evaluator.register("len", |_| Ok(Object::Integer(-1)));
+
+        let tests = vec![
+            ("add(1, 2)", Object::Integer(3)),
+            ("let twice = fn(f) { f() + f() }; twice(count)", Object::Integer(3)),
+            (r#"shout("hi")"#, Object::String("HI".into())),
+            ("let add = fn(a, b) { a - b }; add(1, 2)", Object::Integer(-1)),
+            // registered names shadow builtins
+            ("len([1])", Object::Integer(-1)),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(eval(&evaluator, input), expected, "input: {}", input);
+        }
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn test_host_errors() {
+        let mut evaluator = Evaluator::new();
+        evaluator.register("add", |args| {
+            args.expect(2)?;
+            Ok(Object::Integer(args.integer(0)? + args.integer(1)?))
+        });
+        evaluator.register("parse", |args| {
+            Ok(Object::Integer(args.string(0)?.parse::<i64>()?))
+        });
+        evaluator.register("fail", |_| Err(HostError::new("service unavailable")));
+
+        let tests = vec![
+            (
+                "add(1)",
+                ErrorKind::WrongArguments,
+                "wrong number of arguments to `add`: want=2, got=1",
+            ),
+            (
+                r#"add(1, "2")"#,
+                ErrorKind::InvalidArgument,
+                "argument 2 to `add` must be INTEGER, got STRING",
+            ),
+            ("parse()", ErrorKind::WrongArguments, "missing argument 1 to `parse`"),
+            (r#"parse("x")"#, ErrorKind::Host, "invalid digit found in string"),
+            ("\n  fail()", ErrorKind::Host, "service unavailable"),
+        ];
+        for (input, kind, message) in tests {
+            match eval(&evaluator, input) {
+                Object::Error(err) => {
+                    assert_eq!(err.kind, kind, "input: {}", input);
+                    assert_eq!(err.message, message, "input: {}", input);
+                }
+                obj => panic!("no error object returned for {}. got={:?}", input, obj),
+            }
+        }
+        match eval(&evaluator, "\n  fail()") {
+            Object::Error(err) => assert_eq!((err.span.line, err.span.column), (2, 3)),
+            obj => panic!("no error object returned. got={:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_big_integer_arguments() {
+        let add = |args: &super::Arguments| {
+            Ok(Object::Integer(args.integer(0)? + args.integer(1)?))
+        };
+        let mut evaluator = Evaluator::new().with_integer_mode(IntegerMode::Arbitrary);
+        evaluator.register("add", add);
+        match eval(&evaluator, "add(1, 9223372036854775807 + 1)") {
+            Object::Error(err) => {
+                assert_eq!(err.kind, ErrorKind::IntegerOverflow);
+                assert_eq!(err.message, "argument 2 to `add` does not fit in 64 bits");
+            }
+            obj => panic!("no error object returned. got={:?}", obj),
+        }
+
+        // arithmetic makes big integers only out of range, but a host need not
+        let two = Object::BigInteger(Rc::new(BigInt::from(2)));
+        let add = HostFunction::new("add", add);
+        assert_eq!(add.call(&[Object::Integer(1), two], Span::default()), Object::Integer(3));
+    }
+}
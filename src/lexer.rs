@@ -1,4 +1,4 @@
-use crate::token::{lookup_ident, Token, TokenType};
+use crate::token::{lookup_ident, Span, Token, TokenType};
 
 #[derive(Debug)]
 pub(crate) struct Lexer {
@@ -8,6 +8,9 @@ pub(crate) struct Lexer {
     read_position: usize,
     // current reading position in input (after current char)
     ch: u8, // current char under examination
+    line: usize,
+    // offset in input where the current line starts
+    line_start: usize,
 }
 
 impl Lexer {
@@ -17,6 +20,8 @@ impl Lexer {
             position: 0,
             read_position: 0,
             ch: 0,
+            line: 1,
+            line_start: 0,
         };
         l.read_char();
         l
@@ -40,9 +45,12 @@ impl Lexer {
     }
 
     pub(crate) fn next_token(&mut self) -> Token {
-        let tok: Token;
         self.skip_whitespace();
-        tok = match self.ch {
+        let span = Span {
+            line: self.line,
+            column: self.position - self.line_start + 1,
+        };
+        let mut tok = match self.ch {
             b'=' if self.peek_char() == b'=' => {
                 self.read_char();
                 Token::new(TokenType::EQ, "==")
@@ -53,10 +61,19 @@ impl Lexer {
             b')' => Token::new(TokenType::RPAREN, ")"),
             b'{' => Token::new(TokenType::LBRACE, "{"),
             b'}' => Token::new(TokenType::RBRACE, "}"),
+            b'[' => Token::new(TokenType::LBRACKET, "["),
+            b']' => Token::new(TokenType::RBRACKET, "]"),
+            b':' => Token::new(TokenType::COLON, ":"),
             b',' => Token::new(TokenType::COMMA, ","),
             b'+' => Token::new(TokenType::PLUS, "+"),
             b'-' => Token::new(TokenType::MINUS, "-"),
             b'*' => Token::new(TokenType::ASTERISK, "*"),
+            b'/' if self.peek_char() == b'/' => {
+                let comment = self.read_comment();
+                self.position -= 1;
+                self.read_position -= 1;
+                Token::new(TokenType::COMMENT, comment)
+            }
             b'/' => Token::new(TokenType::SLASH, "/"),
             b'!' if self.peek_char() == b'=' => {
                 self.read_char();
@@ -78,12 +95,21 @@ impl Lexer {
                 self.read_position -= 1;
                 Token::new(TokenType::INT, int)
             }
-            _ => Token::new(
-                TokenType::ILLEGAL,
-                std::str::from_utf8(&[self.ch]).unwrap().to_string(),
-            ),
+            b'"' => match self.read_string() {
+                Some(string) => Token::new(TokenType::STRING, string),
+                None => Token::new(TokenType::ILLEGAL, "unterminated string"),
+            },
+            _ => {
+                // keep multi-byte characters whole
+                let ch = self.input[self.position..].chars().next().unwrap();
+                for _ in 1..ch.len_utf8() {
+                    self.read_char();
+                }
+                Token::new(TokenType::ILLEGAL, ch.to_string())
+            }
         };
         self.read_char();
+        tok.span = span;
         tok
     }
 
@@ -92,9 +118,46 @@ impl Lexer {
         while Lexer::is_letter(self.ch) {
             self.read_char();
         }
-        std::str::from_utf8(&self.input.as_bytes()[position..self.position])
-            .unwrap()
-            .to_string()
+        self.input[position..self.position].to_string()
+    }
+
+    /// Reads up to the closing quote, leaving `ch` on it. Supports the
+    /// escapes `\"`, `\\`, `\n` and `\t`; returns `None` at end of input.
+    fn read_string(&mut self) -> Option<String> {
+        let mut bytes = Vec::new();
+        loop {
+            self.read_char();
+            match self.ch {
+                b'"' => break,
+                0 => return None,
+                b'\\' => {
+                    self.read_char();
+                    match self.ch {
+                        b'n' => bytes.push(b'\n'),
+                        b't' => bytes.push(b'\t'),
+                        b'"' | b'\\' => bytes.push(self.ch),
+                        0 => return None,
+                        other => bytes.extend_from_slice(&[b'\\', other]),
+                    }
+                }
+                b'\n' => {
+                    self.line += 1;
+                    self.line_start = self.read_position;
+                    bytes.push(b'\n');
+                }
+                other => bytes.push(other),
+            }
+        }
+        // only ever split at ASCII bytes, so the input's UTF-8 is intact
+        Some(String::from_utf8(bytes).unwrap())
+    }
+
+    fn read_comment(&mut self) -> String {
+        let position = self.position;
+        while self.ch != b'\n' && self.ch != 0 {
+            self.read_char();
+        }
+        self.input[position..self.position].trim_end().to_string()
     }
 
     fn read_number(&mut self) -> String {
@@ -102,32 +165,29 @@ impl Lexer {
         while Lexer::is_digit(self.ch) {
             self.read_char();
         }
-        std::str::from_utf8(&self.input.as_bytes()[position..self.position])
-            .unwrap()
-            .to_string()
+        self.input[position..self.position].to_string()
     }
 
     fn skip_whitespace(&mut self) {
         while self.ch == b' ' || self.ch == b'\t' || self.ch == b'\n' || self.ch == b'\r' {
+            if self.ch == b'\n' {
+                self.line += 1;
+                self.line_start = self.read_position;
+            }
             self.read_char()
         }
     }
 
     fn is_letter(ch: u8) -> bool {
-        match ch {
-            b'a'..=b'z' | b'A'..=b'Z' | b'_' => true,
-            _ => false,
-        }
+        matches!(ch, b'a'..=b'z' | b'A'..=b'Z' | b'_')
     }
 
     fn is_digit(ch: u8) -> bool {
-        match ch {
-            b'0'..=b'9' => true,
-            _ => false,
-        }
+        ch.is_ascii_digit()
     }
 }
 
+#[cfg(test)]
 mod tests {
     use super::Lexer;
     use crate::token::TokenType;
@@ -167,6 +227,11 @@ if (5 < 10) {
 
 10 == 10;
 10 != 9;
+[1, 2];
+{"foo": "bar"}
+"foobar"
+"foo bar"
+"say \"hé\"\n"
 "#;
         let tests = vec![
             ExpectedToken::new(TokenType::LET, "let"),
@@ -242,6 +307,20 @@ if (5 < 10) {
             ExpectedToken::new(TokenType::NOT_EQ, "!="),
             ExpectedToken::new(TokenType::INT, "9"),
             ExpectedToken::new(TokenType::SEMICOLON, ";"),
+            ExpectedToken::new(TokenType::LBRACKET, "["),
+            ExpectedToken::new(TokenType::INT, "1"),
+            ExpectedToken::new(TokenType::COMMA, ","),
+            ExpectedToken::new(TokenType::INT, "2"),
+            ExpectedToken::new(TokenType::RBRACKET, "]"),
+            ExpectedToken::new(TokenType::SEMICOLON, ";"),
+            ExpectedToken::new(TokenType::LBRACE, "{"),
+            ExpectedToken::new(TokenType::STRING, "foo"),
+            ExpectedToken::new(TokenType::COLON, ":"),
+            ExpectedToken::new(TokenType::STRING, "bar"),
+            ExpectedToken::new(TokenType::RBRACE, "}"),
+            ExpectedToken::new(TokenType::STRING, "foobar"),
+            ExpectedToken::new(TokenType::STRING, "foo bar"),
+            ExpectedToken::new(TokenType::STRING, "say \"hé\"\n"),
             ExpectedToken::new(TokenType::EOF, ""),
         ];
 
@@ -261,4 +340,95 @@ if (5 < 10) {
             )
         }
     }
+
+    #[test]
+    fn test_comments() {
+        let input = "let x = 5; // five\n// a whole line\nx / 2;";
+        let tests = vec![
+            ExpectedToken::new(TokenType::LET, "let"),
+            ExpectedToken::new(TokenType::IDENT, "x"),
+            ExpectedToken::new(TokenType::ASSIGN, "="),
+            ExpectedToken::new(TokenType::INT, "5"),
+            ExpectedToken::new(TokenType::SEMICOLON, ";"),
+            ExpectedToken::new(TokenType::COMMENT, "// five"),
+            ExpectedToken::new(TokenType::COMMENT, "// a whole line"),
+            ExpectedToken::new(TokenType::IDENT, "x"),
+            ExpectedToken::new(TokenType::SLASH, "/"),
+            ExpectedToken::new(TokenType::INT, "2"),
+            ExpectedToken::new(TokenType::SEMICOLON, ";"),
+            ExpectedToken::new(TokenType::EOF, ""),
+        ];
+
+        let mut l = Lexer::new(input.to_string());
+
+        for (i, tt) in tests.iter().enumerate() {
+            let tok = l.next_token();
+            assert_eq!(
+                tok.t_type, tt.expected_type,
+                "tests[{}] - tokentype wrong. expected={}, got {}",
+                i, tt.expected_type, tok.t_type,
+            );
+            assert_eq!(
+                tok.literal, tt.expected_literal,
+                "tests[{}] - literal wrong. expected={}, got={}",
+                i, tt.expected_literal, tok.literal,
+            )
+        }
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let input = "let x = 5;\n  if (x) {\n\treturn x;\n}";
+        let expected = vec![
+            ("let", 1, 1),
+            ("x", 1, 5),
+            ("=", 1, 7),
+            ("5", 1, 9),
+            (";", 1, 10),
+            ("if", 2, 3),
+            ("(", 2, 6),
+            ("x", 2, 7),
+            (")", 2, 8),
+            ("{", 2, 10),
+            ("return", 3, 2),
+            ("x", 3, 9),
+            (";", 3, 10),
+            ("}", 4, 1),
+        ];
+
+        let mut l = Lexer::new(input.to_string());
+
+        for (i, (literal, line, column)) in expected.into_iter().enumerate() {
+            let tok = l.next_token();
+            assert_eq!(tok.literal, literal, "tests[{}] - literal wrong", i);
+            assert_eq!(
+                (tok.span.line, tok.span.column),
+                (line, column),
+                "tests[{}] - span wrong for {}",
+                i,
+                literal,
+            );
+        }
+    }
+
+    #[test]
+    fn test_illegal_and_unterminated() {
+        let mut l = Lexer::new("é \"ab".to_string());
+        let tok = l.next_token();
+        assert_eq!((tok.t_type, tok.literal.as_str()), (TokenType::ILLEGAL, "é"));
+        let tok = l.next_token();
+        assert_eq!(
+            (tok.t_type, tok.literal.as_str()),
+            (TokenType::ILLEGAL, "unterminated string")
+        );
+        assert_eq!(l.next_token().t_type, TokenType::EOF);
+    }
+
+    #[test]
+    fn test_multiline_string_spans() {
+        let mut l = Lexer::new("\"a\nb\" x".to_string());
+        assert_eq!(l.next_token().literal, "a\nb");
+        let tok = l.next_token();
+        assert_eq!((tok.span.line, tok.span.column), (2, 4));
+    }
 }
@@ -0,0 +1,52 @@
+//! An interpreter for the Monkey programming language.
+//!
+//! ```
+//! use monkey_rs::{Engine, Object};
+//!
+//! let mut engine = Engine::new();
+//! engine.register("twice", |args| Ok(Object::Integer(args.integer(0)? * 2)));
+//! engine.eval("let add = fn(a, b) { a + b };").unwrap();
+//! assert_eq!(engine.eval("add(twice(20), 2)").unwrap(), Object::Integer(42));
+//! ```
+mod ast;
+mod builtins;
+mod bytecode_file;
+mod code;
+mod compiler;
+mod convert;
+mod coverage;
+mod debug;
+mod disassembler;
+mod engine;
+mod environment;
+mod evaluator;
+mod formatter;
+mod gc;
+mod host;
+mod lexer;
+mod object;
+mod parser;
+mod profiler;
+mod symbol_table;
+mod token;
+mod trace;
+mod verifier;
+mod vm;
+
+pub use bytecode_file::LoadError;
+pub use compiler::{compile, Bytecode, CompileError};
+pub use convert::{ConversionError, FromMonkey, ToMonkey, ToMonkeyKey};
+pub use coverage::{Branch, Coverage};
+pub use debug::{Debugger, Pause, Resume};
+pub use engine::{Engine, Error};
+pub use evaluator::{CancelHandle, IntegerMode, Limits, MAX_CALL_DEPTH, STACK_SIZE};
+pub use formatter::format;
+pub use host::{Arguments, HostError, HostFunction};
+pub use num_bigint::BigInt;
+pub use object::{
+    Backtrace, Builtin, ErrorKind, Function, HashKey, HashPair, Object, RuntimeError, TraceFrame,
+};
+pub use parser::ParserError;
+pub use profiler::{FunctionProfile, Profile};
+pub use token::Span;
+pub use trace::{Observer, Tracer};
@@ -1,12 +1,411 @@
-pub(crate) mod ast;
-pub(crate) mod lexer;
-pub(crate) mod parser;
-pub(crate) mod repl;
-pub(crate) mod token;
-use std::{env, io};
+mod console;
+mod dap;
+mod repl;
+use monkey_rs::{
+    compile, format, Bytecode, Engine, IntegerMode, Limits, LoadError, Tracer, STACK_SIZE,
+};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, fs, io, process, thread};
 fn main() {
+    // calls nest on the native stack, so give them room for the deepest
+    // the evaluator allows
+    let monkey = thread::Builder::new().stack_size(STACK_SIZE).spawn(monkey).unwrap();
+    if monkey.join().is_err() {
+        process::exit(101);
+    }
+}
+
+fn monkey() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("fmt") => process::exit(fmt(&args[1..])),
+        Some("debug") => process::exit(debug(&args[1..])),
+        Some("dap") => process::exit(dap(&args[1..])),
+        Some("profile") => process::exit(profile(&args[1..])),
+        Some("coverage") => process::exit(coverage(&args[1..])),
+        Some("run") => process::exit(run(&args[1..])),
+        Some("build") => process::exit(build(&args[1..])),
+        Some("disasm") => process::exit(disasm(&args[1..])),
+        _ => {}
+    }
+
+    let engine = match engine(&args) {
+        Ok(engine) => engine,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    };
+    // Ctrl-C stops the line being evaluated rather than the REPL
+    let cancel = engine.cancel_handle();
+    ctrlc::set_handler(move || cancel.cancel()).unwrap();
+
     let user = env::var_os("USER").unwrap().into_string().unwrap();
     println!("Hello {}! This is the Monkey programming language!", user);
     println!("Feel free to type in commands");
-    repl::start(io::stdin(), io::stdout());
+    repl::start(io::stdin(), io::stdout(), engine);
+}
+
+/// Builds the REPL's engine from the command line.
+///
+/// `--big-integers` trades overflow errors for arbitrary precision,
+/// `--trace` prints each expression evaluated and its value to stderr, and
+/// `--max-steps=N`, `--max-depth=N`, `--max-memory=BYTES` and
+/// `--timeout=MS` limit each line entered.
+fn engine(args: &[String]) -> Result<Engine, String> {
+    let mut engine = Engine::new().with_stack_size(STACK_SIZE);
+    if args.iter().any(|arg| arg == "--big-integers") {
+        engine = engine.with_integer_mode(IntegerMode::Arbitrary);
+    }
+    if args.iter().any(|arg| arg == "--trace") {
+        engine = engine.with_observer(Tracer::new(io::stderr()));
+    }
+
+    let mut limits = Limits::default();
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some(option) => option,
+            None => continue,
+        };
+        let parse = || {
+            value
+                .parse::<usize>()
+                .map_err(|_| format!("{}: expected a number, got {:?}", name, value))
+        };
+        match name {
+            "--max-steps" => limits.steps = Some(parse()? as u64),
+            "--max-depth" => limits.call_depth = Some(parse()?),
+            "--max-memory" => limits.memory = Some(parse()?),
+            "--timeout" => engine = engine.with_timeout(Duration::from_millis(parse()? as u64)),
+            _ => {}
+        }
+    }
+    Ok(engine.with_limits(limits))
+}
+
+/// `monkey debug [OPTIONS] FILE`
+///
+/// Runs the file under a debugger that stops before its first statement
+/// and reads commands from stdin; `help` lists them. Takes the same options
+/// as the REPL.
+fn debug(args: &[String]) -> i32 {
+    let (engine, source) = match script(args, "debug") {
+        Ok(script) => script,
+        Err(status) => return status,
+    };
+    let console = console::Console::new(io::stdin().lock(), io::stdout(), &source);
+    match engine.with_debugger(console).eval(&source) {
+        Ok(value) => {
+            println!("{}", value);
+            0
+        }
+        Err(err) => {
+            repl::report(&mut io::stdout(), &err);
+            1
+        }
+    }
+}
+
+/// `monkey run [OPTIONS] FILE`
+///
+/// Runs the file on the virtual machine, compiling it first unless it is
+/// bytecode from `monkey build`. Takes the same options as the REPL but
+/// `--trace`, as the VM does not evaluate expressions one by one.
+fn run(args: &[String]) -> i32 {
+    if args.iter().any(|arg| arg == "--trace") {
+        eprintln!("--trace: not supported by monkey run");
+        return 2;
+    }
+    let (engine, file) = match script_file(args, "run") {
+        Ok(script) => script,
+        Err(status) => return status,
+    };
+    let (bytecode, _) = match load(file) {
+        Ok(program) => program,
+        Err(status) => return status,
+    };
+    // Ctrl-C stops the program with an error and its backtrace
+    let cancel = engine.cancel_handle();
+    ctrlc::set_handler(move || cancel.cancel()).unwrap();
+    match engine.run(&bytecode) {
+        Ok(_) => 0,
+        Err(err) => {
+            repl::report(&mut io::stderr(), &err);
+            1
+        }
+    }
+}
+
+/// `monkey build [--out=OUT] FILE`
+///
+/// Compiles the file and writes its bytecode to OUT, by default the file
+/// with the extension `.mbc`, for `monkey run` to run without compiling.
+fn build(args: &[String]) -> i32 {
+    let (_, source) = match script(args, "build") {
+        Ok(script) => script,
+        Err(status) => return status,
+    };
+    let bytecode = match compile(&source) {
+        Ok(bytecode) => bytecode,
+        Err(err) => {
+            repl::report(&mut io::stderr(), &err);
+            return 1;
+        }
+    };
+    let out = match args.iter().find_map(|arg| arg.strip_prefix("--out=")) {
+        Some(out) => PathBuf::from(out),
+        None => {
+            let file = args.iter().find(|arg| !arg.starts_with("--")).unwrap();
+            Path::new(file).with_extension("mbc")
+        }
+    };
+    if let Err(err) = fs::write(&out, bytecode.to_bytes()) {
+        eprintln!("{}: {}", out.display(), err);
+        return 2;
+    }
+    0
+}
+
+/// `monkey disasm FILE`
+///
+/// Prints the bytecode of the file, compiling it first unless it is
+/// bytecode already: the instructions of the program and of each function,
+/// next to the lines of source they came from when there is source.
+fn disasm(args: &[String]) -> i32 {
+    let (_, file) = match script_file(args, "disasm") {
+        Ok(script) => script,
+        Err(status) => return status,
+    };
+    match load(file) {
+        Ok((bytecode, source)) => {
+            print!("{}", bytecode.disassemble(source.as_deref()));
+            0
+        }
+        Err(status) => status,
+    }
+}
+
+/// The bytecode in `file`, and its source if it had to be compiled, or the
+/// exit code after reporting why there is none.
+fn load(file: &str) -> Result<(Bytecode, Option<String>), i32> {
+    let bytes = match fs::read(file) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("{}: {}", file, err);
+            return Err(2);
+        }
+    };
+    match Bytecode::from_bytes(&bytes) {
+        Ok(bytecode) => return Ok((bytecode, None)),
+        Err(LoadError::NotBytecode) => {}
+        Err(err) => {
+            eprintln!("{}: {}", file, err);
+            return Err(1);
+        }
+    }
+    let source = match String::from_utf8(bytes) {
+        Ok(source) => source,
+        Err(_) => {
+            eprintln!("{}: neither source nor bytecode", file);
+            return Err(2);
+        }
+    };
+    match compile(&source) {
+        Ok(bytecode) => Ok((bytecode, Some(source))),
+        Err(err) => {
+            repl::report(&mut io::stderr(), &err);
+            Err(1)
+        }
+    }
+}
+
+/// `monkey profile [OPTIONS] [--collapsed=OUT] FILE`
+///
+/// Runs the file with the profiler on and prints a report of the time spent
+/// in each function to stderr. `--collapsed` also writes the call stacks
+/// in the collapsed format flame graph tools read.
+fn profile(args: &[String]) -> i32 {
+    let (engine, source) = match script(args, "profile") {
+        Ok(script) => script,
+        Err(status) => return status,
+    };
+    let engine = engine.with_profiler();
+    let status = match engine.eval(&source) {
+        Ok(_) => 0,
+        Err(err) => {
+            repl::report(&mut io::stderr(), &err);
+            1
+        }
+    };
+    let profile = engine.profile().unwrap();
+    eprint!("{}", profile.report());
+    if let Some(out) = args.iter().find_map(|arg| arg.strip_prefix("--collapsed=")) {
+        if let Err(err) = fs::write(out, profile.collapsed()) {
+            eprintln!("{}: {}", out, err);
+            return 2;
+        }
+    }
+    status
+}
+
+/// `monkey coverage [OPTIONS] [--lcov=OUT] FILE...`
+///
+/// Runs each file in an engine of its own and writes which of its lines
+/// and branches ran to OUT, `lcov.info` by default, in the lcov format.
+/// A summary for each file goes to stderr.
+fn coverage(args: &[String]) -> i32 {
+    let files: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    if files.is_empty() {
+        eprintln!("usage: monkey coverage [OPTIONS] [--lcov=OUT] FILE...");
+        return 2;
+    }
+    let out = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--lcov="))
+        .unwrap_or("lcov.info");
+
+    let mut status = 0;
+    let mut lcov = String::new();
+    for file in files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("{}: {}", file, err);
+                return 2;
+            }
+        };
+        let engine = match engine(args) {
+            Ok(engine) => engine.with_coverage(),
+            Err(err) => {
+                eprintln!("{}", err);
+                return 2;
+            }
+        };
+        if let Err(err) = engine.eval(&source) {
+            eprint!("{}: ", file);
+            repl::report(&mut io::stderr(), &err);
+            status = 1;
+        }
+        let coverage = engine.coverage().unwrap();
+        eprintln!(
+            "{}: {}/{} lines, {}/{} branches",
+            file,
+            coverage.lines_hit(),
+            coverage.lines.len(),
+            coverage.branches_hit(),
+            coverage.branches.len() * 2
+        );
+        lcov.push_str(&coverage.lcov(file));
+    }
+    if let Err(err) = fs::write(out, lcov) {
+        eprintln!("{}: {}", out, err);
+        return 2;
+    }
+    status
+}
+
+/// The engine and source for a subcommand that runs the one file among its
+/// arguments, or the exit code after reporting why there is none.
+fn script(args: &[String], command: &str) -> Result<(Engine, String), i32> {
+    let (engine, file) = script_file(args, command)?;
+    match fs::read_to_string(file) {
+        Ok(source) => Ok((engine, source)),
+        Err(err) => {
+            eprintln!("{}: {}", file, err);
+            Err(2)
+        }
+    }
+}
+
+/// Like `script`, but leaves reading the file to the subcommand.
+fn script_file<'a>(args: &'a [String], command: &str) -> Result<(Engine, &'a str), i32> {
+    let file = match args.iter().find(|arg| !arg.starts_with("--")) {
+        Some(file) => file,
+        None => {
+            eprintln!("usage: monkey {} [OPTIONS] FILE", command);
+            return Err(2);
+        }
+    };
+    match engine(args) {
+        Ok(engine) => Ok((engine, file)),
+        Err(err) => {
+            eprintln!("{}", err);
+            Err(2)
+        }
+    }
+}
+
+/// `monkey dap [OPTIONS]`
+///
+/// Serves the Debug Adapter Protocol on stdin and stdout, for editors to
+/// launch and debug one program. Takes the same options as the REPL.
+fn dap(args: &[String]) -> i32 {
+    match engine(args) {
+        Ok(engine) => dap::serve(engine, io::stdin().lock(), io::stdout()),
+        Err(err) => {
+            eprintln!("{}", err);
+            2
+        }
+    }
+}
+
+/// `monkey fmt [--check] [FILE...]`
+///
+/// Formats each file in place, or stdin to stdout when no files are given.
+/// With `--check` nothing is written; the names of files that are not
+/// formatted are printed and the exit code is 1.
+fn fmt(args: &[String]) -> i32 {
+    let check = args.iter().any(|arg| arg == "--check");
+    let files: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+
+    if files.is_empty() {
+        let mut source = String::new();
+        if let Err(err) = io::stdin().read_to_string(&mut source) {
+            eprintln!("<stdin>: {}", err);
+            return 2;
+        }
+        return match format(&source) {
+            Ok(formatted) if check => (formatted != source) as i32,
+            Ok(formatted) => {
+                io::stdout().write_all(formatted.as_bytes()).unwrap();
+                0
+            }
+            Err(errors) => {
+                errors.iter().for_each(|err| eprintln!("<stdin>: {}", err));
+                2
+            }
+        };
+    }
+
+    let mut status = 0;
+    for file in files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("{}: {}", file, err);
+                status = 2;
+                continue;
+            }
+        };
+        match format(&source) {
+            Ok(formatted) if formatted == source => {}
+            Ok(_) if check => {
+                println!("{}", file);
+                status = status.max(1);
+            }
+            Ok(formatted) => {
+                if let Err(err) = fs::write(file, formatted) {
+                    eprintln!("{}: {}", file, err);
+                    status = 2;
+                }
+            }
+            Err(errors) => {
+                errors.iter().for_each(|err| eprintln!("{}: {}", file, err));
+                status = 2;
+            }
+        }
+    }
+    status
 }
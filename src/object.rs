@@ -0,0 +1,348 @@
+use crate::ast::{BlockStatement, Identifier};
+use crate::code::Instructions;
+use crate::environment::Env;
+use crate::gc::{self, Garbage};
+use crate::host::HostFunction;
+use crate::token::Span;
+use num_bigint::BigInt;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display, Error, Formatter};
+use std::rc::Rc;
+
+/// A Monkey value.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Object {
+    Integer(i64),
+    BigInteger(Rc<BigInt>), // only for values that do not fit in an i64
+    Boolean(bool),
+    String(Rc<str>),
+    Null,
+    #[doc(hidden)] // never escapes a function body
+    ReturnValue(Box<Object>),
+    #[doc(hidden)]
+    TailCall(Box<TailCall>),
+    Error(Box<RuntimeError>), // boxed, as errors are rare and large
+    Function(Function),
+    #[doc(hidden)] // only in the constants of compiled code
+    CompiledFunction(Rc<CompiledFunction>),
+    // a function made by compiled code, which only the VM that made it can
+    // call; `Function` is what an embedder passes back to the engine
+    #[doc(hidden)]
+    Closure(Rc<Closure>),
+    #[doc(hidden)] // only on the stack of the VM
+    Cell(Rc<Variable>),
+    Builtin(Builtin),
+    Host(HostFunction),
+    Array(Rc<Vec<Object>>),
+    Hash(Rc<BTreeMap<HashKey, HashPair>>),
+}
+
+impl Object {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Integer(_) | Object::BigInteger(_) => "INTEGER",
+            Object::Boolean(_) => "BOOLEAN",
+            Object::String(_) => "STRING",
+            Object::Null => "NULL",
+            Object::ReturnValue(_) => "RETURN_VALUE",
+            Object::TailCall(_) => "TAIL_CALL",
+            Object::Error(_) => "ERROR",
+            Object::Function(_) | Object::Closure(_) => "FUNCTION",
+            Object::CompiledFunction(_) => "COMPILED_FUNCTION",
+            Object::Cell(_) => "CELL",
+            Object::Builtin(_) | Object::Host(_) => "BUILTIN",
+            Object::Array(_) => "ARRAY",
+            Object::Hash(_) => "HASH",
+        }
+    }
+
+    /// The key this object is stored under when used in a hash, or `None`
+    /// if objects of its type cannot be hash keys.
+    pub(crate) fn hash_key(&self) -> Option<HashKey> {
+        match self {
+            Object::Integer(value) => Some(HashKey::Integer(*value)),
+            Object::BigInteger(value) => Some(HashKey::BigInteger(BigInt::clone(value))),
+            Object::Boolean(value) => Some(HashKey::Boolean(*value)),
+            Object::String(value) => Some(HashKey::String(value.to_string())),
+            _ => None,
+        }
+    }
+
+    /// Errors, returns and tail calls end the enclosing function (or
+    /// program) and must be passed up rather than used as a value.
+    pub(crate) fn is_abrupt(&self) -> bool {
+        matches!(
+            self,
+            Object::Error(_) | Object::ReturnValue(_) | Object::TailCall(_)
+        )
+    }
+
+    /// Everything except `false` and `null` is truthy.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Object::Boolean(false) | Object::Null)
+    }
+}
+
+impl Display for Object {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            Object::Integer(value) => write!(f, "{}", value),
+            Object::BigInteger(value) => write!(f, "{}", value),
+            Object::Boolean(value) => write!(f, "{}", value),
+            Object::String(value) => write!(f, "{}", value),
+            Object::Null => write!(f, "null"),
+            Object::ReturnValue(value) => write!(f, "{}", value),
+            Object::TailCall(call) => write!(f, "tail call to {}", call.function),
+            Object::Error(err) => write!(f, "ERROR: {}", err),
+            Object::Function(func) => write!(f, "{}", func),
+            Object::CompiledFunction(func) => write!(f, "compiled {}", func.text),
+            Object::Closure(closure) => write!(f, "{}", closure.func.text),
+            Object::Cell(variable) => match &*variable.value.borrow() {
+                Some(value) => write!(f, "cell {}", value),
+                None => write!(f, "unbound cell {}", variable.name),
+            },
+            Object::Builtin(builtin) => write!(f, "builtin function {}", builtin.name),
+            Object::Host(host) => write!(f, "host function {}", host.name),
+            Object::Array(elements) => {
+                let elements: Vec<_> = elements.iter().map(|e| e.to_string()).collect();
+                write!(f, "[{}]", elements.join(", "))
+            }
+            Object::Hash(pairs) => {
+                let pairs: Vec<_> = pairs
+                    .values()
+                    .map(|pair| format!("{}: {}", pair.key, pair.value))
+                    .collect();
+                write!(f, "{{{}}}", pairs.join(", "))
+            }
+        }
+    }
+}
+
+/// A call in tail position that the function making it returns instead of
+/// performing, leaving it to the caller's loop.
+#[doc(hidden)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TailCall {
+    pub(crate) function: Object,
+    pub(crate) args: Vec<Object>,
+    pub(crate) span: Span,
+}
+
+/// Identity of a hashable object. Keys of different types never collide, so
+/// `{1: "a", true: "b"}` holds two entries.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum HashKey {
+    Integer(i64),
+    BigInteger(BigInt),
+    Boolean(bool),
+    String(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashPair {
+    pub key: Object,
+    pub value: Object,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    TypeMismatch,
+    UnknownOperator,
+    UnknownIdentifier,
+    NotCallable,
+    WrongArguments,
+    InvalidArgument,
+    UnhashableKey,
+    DivisionByZero,
+    IntegerOverflow,
+    LimitExceeded,
+    Cancelled,
+    Host,
+}
+
+/// An error raised while evaluating. It is an ordinary value that every
+/// evaluation step returns immediately, so it reaches the top level with the
+/// span of the expression that failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Span,
+    pub backtrace: Backtrace,
+}
+
+impl RuntimeError {
+    pub(crate) fn new<P: Into<String>>(kind: ErrorKind, message: P, span: Span) -> Self {
+        RuntimeError {
+            kind,
+            message: message.into(),
+            span,
+            backtrace: Backtrace::default(),
+        }
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "{} at {}", self.message, self.span)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// The calls in progress when a runtime error happened, innermost first and
+/// ending with the program itself. A tail call replaces its caller's frame.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Backtrace {
+    pub frames: Vec<TraceFrame>,
+}
+
+/// A function in a backtrace and where evaluation was in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    pub function: String,
+    pub span: Span,
+}
+
+impl Display for Backtrace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        for (i, frame) in self.frames.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "    in {} at {}", frame.function, frame.span)?;
+        }
+        Ok(())
+    }
+}
+
+/// A function literal together with the environment it was evaluated in.
+#[derive(Clone)]
+pub struct Function {
+    pub(crate) name: Option<Rc<str>>,
+    pub(crate) parameters: Vec<Identifier>,
+    pub(crate) body: Rc<BlockStatement>,
+    pub(crate) env: Env,
+}
+
+/// Functions are equal only if they are the same closure; comparing the
+/// captured environments structurally could recurse forever.
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.body, &other.body) && Rc::ptr_eq(&self.env, &other.env)
+    }
+}
+
+impl Debug for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "Function({})", self)
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        let params: Vec<_> = self.parameters.iter().map(|p| p.value.as_str()).collect();
+        write!(f, "fn({}) {{ {} }}", params.join(", "), self.body)
+    }
+}
+
+/// A function literal compiled to bytecode.
+#[doc(hidden)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledFunction {
+    pub(crate) instructions: Instructions,
+    // the offset of the first instruction compiled from each position
+    pub(crate) positions: Vec<(usize, Span)>,
+    pub(crate) num_locals: usize,
+    pub(crate) num_parameters: usize,
+    pub(crate) name: Option<Rc<str>>,
+    // the literal as the evaluator prints a function
+    pub(crate) text: Rc<str>,
+}
+
+impl CompiledFunction {
+    /// The position the instruction at `offset` was compiled from.
+    pub(crate) fn position(&self, offset: usize) -> Span {
+        match self.positions.binary_search_by_key(&offset, |(start, _)| *start) {
+            Ok(i) => self.positions[i].1,
+            Err(0) => Span::default(),
+            Err(i) => self.positions[i - 1].1,
+        }
+    }
+}
+
+/// A compiled function together with the variables it captured from
+/// enclosing functions when it was made: cells it shares with them, or the
+/// closure of a function that refers to itself.
+#[derive(Debug)]
+pub struct Closure {
+    pub(crate) func: Rc<CompiledFunction>,
+    pub(crate) free: Vec<Object>,
+}
+
+/// As with `Function`, only the same closure is equal.
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Drop for Closure {
+    fn drop(&mut self) {
+        gc::release(self.free.drain(..).map(Garbage::Value));
+    }
+}
+
+/// A local of a compiled function that closures made in it capture. They
+/// share it rather than copy its value, so that like the evaluator's
+/// closures they see it bound, or bound again, after they were made.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct Variable {
+    pub(crate) name: Rc<str>,
+    // `None` until the function's `let` binds it
+    pub(crate) value: RefCell<Option<Object>>,
+}
+
+impl PartialEq for Variable {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Drop for Variable {
+    fn drop(&mut self) {
+        gc::release(self.value.get_mut().take().map(Garbage::Value));
+    }
+}
+
+pub(crate) type BuiltinFunction = fn(&[Object], Span) -> Object;
+
+/// A function implemented in Rust. The span passed to it is that of the
+/// call, for use in any error it returns.
+#[derive(Clone, Copy)]
+pub struct Builtin {
+    pub(crate) name: &'static str,
+    pub(crate) func: BuiltinFunction,
+}
+
+impl Builtin {
+    pub(crate) fn new(name: &'static str, func: BuiltinFunction) -> Self {
+        Builtin { name, func }
+    }
+}
+
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Debug for Builtin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "Builtin({})", self.name)
+    }
+}
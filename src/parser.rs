@@ -1,24 +1,65 @@
-use crate::ast::{self, Program, Statement, LetStatement, Identifier, ReturnStatement};
+use crate::ast::{
+	self, ArrayLiteral, BlockStatement, Boolean, CallExpression, Expression, ExpressionStatement,
+	FunctionLiteral, HashLiteral, Identifier, IfExpression, IndexExpression, InfixExpression,
+	IntegerLiteral, LetStatement, PrefixExpression, Program, ReturnStatement, Statement,
+	StringLiteral,
+};
 use crate::lexer::Lexer;
 use crate::token::{Token, TokenType};
-use std::fmt::{Formatter};
+use num_bigint::BigInt;
+use std::fmt::Formatter;
+use std::rc::Rc;
+
+/// How deep expressions may nest, counting each operator applied to the
+/// result of another, so that walking the tree cannot overflow the stack.
+pub(crate) const MAX_NESTING: usize = 128;
 
 #[derive(Debug, Clone)]
-pub(crate) struct ParserError(String);
+pub struct ParserError(String);
 
 impl std::fmt::Display for ParserError {
-	fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::error::Error> {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
 		write!(f, "ParserError: {}", self.0)
 	}
 }
 
 impl std::error::Error for ParserError {}
 
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub(crate) enum Precedence {
+	Lowest,
+	Equals,      // ==
+	LessGreater, // > or <
+	Sum,         // +
+	Product,     // *
+	Prefix,      // -X or !X
+	Call,        // myFunction(X)
+	Index,       // array[index]
+}
+
+pub(crate) fn precedence_of(token_type: &TokenType) -> Precedence {
+	use TokenType::*;
+	match token_type {
+		EQ | NOT_EQ => Precedence::Equals,
+		LT | GT => Precedence::LessGreater,
+		PLUS | MINUS => Precedence::Sum,
+		SLASH | ASTERISK => Precedence::Product,
+		LPAREN => Precedence::Call,
+		LBRACKET => Precedence::Index,
+		_ => Precedence::Lowest,
+	}
+}
+
 pub(crate) struct Parser {
 	lexer: Lexer,
 	errors: Vec<ParserError>,
+	comments: Vec<Token>,
 	cur_token: Token,
 	peek_token: Token,
+	// how deep the expression being parsed is nested
+	depth: usize,
+	// set once nesting goes past `MAX_NESTING`, which ends the parse
+	too_deep: bool,
 }
 
 impl Parser {
@@ -26,26 +67,44 @@ impl Parser {
 		let mut p = Parser {
 			lexer,
 			errors: Vec::new(),
+			comments: Vec::new(),
 			cur_token: Default::default(),
 			peek_token: Default::default(),
+			depth: 0,
+			too_deep: false,
 		};
 		p.next_token();
 		p.next_token();
 		p
 	}
 
+	pub(crate) fn errors(&self) -> &[ParserError] {
+		&self.errors
+	}
+
+	/// Comments skipped while parsing, in source order.
+	pub(crate) fn comments(&self) -> &[Token] {
+		&self.comments
+	}
+
 	fn next_token(&mut self) {
 		self.cur_token = self.peek_token.clone();
 		self.peek_token = self.lexer.next_token();
+		while self.peek_token.t_type == TokenType::COMMENT {
+			self.comments.push(self.peek_token.clone());
+			self.peek_token = self.lexer.next_token();
+		}
 	}
 
 	pub fn parse_program(&mut self) -> Result<ast::Program, ParserError> {
 		let mut program = Program::new();
 
 		while self.cur_token.t_type != TokenType::EOF {
-			let stmt = self.parse_statement();
-			if let Ok(state) = stmt {
-				program.statements.push(state);
+			match self.parse_statement() {
+				Ok(state) => program.statements.push(state),
+				// what follows is as deep, so not worth reading on
+				Err(err) if self.too_deep => return Err(err),
+				Err(_) => {}
 			}
 			self.next_token()
 		}
@@ -57,45 +116,319 @@ impl Parser {
 		match self.cur_token.t_type {
 			LET => self.parse_let_statement(),
 			RETURN => self.parse_return_statement(),
-			_ => Err(ParserError(String::from("unimplemented!"))),
+			_ => self.parse_expression_statement(),
 		}
 	}
 
 	fn parse_let_statement(&mut self) -> Result<ast::Statement, ParserError> {
-		let mut stmt = LetStatement::new(self.cur_token.clone());
+		let token = self.cur_token.clone();
 		self.expect_peek(TokenType::IDENT)?;
-		stmt.name = Identifier { token: self.cur_token.clone(), value: self.cur_token.literal.clone() };
+		let name = Identifier { token: self.cur_token.clone(), value: self.cur_token.literal.clone() };
 		self.expect_peek(TokenType::ASSIGN)?;
+		self.next_token();
 
-		// TODO: We're skipping the expressions until we encounter a semicolon
-		while !self.cur_token_is(TokenType::SEMICOLON) {
+		let mut value = self.parse_expression(Precedence::Lowest)?;
+		if self.peek_token_is(&TokenType::SEMICOLON) {
 			self.next_token();
 		}
 
-		Ok(Statement::Let(stmt))
+		// a function is known by the name it is first bound to
+		if let Expression::FunctionLiteral(func) = &mut value {
+			func.name = Some(name.value.as_str().into());
+		}
+
+		Ok(Statement::Let(LetStatement { token, name, value }))
 	}
 
 	fn parse_return_statement(&mut self) -> Result<ast::Statement, ParserError> {
-		let stmt = ReturnStatement::new(self.cur_token.clone());
+		let token = self.cur_token.clone();
+		self.next_token();
+
+		let return_value = self.parse_expression(Precedence::Lowest)?;
+		if self.peek_token_is(&TokenType::SEMICOLON) {
+			self.next_token();
+		}
+
+		Ok(Statement::Return(ReturnStatement { token, return_value }))
+	}
+
+	fn parse_expression_statement(&mut self) -> Result<ast::Statement, ParserError> {
+		let token = self.cur_token.clone();
+		let expression = self.parse_expression(Precedence::Lowest)?;
+		if self.peek_token_is(&TokenType::SEMICOLON) {
+			self.next_token();
+		}
+
+		Ok(Statement::Expression(ExpressionStatement { token, expression }))
+	}
+
+	fn parse_expression(&mut self, precedence: Precedence) -> Result<Expression, ParserError> {
+		let depth = self.depth;
+		let result = self.parse_nested_expression(precedence);
+		self.depth = depth;
+		result
+	}
+
+	fn parse_nested_expression(&mut self, precedence: Precedence) -> Result<Expression, ParserError> {
+		self.nest()?;
+		let mut left = self.parse_prefix()?;
+
+		while !self.peek_token_is(&TokenType::SEMICOLON) && precedence < self.peek_precedence() {
+			self.next_token();
+			// the expression so far becomes an operand, one level down
+			self.nest()?;
+			left = self.parse_infix(left)?;
+		}
+
+		Ok(left)
+	}
+
+	fn nest(&mut self) -> Result<(), ParserError> {
+		self.depth += 1;
+		if self.depth <= MAX_NESTING {
+			return Ok(());
+		}
+		self.too_deep = true;
+		Err(self.error(format!("expression nested too deeply (at most {})", MAX_NESTING)))
+	}
+
+	fn parse_prefix(&mut self) -> Result<Expression, ParserError> {
+		use TokenType::*;
+		match self.cur_token.t_type {
+			IDENT => Ok(Expression::Identifier(self.parse_identifier())),
+			INT => self.parse_integer_literal(),
+			STRING => Ok(Expression::StringLiteral(StringLiteral {
+				token: self.cur_token.clone(),
+				value: self.cur_token.literal.clone(),
+			})),
+			TRUE | FALSE => Ok(Expression::Boolean(Boolean {
+				token: self.cur_token.clone(),
+				value: self.cur_token_is(TRUE),
+			})),
+			BANG | MINUS => self.parse_prefix_expression(),
+			LPAREN => self.parse_grouped_expression(),
+			IF => self.parse_if_expression(),
+			FUNCTION => self.parse_function_literal(),
+			LBRACKET => {
+				let token = self.cur_token.clone();
+				let elements = self.parse_expression_list(RBRACKET)?;
+				Ok(Expression::ArrayLiteral(ArrayLiteral { token, elements }))
+			}
+			LBRACE => self.parse_hash_literal(),
+			_ => Err(self.error(format!(
+				"no prefix parse function for {} found",
+				self.cur_token.t_type
+			))),
+		}
+	}
+
+	fn parse_infix(&mut self, left: Expression) -> Result<Expression, ParserError> {
+		match self.cur_token.t_type {
+			TokenType::LPAREN => self.parse_call_expression(left),
+			TokenType::LBRACKET => self.parse_index_expression(left),
+			_ => self.parse_infix_expression(left),
+		}
+	}
 
+	fn parse_identifier(&self) -> Identifier {
+		Identifier { token: self.cur_token.clone(), value: self.cur_token.literal.clone() }
+	}
+
+	fn parse_integer_literal(&mut self) -> Result<Expression, ParserError> {
+		match self.cur_token.literal.parse::<BigInt>() {
+			Ok(value) => Ok(Expression::IntegerLiteral(IntegerLiteral {
+				token: self.cur_token.clone(),
+				value,
+			})),
+			Err(_) => Err(self.error(format!(
+				"could not parse {} as integer",
+				self.cur_token.literal
+			))),
+		}
+	}
+
+	fn parse_prefix_expression(&mut self) -> Result<Expression, ParserError> {
+		let token = self.cur_token.clone();
+		let operator = token.literal.clone();
 		self.next_token();
+		let right = self.parse_expression(Precedence::Prefix)?;
 
-		// TODO: We're skipping the expressions until we encounter a semicolon
-		while !self.cur_token_is(TokenType::SEMICOLON) {
+		Ok(Expression::Prefix(PrefixExpression { token, operator, right: Box::new(right) }))
+	}
+
+	fn parse_infix_expression(&mut self, left: Expression) -> Result<Expression, ParserError> {
+		let token = self.cur_token.clone();
+		let operator = token.literal.clone();
+		let precedence = self.cur_precedence();
+		self.next_token();
+		let right = self.parse_expression(precedence)?;
+
+		Ok(Expression::Infix(InfixExpression {
+			token,
+			left: Box::new(left),
+			operator,
+			right: Box::new(right),
+		}))
+	}
+
+	fn parse_grouped_expression(&mut self) -> Result<Expression, ParserError> {
+		self.next_token();
+		let exp = self.parse_expression(Precedence::Lowest)?;
+		self.expect_peek(TokenType::RPAREN)?;
+		Ok(exp)
+	}
+
+	fn parse_if_expression(&mut self) -> Result<Expression, ParserError> {
+		let token = self.cur_token.clone();
+		self.expect_peek(TokenType::LPAREN)?;
+		self.next_token();
+		let condition = self.parse_expression(Precedence::Lowest)?;
+		self.expect_peek(TokenType::RPAREN)?;
+		self.expect_peek(TokenType::LBRACE)?;
+		let consequence = self.parse_block_statement()?;
+
+		let alternative = if self.peek_token_is(&TokenType::ELSE) {
+			self.next_token();
+			self.expect_peek(TokenType::LBRACE)?;
+			Some(self.parse_block_statement()?)
+		} else {
+			None
+		};
+
+		Ok(Expression::If(IfExpression {
+			token,
+			condition: Box::new(condition),
+			consequence,
+			alternative,
+		}))
+	}
+
+	fn parse_block_statement(&mut self) -> Result<BlockStatement, ParserError> {
+		let token = self.cur_token.clone();
+		let mut statements = Vec::new();
+		self.next_token();
+
+		while !self.cur_token_is(TokenType::RBRACE) {
+			if self.cur_token_is(TokenType::EOF) {
+				return Err(self.error(format!("Expected: {}, Got: {}", TokenType::RBRACE, TokenType::EOF)));
+			}
+			match self.parse_statement() {
+				Ok(stmt) => statements.push(stmt),
+				Err(err) if self.too_deep => return Err(err),
+				Err(_) => {}
+			}
 			self.next_token();
 		}
 
-		Ok(Statement::Return(stmt))
+		Ok(BlockStatement { token, statements, end: self.cur_token.span })
 	}
 
-	fn cur_token_is(&mut self, token_type: TokenType) -> bool {
+	fn parse_function_literal(&mut self) -> Result<Expression, ParserError> {
+		let token = self.cur_token.clone();
+		self.expect_peek(TokenType::LPAREN)?;
+		let parameters = self.parse_function_parameters()?;
+		self.expect_peek(TokenType::LBRACE)?;
+		let body = self.parse_block_statement()?;
+
+		Ok(Expression::FunctionLiteral(FunctionLiteral {
+			token,
+			name: None,
+			parameters,
+			body: Rc::new(body),
+		}))
+	}
+
+	fn parse_function_parameters(&mut self) -> Result<Vec<Identifier>, ParserError> {
+		let mut identifiers = Vec::new();
+		if self.peek_token_is(&TokenType::RPAREN) {
+			self.next_token();
+			return Ok(identifiers);
+		}
+
+		self.expect_peek(TokenType::IDENT)?;
+		identifiers.push(self.parse_identifier());
+		while self.peek_token_is(&TokenType::COMMA) {
+			self.next_token();
+			self.expect_peek(TokenType::IDENT)?;
+			identifiers.push(self.parse_identifier());
+		}
+		self.expect_peek(TokenType::RPAREN)?;
+
+		Ok(identifiers)
+	}
+
+	fn parse_call_expression(&mut self, function: Expression) -> Result<Expression, ParserError> {
+		let token = self.cur_token.clone();
+		let arguments = self.parse_expression_list(TokenType::RPAREN)?;
+
+		Ok(Expression::Call(CallExpression { token, function: Box::new(function), arguments }))
+	}
+
+	fn parse_index_expression(&mut self, left: Expression) -> Result<Expression, ParserError> {
+		let token = self.cur_token.clone();
+		self.next_token();
+		let index = self.parse_expression(Precedence::Lowest)?;
+		self.expect_peek(TokenType::RBRACKET)?;
+
+		Ok(Expression::Index(IndexExpression { token, left: Box::new(left), index: Box::new(index) }))
+	}
+
+	fn parse_hash_literal(&mut self) -> Result<Expression, ParserError> {
+		let token = self.cur_token.clone();
+		let mut pairs = Vec::new();
+
+		while !self.peek_token_is(&TokenType::RBRACE) {
+			self.next_token();
+			let key = self.parse_expression(Precedence::Lowest)?;
+			self.expect_peek(TokenType::COLON)?;
+			self.next_token();
+			let value = self.parse_expression(Precedence::Lowest)?;
+			pairs.push((key, value));
+
+			if !self.peek_token_is(&TokenType::RBRACE) {
+				self.expect_peek(TokenType::COMMA)?;
+			}
+		}
+		self.expect_peek(TokenType::RBRACE)?;
+
+		Ok(Expression::HashLiteral(HashLiteral { token, pairs }))
+	}
+
+	fn parse_expression_list(&mut self, end: TokenType) -> Result<Vec<Expression>, ParserError> {
+		let mut list = Vec::new();
+		if self.peek_token_is(&end) {
+			self.next_token();
+			return Ok(list);
+		}
+
+		self.next_token();
+		list.push(self.parse_expression(Precedence::Lowest)?);
+		while self.peek_token_is(&TokenType::COMMA) {
+			self.next_token();
+			self.next_token();
+			list.push(self.parse_expression(Precedence::Lowest)?);
+		}
+		self.expect_peek(end)?;
+
+		Ok(list)
+	}
+
+	fn cur_token_is(&self, token_type: TokenType) -> bool {
 		self.cur_token.t_type == token_type
 	}
 
-	fn peek_token_is(&mut self, token_type: &TokenType) -> bool {
+	fn peek_token_is(&self, token_type: &TokenType) -> bool {
 		&self.peek_token.t_type == token_type
 	}
 
+	fn peek_precedence(&self) -> Precedence {
+		precedence_of(&self.peek_token.t_type)
+	}
+
+	fn cur_precedence(&self) -> Precedence {
+		precedence_of(&self.cur_token.t_type)
+	}
+
 	fn expect_peek(&mut self, token_type: TokenType) -> Result<(), ParserError> {
 		if self.peek_token_is(&token_type) {
 			self.next_token();
@@ -106,14 +439,19 @@ impl Parser {
 			Err(err)
 		}
 	}
+
+	fn error(&mut self, msg: String) -> ParserError {
+		let err = ParserError(msg);
+		self.errors.push(err.clone());
+		err
+	}
 }
 
 #[cfg(test)]
 mod tests {
-	use crate::ast::{Node, Statement};
+	use crate::ast::{Expression, Node, Program, Statement};
 	use crate::lexer::Lexer;
-	use crate::parser::Parser;
-	use std::error::Error;
+	use crate::parser::{Parser, MAX_NESTING};
 
 	struct TestIdent<'a>(&'a str);
 
@@ -139,10 +477,10 @@ mod tests {
 				program.statements.len()
 			)
 		}
-		let tests = vec![TestIdent("x"), TestIdent("y"), TestIdent("foobar")];
+		let tests = [TestIdent("x"), TestIdent("y"), TestIdent("foobar")];
 		for (i, tt) in tests.iter().enumerate() {
 			let stmt = &program.statements[i];
-			if !test_let_statement(&stmt, tt) {
+			if !test_let_statement(stmt, tt) {
 				return;
 			}
 		}
@@ -180,20 +518,312 @@ mod tests {
 		})
 	}
 
+	#[test]
+	fn test_let_statement_values() {
+		let tests = vec![
+			("let x = 5;", "x", "5"),
+			("let y = true;", "y", "true"),
+			("let foobar = y;", "foobar", "y"),
+		];
+
+		for (input, ident, value) in tests {
+			let program = parse(input);
+			assert_eq!(program.statements.len(), 1);
+			match &program.statements[0] {
+				Statement::Let(stmt) => {
+					assert_eq!(stmt.name.value, ident);
+					assert_eq!(stmt.value.to_string(), value);
+				}
+				stmt => panic!("stmt not Let. got={:?}", stmt),
+			}
+		}
+	}
+
+	#[test]
+	fn test_prefix_expressions() {
+		let tests = vec![("!5;", "!", "5"), ("-15;", "-", "15"), ("!true;", "!", "true")];
+
+		for (input, operator, right) in tests {
+			let program = parse(input);
+			assert_eq!(program.statements.len(), 1);
+			match expression(&program.statements[0]) {
+				Expression::Prefix(exp) => {
+					assert_eq!(exp.operator, operator);
+					assert_eq!(exp.right.to_string(), right);
+				}
+				exp => panic!("exp not Prefix. got={:?}", exp),
+			}
+		}
+	}
+
+	#[test]
+	fn test_infix_expressions() {
+		let tests = vec![
+			("5 + 5;", "5", "+", "5"),
+			("5 - 5;", "5", "-", "5"),
+			("5 * 5;", "5", "*", "5"),
+			("5 / 5;", "5", "/", "5"),
+			("5 > 5;", "5", ">", "5"),
+			("5 < 5;", "5", "<", "5"),
+			("5 == 5;", "5", "==", "5"),
+			("5 != 5;", "5", "!=", "5"),
+			("true == false", "true", "==", "false"),
+		];
+
+		for (input, left, operator, right) in tests {
+			let program = parse(input);
+			assert_eq!(program.statements.len(), 1);
+			match expression(&program.statements[0]) {
+				Expression::Infix(exp) => {
+					assert_eq!(exp.left.to_string(), left);
+					assert_eq!(exp.operator, operator);
+					assert_eq!(exp.right.to_string(), right);
+				}
+				exp => panic!("exp not Infix. got={:?}", exp),
+			}
+		}
+	}
+
+	#[test]
+	fn test_operator_precedence_parsing() {
+		let tests = vec![
+			("-a * b", "((-a) * b)"),
+			("!-a", "(!(-a))"),
+			("a + b + c", "((a + b) + c)"),
+			("a + b - c", "((a + b) - c)"),
+			("a * b * c", "((a * b) * c)"),
+			("a + b / c", "(a + (b / c))"),
+			("a + b * c + d / e - f", "(((a + (b * c)) + (d / e)) - f)"),
+			("3 + 4; -5 * 5", "(3 + 4)((-5) * 5)"),
+			("5 > 4 == 3 < 4", "((5 > 4) == (3 < 4))"),
+			("3 + 4 * 5 == 3 * 1 + 4 * 5", "((3 + (4 * 5)) == ((3 * 1) + (4 * 5)))"),
+			("3 > 5 == false", "((3 > 5) == false)"),
+			("1 + (2 + 3) + 4", "((1 + (2 + 3)) + 4)"),
+			("-(5 + 5)", "(-(5 + 5))"),
+			("!(true == true)", "(!(true == true))"),
+			("a + add(b * c) + d", "((a + add((b * c))) + d)"),
+			("add(a, b, 1, 2 * 3, 4 + 5, add(6, 7 * 8))", "add(a, b, 1, (2 * 3), (4 + 5), add(6, (7 * 8)))"),
+			("a * [1, 2, 3, 4][b * c] * d", "((a * ([1, 2, 3, 4][(b * c)])) * d)"),
+			("add(a * b[2], b[1], 2 * [1, 2][1])", "add((a * (b[2])), (b[1]), (2 * ([1, 2][1])))"),
+		];
+
+		for (input, expected) in tests {
+			let program = parse(input);
+			assert_eq!(program.to_string(), expected, "input: {}", input);
+		}
+	}
+
+	#[test]
+	fn test_if_else_expression() {
+		let program = parse("if (x < y) { x } else { y }");
+		assert_eq!(program.statements.len(), 1);
+		match expression(&program.statements[0]) {
+			Expression::If(exp) => {
+				assert_eq!(exp.condition.to_string(), "(x < y)");
+				assert_eq!(exp.consequence.statements.len(), 1);
+				assert_eq!(exp.consequence.to_string(), "x");
+				let alternative = exp.alternative.as_ref().expect("alternative missing");
+				assert_eq!(alternative.to_string(), "y");
+			}
+			exp => panic!("exp not If. got={:?}", exp),
+		}
+	}
+
+	#[test]
+	fn test_function_literal_parsing() {
+		let tests = vec![
+			("fn() {};", vec![]),
+			("fn(x) {};", vec!["x"]),
+			("fn(x, y, z) { x + y; };", vec!["x", "y", "z"]),
+		];
+
+		for (input, params) in tests {
+			let program = parse(input);
+			match expression(&program.statements[0]) {
+				Expression::FunctionLiteral(exp) => {
+					let got: Vec<_> = exp.parameters.iter().map(|p| p.value.as_str()).collect();
+					assert_eq!(got, params);
+				}
+				exp => panic!("exp not FunctionLiteral. got={:?}", exp),
+			}
+		}
+	}
+
+	#[test]
+	fn test_function_literal_with_name() {
+		let program = parse("let myFunction = fn() { }; let other = [fn() { }];");
+		let names: Vec<_> = program
+			.statements
+			.iter()
+			.map(|stmt| match stmt {
+				Statement::Let(stmt) => match &stmt.value {
+					Expression::FunctionLiteral(func) => func.name.as_deref().map(String::from),
+					_ => None,
+				},
+				stmt => panic!("stmt not Let. got={:?}", stmt),
+			})
+			.collect();
+		assert_eq!(names, vec![Some("myFunction".to_string()), None]);
+	}
+
+	#[test]
+	fn test_call_expression_parsing() {
+		let program = parse("add(1, 2 * 3, 4 + 5);");
+		match expression(&program.statements[0]) {
+			Expression::Call(exp) => {
+				assert_eq!(exp.function.to_string(), "add");
+				let args: Vec<_> = exp.arguments.iter().map(|a| a.to_string()).collect();
+				assert_eq!(args, vec!["1", "(2 * 3)", "(4 + 5)"]);
+			}
+			exp => panic!("exp not Call. got={:?}", exp),
+		}
+	}
+
+	#[test]
+	fn test_integer_literal_expression() {
+		for input in ["5", "9223372036854775808", "123456789012345678901234567890"] {
+			let program = parse(input);
+			match expression(&program.statements[0]) {
+				Expression::IntegerLiteral(exp) => assert_eq!(exp.value.to_string(), input),
+				exp => panic!("exp not IntegerLiteral. got={:?}", exp),
+			}
+		}
+	}
+
+	#[test]
+	fn test_string_literal_expression() {
+		let program = parse(r#""hello world";"#);
+		match expression(&program.statements[0]) {
+			Expression::StringLiteral(exp) => assert_eq!(exp.value, "hello world"),
+			exp => panic!("exp not StringLiteral. got={:?}", exp),
+		}
+	}
+
+	#[test]
+	fn test_array_and_index_parsing() {
+		let program = parse("[1, 2 * 2, 3 + 3]; myArray[1 + 1]; []");
+		match expression(&program.statements[0]) {
+			Expression::ArrayLiteral(exp) => {
+				let elements: Vec<_> = exp.elements.iter().map(|e| e.to_string()).collect();
+				assert_eq!(elements, vec!["1", "(2 * 2)", "(3 + 3)"]);
+			}
+			exp => panic!("exp not ArrayLiteral. got={:?}", exp),
+		}
+		match expression(&program.statements[1]) {
+			Expression::Index(exp) => {
+				assert_eq!(exp.left.to_string(), "myArray");
+				assert_eq!(exp.index.to_string(), "(1 + 1)");
+			}
+			exp => panic!("exp not Index. got={:?}", exp),
+		}
+		match expression(&program.statements[2]) {
+			Expression::ArrayLiteral(exp) => assert!(exp.elements.is_empty()),
+			exp => panic!("exp not ArrayLiteral. got={:?}", exp),
+		}
+	}
+
+	#[test]
+	fn test_hash_literal_parsing() {
+		let tests = vec![
+			("{}", vec![]),
+			("{1: 2, true: 3}", vec![("1", "2"), ("true", "3")]),
+			("{one: 0 + 1, two: 10 - 8}", vec![("one", "(0 + 1)"), ("two", "(10 - 8)")]),
+		];
+
+		for (input, expected) in tests {
+			let program = parse(input);
+			match expression(&program.statements[0]) {
+				Expression::HashLiteral(exp) => {
+					let pairs: Vec<_> = exp.pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+					let expected: Vec<_> = expected.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+					assert_eq!(pairs, expected, "input: {}", input);
+				}
+				exp => panic!("exp not HashLiteral. got={:?}", exp),
+			}
+		}
+	}
+
+	#[test]
+	fn test_comments_are_collected() {
+		let l = Lexer::new("// leading\nlet x = 5; // trailing\nx".into());
+		let mut p = Parser::new(l);
+		let program = p.parse_program().unwrap();
+		check_parser_errors(&p);
+		assert_eq!(program.statements.len(), 2);
+		let comments: Vec<_> = p.comments().iter().map(|c| c.literal.as_str()).collect();
+		assert_eq!(comments, vec!["// leading", "// trailing"]);
+	}
+
+	#[test]
+	fn test_parser_errors() {
+		let l = Lexer::new("let = 5; let x 5; )".into());
+		let mut p = Parser::new(l);
+		p.parse_program().unwrap();
+		let errors: Vec<_> = p.errors().iter().map(|e| e.to_string()).collect();
+		assert_eq!(
+			errors,
+			vec![
+				"ParserError: Expected: IDENT, Got: ASSIGN",
+				"ParserError: no prefix parse function for ASSIGN found",
+				"ParserError: Expected: ASSIGN, Got: INT",
+				"ParserError: no prefix parse function for RPAREN found",
+			]
+		);
+	}
+
+	#[test]
+	fn test_nesting_limit() {
+		let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+		// the outermost expression is the first level, and its last operand
+		// one below the operators before it
+		parse(&nested(MAX_NESTING - 1));
+		parse(&vec!["1"; MAX_NESTING - 1].join(" + "));
+
+		let tests = vec![
+			nested(MAX_NESTING),
+			nested(200_000),
+			"-".repeat(300_000) + "1",
+			vec!["1"; 1000].join(" + "),
+			"if (true) { ".repeat(MAX_NESTING) + "1" + &" }".repeat(MAX_NESTING),
+			format!("let f = fn() {{ 1 }}; f{}; 2", "()".repeat(MAX_NESTING)),
+		];
+		for input in tests {
+			let mut p = Parser::new(Lexer::new(input.clone()));
+			let err = p.parse_program().unwrap_err();
+			let expected = "ParserError: expression nested too deeply (at most 128)";
+			assert_eq!(err.to_string(), expected, "{:.40}", input);
+		}
+	}
+
+	fn parse(input: &str) -> Program {
+		let l = Lexer::new(input.into());
+		let mut p = Parser::new(l);
+		let program = p.parse_program().unwrap();
+		check_parser_errors(&p);
+		program
+	}
+
+	fn expression(stmt: &Statement) -> &Expression {
+		match stmt {
+			Statement::Expression(stmt) => &stmt.expression,
+			stmt => panic!("stmt not Expression. got={:?}", stmt),
+		}
+	}
+
 	fn test_let_statement(actual: &Statement, expected: &TestIdent) -> bool {
-		return match actual {
+		match actual {
 			Statement::Let(stmt) if stmt.token_literal().as_str() != "let" => {
 				eprintln!("s.TokenLiteral not 'let'. got={:?}", stmt);
 				false
 			}
-			Statement::Let(stmt) if &stmt.name.value != expected.0 => {
+			Statement::Let(stmt) if stmt.name.value != expected.0 => {
 				eprintln!(
 					"letStmt.Name.Value not '{}'. got={}",
 					expected.0, &stmt.name.value
 				);
 				false
 			}
-			Statement::Let(stmt) if &stmt.name.token_literal() != expected.0 => {
+			Statement::Let(stmt) if stmt.name.token_literal() != expected.0 => {
 				eprintln!(
 					"letStmt.Name.TokenLiteral not '{}'. got={}",
 					expected.0,
@@ -206,7 +836,7 @@ mod tests {
 				eprintln!("s not *ast.LetStatement. got={:?}", actual);
 				false
 			}
-		};
+		}
 	}
 
 	fn check_parser_errors(p: &Parser) {
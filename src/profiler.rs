@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// Times the calls an evaluator makes, every one of them rather than a
+/// sample, so it slows evaluation down and is only kept when asked for.
+#[derive(Debug, Default)]
+pub(crate) struct Profiler {
+    open: Vec<Call>,
+    functions: HashMap<String, FunctionProfile>,
+    stacks: BTreeMap<String, Duration>,
+}
+
+/// A call that has not returned yet.
+#[derive(Debug)]
+struct Call {
+    name: String,
+    start: Instant,
+    // spent in the calls it made
+    children: Duration,
+}
+
+impl Profiler {
+    pub(crate) fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    pub(crate) fn enter(&mut self, name: String) {
+        self.functions
+            .entry(name.clone())
+            .or_insert_with(|| FunctionProfile::new(&name))
+            .calls += 1;
+        self.open.push(Call {
+            name,
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    /// Ends the calls above `depth`, innermost first.
+    pub(crate) fn exit_to(&mut self, depth: usize) {
+        while self.open.len() > depth {
+            self.exit();
+        }
+    }
+
+    pub(crate) fn exit(&mut self) {
+        let call = match self.open.pop() {
+            Some(call) => call,
+            None => return,
+        };
+        let elapsed = call.start.elapsed();
+        let exclusive = elapsed.saturating_sub(call.children);
+        if let Some(caller) = self.open.last_mut() {
+            caller.children += elapsed;
+        }
+
+        // a recursive call's time is already part of the outermost one's
+        let recursive = self.open.iter().any(|open| open.name == call.name);
+        let function = self.functions.get_mut(&call.name).unwrap();
+        if !recursive {
+            function.inclusive += elapsed;
+        }
+        function.exclusive += exclusive;
+
+        let mut stack: Vec<&str> = self.open.iter().map(|open| open.name.as_str()).collect();
+        stack.push(&call.name);
+        *self.stacks.entry(stack.join(";")).or_default() += exclusive;
+    }
+
+    pub(crate) fn profile(&self) -> Profile {
+        let mut functions: Vec<_> = self.functions.values().cloned().collect();
+        functions.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then(a.name.cmp(&b.name)));
+        Profile {
+            functions,
+            stacks: self.stacks.clone(),
+        }
+    }
+}
+
+/// What the profiler measured, for every function called so far.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    /// Sorted by self time, the most first.
+    pub functions: Vec<FunctionProfile>,
+    stacks: BTreeMap<String, Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionProfile {
+    /// As in a backtrace; builtins and host functions are prefixed with
+    /// `builtin` and `host`.
+    pub name: String,
+    pub calls: u64,
+    /// Time from call to return, counting a recursive call only once.
+    pub inclusive: Duration,
+    /// Time not spent in the calls it made.
+    pub exclusive: Duration,
+}
+
+impl FunctionProfile {
+    fn new(name: &str) -> Self {
+        FunctionProfile {
+            name: name.to_string(),
+            calls: 0,
+            inclusive: Duration::ZERO,
+            exclusive: Duration::ZERO,
+        }
+    }
+}
+
+impl Profile {
+    /// A table of the functions, sorted by self time.
+    pub fn report(&self) -> String {
+        let mut report = format!("{:>12} {:>12} {:>10}  function\n", "self ms", "total ms", "calls");
+        for function in &self.functions {
+            writeln!(
+                report,
+                "{:>12.3} {:>12.3} {:>10}  {}",
+                millis(function.exclusive),
+                millis(function.inclusive),
+                function.calls,
+                function.name
+            )
+            .unwrap();
+        }
+        report
+    }
+
+    /// One line per call stack, its frames outermost first and separated
+    /// by `;`, then the self time spent in it in microseconds: the input
+    /// `flamegraph.pl` and `inferno-flamegraph` take.
+    pub fn collapsed(&self) -> String {
+        let mut collapsed = String::new();
+        for (stack, time) in &self.stacks {
+            writeln!(collapsed, "{} {}", stack, time.as_micros()).unwrap();
+        }
+        collapsed
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_profile() {
+        let engine = Engine::new().with_profiler();
+        engine
+            .eval(
+                "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };
+let count = fn(xs, n) { if (len(xs) == 0) { n } else { count(rest(xs), n + 1) } };
+fib(10);
+count([1, 2, 3], 0);",
+            )
+            .unwrap();
+
+        let profile = engine.profile().unwrap();
+        let mut calls: Vec<_> = profile
+            .functions
+            .iter()
+            .map(|function| (function.name.as_str(), function.calls))
+            .collect();
+        calls.sort();
+        assert_eq!(
+            calls,
+            vec![
+                ("<program>", 1),
+                ("builtin len", 4),
+                ("builtin rest", 3),
+                ("count", 4),
+                ("fib", 177),
+            ]
+        );
+        for function in &profile.functions {
+            assert!(function.exclusive <= function.inclusive, "{:?}", function);
+        }
+
+        let stacks: Vec<_> = profile.stacks.keys().map(String::as_str).collect();
+        assert_eq!(
+            stacks,
+            vec![
+                "<program>",
+                "<program>;count",
+                "<program>;count;builtin len",
+                "<program>;count;builtin rest",
+                "<program>;fib",
+                "<program>;fib;fib",
+                "<program>;fib;fib;fib",
+                "<program>;fib;fib;fib;fib",
+                "<program>;fib;fib;fib;fib;fib",
+                "<program>;fib;fib;fib;fib;fib;fib",
+                "<program>;fib;fib;fib;fib;fib;fib;fib",
+                "<program>;fib;fib;fib;fib;fib;fib;fib;fib",
+                "<program>;fib;fib;fib;fib;fib;fib;fib;fib;fib",
+                "<program>;fib;fib;fib;fib;fib;fib;fib;fib;fib;fib",
+            ]
+        );
+        assert!(profile.collapsed().starts_with("<program> "));
+        assert!(profile.report().contains("      177  fib\n"));
+    }
+
+    #[test]
+    fn test_profiling_is_opt_in() {
+        let engine = Engine::new();
+        engine.eval("len([])").unwrap();
+        assert!(engine.profile().is_none());
+    }
+}
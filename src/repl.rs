@@ -1,27 +1,39 @@
-use crate::lexer::Lexer;
-use crate::token::TokenType;
+use monkey_rs::{Engine, Error};
 use scanner_rust::Scanner;
 use std::io::{Read, Write};
 
 static PROMPT: &str = ">>";
 
-pub(crate) fn start<I: Read, O: Write>(inpt: I, out: O) {
+pub(crate) fn start<I: Read, O: Write>(inpt: I, mut out: O, engine: Engine) {
     let mut scanner = Scanner::scan_stream(inpt);
     loop {
-        println!("{}", PROMPT);
+        writeln!(out, "{}", PROMPT).unwrap();
         let scanned = scanner.next_line().unwrap();
         if let Some(val) = scanned {
-            let mut lexer = Lexer::new(val);
-            loop {
-                let tok = lexer.next_token();
-                if tok.t_type == TokenType::EOF {
-                    break;
-                } else {
-                    println!("{{Type: {}, Literal: {}}}", tok.t_type, tok.literal);
-                }
+            match engine.eval(&val) {
+                Ok(evaluated) => writeln!(out, "{}", evaluated).unwrap(),
+                Err(err) => report(&mut out, &err),
             }
         } else {
-            continue;
+            break;
+        }
+    }
+}
+
+pub(crate) fn report<O: Write>(out: &mut O, err: &Error) {
+    match err {
+        Error::Parse(errors) => {
+            for err in errors {
+                writeln!(out, "\t{}", err).unwrap();
+            }
+        }
+        Error::Compile(err) => writeln!(out, "ERROR: {}", err).unwrap(),
+        Error::Runtime(err) => {
+            writeln!(out, "ERROR: {}", err).unwrap();
+            // errors at the top level need no trace
+            if err.backtrace.frames.len() > 1 {
+                writeln!(out, "{}", err.backtrace).unwrap();
+            }
         }
     }
 }
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SymbolScope {
+    Global,
+    Local,
+    Free,
+    // the function being compiled, referring to itself by its `let` name
+    Function,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Symbol {
+    pub(crate) name: Rc<str>,
+    pub(crate) scope: SymbolScope,
+    pub(crate) index: usize,
+}
+
+/// The names the compiler knows in one function, or at the top level, and
+/// where their values are kept at run time.
+#[derive(Debug, Default)]
+pub(crate) struct SymbolTable {
+    pub(crate) outer: Option<Box<SymbolTable>>,
+    store: HashMap<Rc<str>, Symbol>,
+    // slots for names a `let` binds later, which functions inside this one
+    // can see before then, as they may run after the `let` does
+    reserved: HashMap<Rc<str>, Symbol>,
+    pub(crate) num_definitions: usize,
+    // the variables of enclosing functions this one uses, in the order
+    // their values are captured when its closure is made
+    pub(crate) free_symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub(crate) fn new() -> Self {
+        SymbolTable::default()
+    }
+
+    pub(crate) fn new_enclosed(outer: SymbolTable) -> Self {
+        SymbolTable {
+            outer: Some(Box::new(outer)),
+            ..SymbolTable::default()
+        }
+    }
+
+    /// Binds `name` in this scope. A name bound here before keeps its slot,
+    /// as a second `let` replaces the value of the first.
+    pub(crate) fn define(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.store.get(name) {
+            if matches!(symbol.scope, SymbolScope::Global | SymbolScope::Local) {
+                return symbol.clone();
+            }
+        }
+        if let Some(symbol) = self.reserved.remove(name) {
+            self.store.insert(symbol.name.clone(), symbol.clone());
+            return symbol;
+        }
+        let scope = match self.outer {
+            Some(_) => SymbolScope::Local,
+            None => SymbolScope::Global,
+        };
+        let symbol = Symbol {
+            name: name.into(),
+            scope,
+            index: self.num_definitions,
+        };
+        self.num_definitions += 1;
+        self.store.insert(symbol.name.clone(), symbol.clone());
+        symbol
+    }
+
+    /// Gives `name` the local slot a later `define` of it binds, and lets
+    /// enclosed scopes resolve it in the meantime.
+    pub(crate) fn reserve(&mut self, name: &str) -> Symbol {
+        let symbol = Symbol {
+            name: name.into(),
+            scope: SymbolScope::Local,
+            index: self.num_definitions,
+        };
+        self.num_definitions += 1;
+        self.reserved.insert(symbol.name.clone(), symbol.clone());
+        symbol
+    }
+
+    pub(crate) fn define_function_name(&mut self, name: &str) -> Symbol {
+        let symbol = Symbol {
+            name: name.into(),
+            scope: SymbolScope::Function,
+            index: 0,
+        };
+        self.store.insert(symbol.name.clone(), symbol.clone());
+        symbol
+    }
+
+    /// Finds `name` here or in an enclosing scope. A local of an enclosing
+    /// function becomes a free variable of this one.
+    pub(crate) fn resolve(&mut self, name: &str) -> Option<Symbol> {
+        self.lookup(name, false)
+    }
+
+    /// Like `resolve`, but for an `enclosed` scope names reserved here count,
+    /// ahead of the function's own name.
+    fn lookup(&mut self, name: &str, enclosed: bool) -> Option<Symbol> {
+        if let Some(symbol) = self.reserved.get(name).filter(|_| enclosed) {
+            return Some(symbol.clone());
+        }
+        if let Some(symbol) = self.store.get(name) {
+            return Some(symbol.clone());
+        }
+        let symbol = self.outer.as_mut()?.lookup(name, true)?;
+        match symbol.scope {
+            SymbolScope::Global => Some(symbol),
+            _ => Some(self.define_free(symbol)),
+        }
+    }
+
+    /// The outermost table, where the globals are.
+    pub(crate) fn global(&mut self) -> &mut SymbolTable {
+        match self.outer {
+            Some(ref mut outer) => outer.global(),
+            None => self,
+        }
+    }
+
+    /// The names bound in this scope, by slot.
+    pub(crate) fn definitions(&self) -> Vec<Rc<str>> {
+        let mut names = vec![Rc::from(""); self.num_definitions];
+        for symbol in self.store.values() {
+            if matches!(symbol.scope, SymbolScope::Global | SymbolScope::Local) {
+                names[symbol.index] = Rc::clone(&symbol.name);
+            }
+        }
+        names
+    }
+
+    fn define_free(&mut self, original: Symbol) -> Symbol {
+        let symbol = Symbol {
+            name: original.name.clone(),
+            scope: SymbolScope::Free,
+            index: self.free_symbols.len(),
+        };
+        self.free_symbols.push(original);
+        self.store.insert(symbol.name.clone(), symbol.clone());
+        symbol
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Symbol, SymbolScope, SymbolTable};
+
+    fn symbol(name: &str, scope: SymbolScope, index: usize) -> Symbol {
+        Symbol {
+            name: name.into(),
+            scope,
+            index,
+        }
+    }
+
+    #[test]
+    fn test_define_and_resolve() {
+        let mut global = SymbolTable::new();
+        assert_eq!(global.define("a"), symbol("a", SymbolScope::Global, 0));
+        assert_eq!(global.define("b"), symbol("b", SymbolScope::Global, 1));
+        assert_eq!(global.define("a"), symbol("a", SymbolScope::Global, 0));
+        assert_eq!(global.definitions(), vec!["a".into(), "b".into()]);
+
+        let mut first = SymbolTable::new_enclosed(global);
+        first.define("c");
+        let mut second = SymbolTable::new_enclosed(first);
+        second.define("e");
+
+        let tests = vec![
+            ("a", symbol("a", SymbolScope::Global, 0)),
+            ("c", symbol("c", SymbolScope::Free, 0)),
+            ("e", symbol("e", SymbolScope::Local, 0)),
+        ];
+        for (name, expected) in tests {
+            assert_eq!(second.resolve(name), Some(expected), "{}", name);
+        }
+        assert_eq!(second.resolve("missing"), None);
+        assert_eq!(second.free_symbols, vec![symbol("c", SymbolScope::Local, 0)]);
+    }
+
+    #[test]
+    fn test_function_name_is_shadowed_by_parameters() {
+        let mut local = SymbolTable::new_enclosed(SymbolTable::new());
+        local.define_function_name("f");
+        assert_eq!(local.resolve("f"), Some(symbol("f", SymbolScope::Function, 0)));
+        local.define("f");
+        assert_eq!(local.resolve("f"), Some(symbol("f", SymbolScope::Local, 0)));
+    }
+
+    #[test]
+    fn test_reserved_names() {
+        let mut outer = SymbolTable::new_enclosed(SymbolTable::new());
+        outer.define("a");
+        assert_eq!(outer.reserve("b"), symbol("b", SymbolScope::Local, 1));
+        assert_eq!(outer.resolve("b"), None);
+
+        let mut inner = SymbolTable::new_enclosed(outer);
+        assert_eq!(inner.resolve("b"), Some(symbol("b", SymbolScope::Free, 0)));
+        let mut outer = *inner.outer.take().unwrap();
+        assert_eq!(outer.define("b"), symbol("b", SymbolScope::Local, 1));
+        assert_eq!(outer.resolve("b"), Some(symbol("b", SymbolScope::Local, 1)));
+        assert_eq!(outer.num_definitions, 2);
+    }
+}
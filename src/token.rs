@@ -24,13 +24,14 @@ pub(crate) fn lookup_ident(ident: String) -> TokenType {
         TokenType::IDENT
     }
 }
-#[allow(dead_code, non_camel_case_types)]
+#[allow(dead_code, non_camel_case_types, clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Clone)]
 pub(crate) enum TokenType {
     ILLEGAL,
     EOF,
     IDENT,
     INT,
+    STRING,
     ASSIGN,
     PLUS,
     MINUS,
@@ -45,6 +46,9 @@ pub(crate) enum TokenType {
     LPAREN,
     RBRACE,
     LBRACE,
+    LBRACKET,
+    RBRACKET,
+    COLON,
     FUNCTION,
     LET,
     TRUE,
@@ -54,6 +58,7 @@ pub(crate) enum TokenType {
     RETURN,
     EQ,
     NOT_EQ,
+    COMMENT,
 }
 
 impl Display for TokenType {
@@ -64,6 +69,7 @@ impl Display for TokenType {
             EOF => write!(f, "EOF"),
             IDENT => write!(f, "IDENT"),
             INT => write!(f, "INT"),
+            STRING => write!(f, "STRING"),
             ASSIGN => write!(f, "ASSIGN"),
             PLUS => write!(f, "PLUS"),
             MINUS => write!(f, "MINUS"),
@@ -78,6 +84,9 @@ impl Display for TokenType {
             LPAREN => write!(f, "LPAREN"),
             RBRACE => write!(f, "RBRACE"),
             LBRACE => write!(f, "LBRACE"),
+            LBRACKET => write!(f, "LBRACKET"),
+            RBRACKET => write!(f, "RBRACKET"),
+            COLON => write!(f, "COLON"),
             FUNCTION => write!(f, "FUNCTION"),
             LET => write!(f, "LET"),
             IF => write!(f, "IF"),
@@ -87,14 +96,29 @@ impl Display for TokenType {
             RETURN => write!(f, "RETURN"),
             EQ => write!(f, "EQ"),
             NOT_EQ => write!(f, "NOT_EQ"),
+            COMMENT => write!(f, "COMMENT"),
         }
     }
 }
 
+/// Position of a token in the source, both 1-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct Token {
     pub(crate) t_type: TokenType,
     pub(crate) literal: String,
+    pub(crate) span: Span,
 }
 
 impl Default for Token {
@@ -102,6 +126,7 @@ impl Default for Token {
         Token {
             t_type: TokenType::EOF,
             literal: "".to_string(),
+            span: Span::default(),
         }
     }
 }
@@ -111,6 +136,7 @@ impl Token {
         Token {
             t_type,
             literal: lit.into(),
+            span: Span::default(),
         }
     }
 }
@@ -0,0 +1,164 @@
+use crate::object::{Object, RuntimeError};
+use crate::token::Span;
+use std::io::Write;
+
+/// Told what the evaluator does as it does it, to trace, log or visualize
+/// programs without changing the evaluator. Every method does nothing
+/// unless implemented.
+pub trait Observer {
+    /// Before each statement runs.
+    fn statement(&mut self, _span: Span) {}
+
+    /// After `expression`, printed the way the parser reads it, evaluated
+    /// to `value`. Expressions that fail or return are not reported.
+    fn expression(&mut self, _expression: &str, _span: Span, _value: &Object) {}
+
+    /// Before a function, builtin or host function is called. A tail call
+    /// is reported as a call made by the function it replaces.
+    fn call(&mut self, _function: &str, _args: &[Object], _span: Span) {}
+
+    /// When the innermost call not yet returned returns `value`, an error
+    /// if it failed. Every `call` has one.
+    fn returned(&mut self, _value: &Object) {}
+
+    /// When an error is raised, once, before the calls it ends return.
+    fn error(&mut self, _error: &RuntimeError) {}
+}
+
+/// Prints every expression evaluated and its value, indented by the calls
+/// in progress.
+pub struct Tracer<W> {
+    out: W,
+    depth: usize,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W) -> Self {
+        Tracer { out, depth: 0 }
+    }
+
+    fn line(&mut self, line: String) {
+        writeln!(self.out, "{:indent$}{}", "", line, indent = self.depth * 2).unwrap();
+    }
+}
+
+impl<W: Write> Observer for Tracer<W> {
+    fn expression(&mut self, expression: &str, span: Span, value: &Object) {
+        self.line(format!("{} {} => {}", span, expression, value));
+    }
+
+    fn call(&mut self, function: &str, args: &[Object], span: Span) {
+        let args: Vec<_> = args.iter().map(|arg| arg.to_string()).collect();
+        self.line(format!("{} call {}({})", span, function, args.join(", ")));
+        self.depth += 1;
+    }
+
+    fn returned(&mut self, value: &Object) {
+        self.depth = self.depth.saturating_sub(1);
+        if !matches!(value, Object::Error(_)) {
+            self.line(format!("return {}", value));
+        }
+    }
+
+    fn error(&mut self, error: &RuntimeError) {
+        self.line(format!("error: {}", error));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Observer, Tracer};
+    use crate::engine::Engine;
+    use crate::object::{Object, RuntimeError};
+    use crate::token::Span;
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tracer() {
+        let out = Shared::default();
+        let engine = Engine::new().with_observer(Tracer::new(out.clone()));
+        engine.eval("let double = fn(x) { x * 2 };\ndouble(len([1, 2]))").unwrap();
+        let _ = engine.eval("first(1)");
+
+        let expected = "\
+1:14 fn(x) (x * 2) => fn(x) { (x * 2) }
+2:1 double => fn(x) { (x * 2) }
+2:8 len => builtin function len
+2:13 1 => 1
+2:16 2 => 2
+2:12 [1, 2] => [1, 2]
+2:8 call builtin len([1, 2])
+return 2
+2:8 len([1, 2]) => 2
+2:1 call double(2)
+  1:22 x => 2
+  1:26 2 => 2
+  1:22 (x * 2) => 4
+return 4
+2:1 double(len([1, 2])) => 4
+1:1 first => builtin function first
+1:7 1 => 1
+1:1 call builtin first(1)
+  error: argument to `first` not supported, got INTEGER at 1:1
+";
+        assert_eq!(String::from_utf8(out.0.take()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_observer_events() {
+        struct Events(Rc<RefCell<Vec<String>>>);
+
+        impl Observer for Events {
+            fn statement(&mut self, span: Span) {
+                self.0.borrow_mut().push(format!("statement {}", span));
+            }
+
+            fn call(&mut self, function: &str, _args: &[Object], _span: Span) {
+                self.0.borrow_mut().push(format!("call {}", function));
+            }
+
+            fn returned(&mut self, value: &Object) {
+                self.0.borrow_mut().push(format!("returned {}", value));
+            }
+
+            fn error(&mut self, error: &RuntimeError) {
+                self.0.borrow_mut().push(format!("error {}", error.message));
+            }
+        }
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let engine = Engine::new().with_observer(Events(Rc::clone(&events)));
+        let program = "let down = fn(n) { if (n == 0) { 1 / n } else { down(n - 1) } };\ndown(1)";
+        assert!(engine.eval(program).is_err());
+
+        let expected = vec![
+            "statement 1:1",
+            "statement 2:1",
+            "call down",
+            "statement 1:20",
+            "statement 1:49",
+            "call down",
+            "statement 1:20",
+            "statement 1:34",
+            "error division by zero",
+            "returned ERROR: division by zero at 1:34",
+            "returned ERROR: division by zero at 1:34",
+        ];
+        assert_eq!(*events.borrow(), expected);
+    }
+}
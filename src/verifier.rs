@@ -0,0 +1,389 @@
+use crate::code::{read_operands, Opcode};
+use crate::compiler::Bytecode;
+use crate::object::{CompiledFunction, Object};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// `OpGetLocal` and `OpSetLocal` have a byte for the slot.
+const MAX_LOCALS: usize = 256;
+
+/// Checks that the VM can run `bytecode` without reading past its
+/// instructions, constants, globals, locals, captured variables or stack,
+/// as bytecode loaded from a file might. Returns what is wrong, and where,
+/// in the first function that fails.
+pub(crate) fn verify(bytecode: &Bytecode) -> Result<(), String> {
+    let main = Function::decode("<program>", &bytecode.main)?;
+    let mut functions = Vec::with_capacity(bytecode.constants.len());
+    for constant in &bytecode.constants {
+        functions.push(match constant {
+            Object::CompiledFunction(func) => {
+                let name = func.name.as_deref().unwrap_or("<anonymous>");
+                Some(Function::decode(name, func)?)
+            }
+            _ => None,
+        });
+    }
+    let verifier = Verifier { bytecode, functions: &functions };
+    verifier.check(&main, true)?;
+    for function in functions.iter().flatten() {
+        verifier.check(function, false)?;
+    }
+    Ok(())
+}
+
+struct Instruction {
+    start: usize,
+    op: Opcode,
+    operands: [usize; 2],
+}
+
+/// A function split into instructions.
+struct Function<'a> {
+    name: &'a str,
+    func: &'a CompiledFunction,
+    instructions: Vec<Instruction>,
+    // one more than the highest captured variable it reads
+    num_free: usize,
+}
+
+impl<'a> Function<'a> {
+    fn decode(name: &'a str, func: &'a CompiledFunction) -> Result<Self, String> {
+        let mut function = Function { name, func, instructions: Vec::new(), num_free: 0 };
+        let ins = &func.instructions;
+        let mut start = 0;
+        while start < ins.len() {
+            let op = match Opcode::try_from(ins[start]) {
+                Ok(op) => op,
+                Err(byte) => return Err(function.error(start, format!("unknown opcode {}", byte))),
+            };
+            let definition = op.definition();
+            let width: usize = definition.operand_widths.iter().sum();
+            if start + width >= ins.len() {
+                return Err(function.error(start, format!("{} is cut short", definition.name)));
+            }
+            let (operands, _) = read_operands(&definition, &ins[start + 1..]);
+            if let Opcode::GetFree | Opcode::GetFreeCell = op {
+                function.num_free = function.num_free.max(operands[0] + 1);
+            }
+            function.instructions.push(Instruction { start, op, operands });
+            start += 1 + width;
+        }
+        Ok(function)
+    }
+
+    fn error(&self, start: usize, message: String) -> String {
+        format!("{} at {:04}: {}", self.name, start, message)
+    }
+}
+
+struct Verifier<'a> {
+    bytecode: &'a Bytecode,
+    // the functions among the constants, at their indexes
+    functions: &'a [Option<Function<'a>>],
+}
+
+impl Verifier<'_> {
+    fn check(&self, function: &Function, is_main: bool) -> Result<(), String> {
+        let func = function.func;
+        let end = func.instructions.len();
+        if is_main && func.num_locals > 0 {
+            return Err(format!("{}: the program has locals", function.name));
+        }
+        if func.num_locals > MAX_LOCALS || func.num_parameters > func.num_locals {
+            let message = format!(
+                "{} locals for {} parameters",
+                func.num_locals, func.num_parameters
+            );
+            return Err(format!("{}: {}", function.name, message));
+        }
+
+        let mut starts = vec![false; end + 1];
+        for instruction in &function.instructions {
+            starts[instruction.start] = true;
+        }
+        // the end too, where the program stops
+        starts[end] = is_main;
+
+        // the depth of the stack, above the locals, at the offsets jumped to
+        let mut jumps: BTreeMap<usize, usize> = BTreeMap::new();
+        // none after a jump or return, until an instruction jumped to
+        let mut depth = Some(0);
+        for instruction in &function.instructions {
+            let start = instruction.start;
+            let error = |message: String| function.error(start, message);
+            let name = instruction.op.definition().name;
+            let [operand, count] = instruction.operands;
+
+            match instruction.op {
+                Opcode::Constant => match self.bytecode.constants.get(operand) {
+                    None => return Err(error(format!("no constant {}", operand))),
+                    Some(Object::CompiledFunction(_)) => {
+                        return Err(error(format!("constant {} is a function", operand)))
+                    }
+                    Some(_) => {}
+                },
+                Opcode::Closure => match self.functions.get(operand) {
+                    Some(Some(closed)) if count >= closed.num_free => {}
+                    Some(Some(closed)) => {
+                        let message = format!(
+                            "too few captured variables for {} ({} of {})",
+                            closed.name, count, closed.num_free
+                        );
+                        return Err(error(message));
+                    }
+                    _ => return Err(error(format!("constant {} is not a function", operand))),
+                },
+                Opcode::GetGlobal | Opcode::SetGlobal if operand >= self.bytecode.globals.len() => {
+                    return Err(error(format!("no global {}", operand)));
+                }
+                Opcode::GetLocal | Opcode::SetLocal | Opcode::MakeCell | Opcode::GetLocalCell
+                    if operand >= func.num_locals =>
+                {
+                    return Err(error(format!("no local {}", operand)));
+                }
+                // the name of the variable, for when it is not bound yet
+                Opcode::MakeCell => match self.bytecode.constants.get(count) {
+                    Some(Object::String(_)) => {}
+                    _ => return Err(error(format!("constant {} is not a name", count))),
+                },
+                Opcode::GetFree | Opcode::GetFreeCell | Opcode::CurrentClosure if is_main => {
+                    return Err(error(format!("{} outside a function", name)));
+                }
+                Opcode::Jump | Opcode::JumpNotTruthy
+                    if operand <= start || starts.get(operand) != Some(&true) =>
+                {
+                    // jumps only go forward, so a function cannot loop
+                    return Err(error(format!("jump to {:04}, not a later instruction", operand)));
+                }
+                Opcode::Hash if operand % 2 == 1 => {
+                    return Err(error("an odd number of hash keys and values".to_string()));
+                }
+                _ => {}
+            }
+
+            depth = merge(depth, jumps.remove(&start)).map_err(error)?;
+            let before = match depth {
+                Some(depth) => depth,
+                // no path runs this
+                None => continue,
+            };
+            let (pops, pushes) = stack_effect(instruction);
+            if before < pops {
+                return Err(error(format!("{} on a stack {} deep", name, before)));
+            }
+            let after = before - pops + pushes;
+            if let Opcode::Jump | Opcode::JumpNotTruthy = instruction.op {
+                let jumped = jumps.entry(operand).or_insert(after);
+                if *jumped != after {
+                    let message = format!("jump to {:04} with a different stack depth", operand);
+                    return Err(error(message));
+                }
+            }
+            depth = match instruction.op {
+                Opcode::Jump | Opcode::ReturnValue | Opcode::Return => None,
+                _ => Some(after),
+            };
+        }
+
+        let depth = merge(depth, jumps.remove(&end)).map_err(|m| function.error(end, m))?;
+        if depth.is_some() && !is_main {
+            return Err(function.error(end, "it ends without returning".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// The stack depth where the previous instruction and the jumps to an
+/// instruction meet, which must be the same on every path.
+fn merge(depth: Option<usize>, jumped: Option<usize>) -> Result<Option<usize>, String> {
+    match (depth, jumped) {
+        (Some(depth), Some(jumped)) if depth != jumped => Err(format!(
+            "the stack is {} deep on one path here and {} on another",
+            depth, jumped
+        )),
+        (None, jumped) => Ok(jumped),
+        (depth, _) => Ok(depth),
+    }
+}
+
+/// How many values the instruction takes from the stack and how many it
+/// leaves there.
+fn stack_effect(instruction: &Instruction) -> (usize, usize) {
+    let [operand, count] = instruction.operands;
+    match instruction.op {
+        Opcode::Constant
+        | Opcode::True
+        | Opcode::False
+        | Opcode::Null
+        | Opcode::GetGlobal
+        | Opcode::GetLocal
+        | Opcode::GetFree
+        | Opcode::GetLocalCell
+        | Opcode::GetFreeCell
+        | Opcode::CurrentClosure => (0, 1),
+        Opcode::Pop
+        | Opcode::SetGlobal
+        | Opcode::SetLocal
+        | Opcode::JumpNotTruthy
+        | Opcode::ReturnValue => (1, 0),
+        Opcode::Add
+        | Opcode::Sub
+        | Opcode::Mul
+        | Opcode::Div
+        | Opcode::Equal
+        | Opcode::NotEqual
+        | Opcode::LessThan
+        | Opcode::GreaterThan
+        | Opcode::Index => (2, 1),
+        Opcode::Minus | Opcode::Bang | Opcode::HashKey => (1, 1),
+        Opcode::Jump | Opcode::Return | Opcode::MakeCell => (0, 0),
+        Opcode::Array | Opcode::Hash => (operand, 1),
+        // the function and its arguments
+        Opcode::Call => (operand + 1, 1),
+        Opcode::Closure => (count, 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verify;
+    use crate::code::{make, Opcode};
+    use crate::compiler::compile;
+    use crate::object::Object;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_accepts_compiled_code() {
+        let source = r#"
+let fib = fn(n) { if (n < 2) { return n; } fib(n - 1) + fib(n - 2) };
+let adder = fn(a) { fn(b) { let c = a + b; c } };
+let h = {"a": [1, 2], true: if (false) { 1 }};
+puts(fib(10), adder(1)(2), h["a"][0], -1, !true)"#;
+        assert_eq!(verify(&compile(source).unwrap()), Ok(()));
+    }
+
+    #[test]
+    fn test_rejects_invalid_code() {
+        use Opcode::*;
+        // code put in place of the program's, or of the function's, in
+        // `let x = fn(a) { a }; x(1); y`, with the globals `x` and `y`
+        let tests: Vec<(Vec<u8>, Vec<u8>, &str)> = vec![
+            (
+                vec![42],
+                vec![],
+                "<program> at 0000: unknown opcode 42",
+            ),
+            (
+                [make(Constant, &[1]), vec![Jump as u8, 0]].concat(),
+                vec![],
+                "<program> at 0003: OpJump is cut short",
+            ),
+            (
+                [make(Constant, &[7]), make(Pop, &[])].concat(),
+                vec![],
+                "<program> at 0000: no constant 7",
+            ),
+            (
+                [make(Constant, &[0]), make(Pop, &[])].concat(),
+                vec![],
+                "<program> at 0000: constant 0 is a function",
+            ),
+            (
+                [make(Closure, &[1, 0]), make(Pop, &[])].concat(),
+                vec![],
+                "<program> at 0000: constant 1 is not a function",
+            ),
+            (
+                [make(GetGlobal, &[2]), make(Pop, &[])].concat(),
+                vec![],
+                "<program> at 0000: no global 2",
+            ),
+            (
+                [make(GetLocal, &[0]), make(Pop, &[])].concat(),
+                vec![],
+                "<program> at 0000: no local 0",
+            ),
+            (
+                [make(CurrentClosure, &[]), make(Pop, &[])].concat(),
+                vec![],
+                "<program> at 0000: OpCurrentClosure outside a function",
+            ),
+            (
+                make(Jump, &[0]),
+                vec![],
+                "<program> at 0000: jump to 0000, not a later instruction",
+            ),
+            (
+                [make(Jump, &[2]), make(Pop, &[])].concat(),
+                vec![],
+                "<program> at 0000: jump to 0002, not a later instruction",
+            ),
+            (
+                make(Pop, &[]),
+                vec![],
+                "<program> at 0000: OpPop on a stack 0 deep",
+            ),
+            (
+                [make(True, &[]), make(Call, &[1])].concat(),
+                vec![],
+                "<program> at 0001: OpCall on a stack 1 deep",
+            ),
+            (
+                [make(True, &[]), make(True, &[]), make(Hash, &[1])].concat(),
+                vec![],
+                "<program> at 0002: an odd number of hash keys and values",
+            ),
+            (
+                [
+                    make(True, &[]),
+                    make(JumpNotTruthy, &[5]),
+                    make(Null, &[]),
+                    make(Pop, &[]),
+                ]
+                .concat(),
+                vec![],
+                "<program> at 0005: the stack is 1 deep on one path here and 0 on another",
+            ),
+            (
+                vec![],
+                [make(GetLocal, &[1]), make(ReturnValue, &[])].concat(),
+                "x at 0000: no local 1",
+            ),
+            (
+                vec![],
+                [make(GetLocal, &[0]), make(Pop, &[])].concat(),
+                "x at 0003: it ends without returning",
+            ),
+            (
+                vec![],
+                [make(MakeCell, &[0, 1]), make(Return, &[])].concat(),
+                "x at 0000: constant 1 is not a name",
+            ),
+            (
+                [make(GetFreeCell, &[0]), make(Pop, &[])].concat(),
+                vec![],
+                "<program> at 0000: OpGetFreeCell outside a function",
+            ),
+            (
+                vec![],
+                [make(GetFree, &[0]), make(ReturnValue, &[])].concat(),
+                "<program> at 0000: too few captured variables for x (0 of 1)",
+            ),
+        ];
+        for (main, func, expected) in tests {
+            let mut bytecode = compile("let x = fn(a) { a }; x(1); y").unwrap();
+            if !main.is_empty() {
+                Rc::make_mut(&mut bytecode.main).instructions = main;
+            }
+            if !func.is_empty() {
+                match &mut bytecode.constants[0] {
+                    Object::CompiledFunction(f) => {
+                        Rc::make_mut(f).instructions = func
+                    }
+                    constant => panic!("not a function: {}", constant),
+                }
+            }
+            assert_eq!(verify(&bytecode), Err(expected.to_string()));
+        }
+    }
+}
@@ -0,0 +1,698 @@
+use crate::code::{read_operands, read_u16, Opcode};
+use crate::compiler::Bytecode;
+use crate::evaluator::{error, eval_index_expression, unusable_as_hash_key, Evaluator};
+use crate::gc;
+use crate::object::{Backtrace, Closure, ErrorKind, HashPair, Object, TraceFrame, Variable};
+use crate::token::Span;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::mem;
+use std::rc::Rc;
+
+/// The span given to the evaluator's operators, which use it only for
+/// errors; the VM looks up the real one when there is an error.
+const NOWHERE: Span = Span { line: 0, column: 0 };
+
+/// A call in progress.
+struct Frame {
+    closure: Rc<Closure>,
+    // the offset of the next instruction
+    ip: usize,
+    // where the arguments, then the other locals, start on the stack
+    base_pointer: usize,
+}
+
+/// Runs bytecode with the integer mode and host functions of an evaluator,
+/// and its operators, so that results and errors are the evaluator's.
+pub(crate) struct Vm<'a> {
+    evaluator: &'a Evaluator,
+    constants: &'a [Object],
+    global_names: &'a [Rc<str>],
+    globals: Vec<Option<Object>>,
+    stack: Vec<Object>,
+    frames: Vec<Frame>,
+    // the value of the last expression statement, which is the program's
+    last_popped: Object,
+}
+
+impl<'a> Vm<'a> {
+    pub(crate) fn new(bytecode: &'a Bytecode, evaluator: &'a Evaluator) -> Self {
+        let main = Closure {
+            func: Rc::clone(&bytecode.main),
+            free: Vec::new(),
+        };
+        Vm {
+            evaluator,
+            constants: &bytecode.constants,
+            global_names: &bytecode.globals,
+            globals: vec![None; bytecode.globals.len()],
+            stack: Vec::new(),
+            frames: vec![Frame {
+                closure: Rc::new(main),
+                ip: 0,
+                base_pointer: 0,
+            }],
+            last_popped: Object::Null,
+        }
+    }
+
+    /// Runs the program to its end or to a top-level `return`, and returns
+    /// its value or the error that stopped it.
+    pub(crate) fn run(&mut self) -> Object {
+        self.evaluator.start();
+        let result = match self.execute() {
+            Ok(value) => value,
+            Err(mut err) => {
+                if let Object::Error(err) = &mut err {
+                    err.backtrace = self.backtrace(err.span);
+                }
+                err
+            }
+        };
+        self.evaluator.finish();
+        result
+    }
+
+    fn execute(&mut self) -> Result<Object, Object> {
+        while let Some((op, operands, start)) = self.fetch() {
+            // the limits, timeout and cancellation of the evaluator
+            if let Err(err) = self.evaluator.step(NOWHERE) {
+                return self.at(err, start);
+            }
+            match op {
+                Opcode::Constant => {
+                    let value = match &self.constants[operands[0]] {
+                        Object::BigInteger(value) => {
+                            let value = self.evaluator.eval_integer_literal(value, NOWHERE);
+                            self.at(value, start)?
+                        }
+                        constant => constant.clone(),
+                    };
+                    self.push(value);
+                }
+                Opcode::Pop => self.last_popped = self.pop(),
+                Opcode::Add
+                | Opcode::Sub
+                | Opcode::Mul
+                | Opcode::Div
+                | Opcode::Equal
+                | Opcode::NotEqual
+                | Opcode::LessThan
+                | Opcode::GreaterThan => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let result =
+                        self.evaluator.eval_infix_expression(operator(op), left, right, NOWHERE);
+                    let result = self.at(result, start)?;
+                    self.push(result);
+                }
+                Opcode::Minus | Opcode::Bang => {
+                    let right = self.pop();
+                    let result =
+                        self.evaluator.eval_prefix_expression(operator(op), right, NOWHERE);
+                    let result = self.at(result, start)?;
+                    self.push(result);
+                }
+                Opcode::True => self.push(Object::Boolean(true)),
+                Opcode::False => self.push(Object::Boolean(false)),
+                Opcode::Null => self.push(Object::Null),
+                Opcode::JumpNotTruthy => {
+                    if !self.pop().is_truthy() {
+                        self.frame_mut().ip = operands[0];
+                    }
+                }
+                Opcode::Jump => self.frame_mut().ip = operands[0],
+                Opcode::GetGlobal => {
+                    let value = match &self.globals[operands[0]] {
+                        Some(value) => value.clone(),
+                        None => self.predefined(&self.global_names[operands[0]], start)?,
+                    };
+                    self.push(value);
+                }
+                Opcode::SetGlobal => {
+                    self.globals[operands[0]] = Some(self.pop());
+                    // a `let` is the last statement so far
+                    self.last_popped = Object::Null;
+                }
+                Opcode::GetLocal => {
+                    let value = &self.stack[self.frame().base_pointer + operands[0]];
+                    let value = self.load(value, start)?;
+                    self.push(value);
+                }
+                Opcode::SetLocal => {
+                    let slot = self.frame().base_pointer + operands[0];
+                    let value = self.pop();
+                    match &self.stack[slot] {
+                        Object::Cell(variable) => *variable.value.borrow_mut() = Some(value),
+                        _ => self.stack[slot] = value,
+                    }
+                }
+                Opcode::GetFree => {
+                    let value = self.load(&self.frame().closure.free[operands[0]], start)?;
+                    self.push(value);
+                }
+                Opcode::MakeCell => {
+                    self.charge(mem::size_of::<Variable>(), start)?;
+                    let slot = self.frame().base_pointer + operands[0];
+                    let name = match &self.constants[operands[1]] {
+                        Object::String(name) => Rc::clone(name),
+                        _ => unreachable!("the verifier checked the name is a string"),
+                    };
+                    // a parameter is bound already, a `let` not yet
+                    let bound = operands[0] < self.frame().closure.func.num_parameters;
+                    let value = mem::replace(&mut self.stack[slot], Object::Null);
+                    let value = RefCell::new(Some(value).filter(|_| bound));
+                    let cell = Rc::new(Variable { name, value });
+                    gc::track_cell(&cell);
+                    self.stack[slot] = Object::Cell(cell);
+                }
+                Opcode::GetLocalCell => {
+                    let value = self.stack[self.frame().base_pointer + operands[0]].clone();
+                    self.push(value);
+                }
+                Opcode::GetFreeCell => {
+                    let value = self.frame().closure.free[operands[0]].clone();
+                    self.push(value);
+                }
+                Opcode::CurrentClosure => {
+                    let closure = Rc::clone(&self.frame().closure);
+                    self.push(Object::Closure(closure));
+                }
+                Opcode::Array => {
+                    let elements = self.stack.split_off(self.stack.len() - operands[0]);
+                    let array = self.allocate(Object::Array(Rc::new(elements)), start)?;
+                    self.push(array);
+                }
+                Opcode::HashKey => {
+                    let key = &self.stack[self.stack.len() - 1];
+                    if key.hash_key().is_none() {
+                        return self.at(unusable_as_hash_key(key, NOWHERE), start);
+                    }
+                }
+                Opcode::Hash => {
+                    let items = self.stack.split_off(self.stack.len() - operands[0]);
+                    let mut items = items.into_iter();
+                    let mut pairs = BTreeMap::new();
+                    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                        let hash_key = match key.hash_key() {
+                            Some(hash_key) => hash_key,
+                            None => return self.at(unusable_as_hash_key(&key, NOWHERE), start),
+                        };
+                        pairs.insert(hash_key, HashPair { key, value });
+                    }
+                    let hash = self.allocate(Object::Hash(Rc::new(pairs)), start)?;
+                    self.push(hash);
+                }
+                Opcode::Index => {
+                    let index = self.pop();
+                    let left = self.pop();
+                    let result = self.allocate(eval_index_expression(left, index, NOWHERE), start)?;
+                    self.push(result);
+                }
+                Opcode::Call => self.call(operands[0], start)?,
+                Opcode::ReturnValue => {
+                    let value = self.pop();
+                    if let Some(value) = self.return_value(value) {
+                        return Ok(value);
+                    }
+                }
+                Opcode::Return => {
+                    if let Some(value) = self.return_value(Object::Null) {
+                        return Ok(value);
+                    }
+                }
+                Opcode::Closure => {
+                    let func = match &self.constants[operands[0]] {
+                        Object::CompiledFunction(func) => Rc::clone(func),
+                        constant => unreachable!("not a function: {}", constant.type_name()),
+                    };
+                    let size = mem::size_of::<Closure>() + operands[1] * mem::size_of::<Object>();
+                    self.charge(size, start)?;
+                    let free = self.stack.split_off(self.stack.len() - operands[1]);
+                    self.push(Object::Closure(Rc::new(Closure { func, free })));
+                }
+            }
+        }
+        Ok(mem::replace(&mut self.last_popped, Object::Null))
+    }
+
+    /// Decodes the next instruction of the current function and moves past
+    /// it, or returns `None` at the end of the program. Returns the opcode,
+    /// its operands and its offset.
+    fn fetch(&mut self) -> Option<(Opcode, [usize; 2], usize)> {
+        let frame = self.frames.last_mut().unwrap();
+        let ins = &frame.closure.func.instructions;
+        let start = frame.ip;
+        let op = Opcode::try_from(*ins.get(start)?).expect("compiled code has known opcodes");
+        let (operands, width) = read_operands(&op.definition(), &ins[start + 1..]);
+        frame.ip = start + 1 + width;
+        Some((op, operands, start))
+    }
+
+    fn call(&mut self, num_args: usize, start: usize) -> Result<(), Object> {
+        // a call whose value the caller returns takes the caller's place,
+        // so tail recursion runs in constant space
+        let tail = self.frames.len() > 1 && self.returns_next();
+        let max = self.evaluator.max_call_depth();
+        // the program's frame is not a call
+        if !tail && self.frames.len() > max {
+            let message = format!("call depth limit of {} exceeded", max);
+            return Err(self.error(ErrorKind::LimitExceeded, message, start));
+        }
+        let callee = self.stack.len() - 1 - num_args;
+        let closure = match &self.stack[callee] {
+            Object::Closure(closure) => Rc::clone(closure),
+            Object::Builtin(builtin) => {
+                let builtin = *builtin;
+                let args = self.stack.split_off(callee + 1);
+                self.stack.pop();
+                let result = self.allocate((builtin.func)(&args, NOWHERE), start)?;
+                self.push(result);
+                return Ok(());
+            }
+            Object::Host(host) => {
+                let host = host.clone();
+                let args = self.stack.split_off(callee + 1);
+                self.stack.pop();
+                let result = self.allocate(host.call(&args, NOWHERE), start)?;
+                self.push(result);
+                return Ok(());
+            }
+            other => {
+                let message = format!("not a function: {}", other.type_name());
+                return Err(self.error(ErrorKind::NotCallable, message, start));
+            }
+        };
+        let func = &closure.func;
+        if num_args != func.num_parameters {
+            let message = format!(
+                "wrong number of arguments: want={}, got={}",
+                func.num_parameters, num_args
+            );
+            return Err(self.error(ErrorKind::WrongArguments, message, start));
+        }
+
+        let mut base_pointer = callee + 1;
+        if tail {
+            let caller = self.frames.pop().unwrap();
+            self.stack.drain(caller.base_pointer - 1..callee);
+            base_pointer = caller.base_pointer;
+        }
+        self.stack.resize(base_pointer + func.num_locals, Object::Null);
+        self.frames.push(Frame {
+            closure,
+            ip: 0,
+            base_pointer,
+        });
+        Ok(())
+    }
+
+    /// Whether the current function returns the value of the call it just
+    /// made, after any jumps out of the `if` the call is in.
+    fn returns_next(&self) -> bool {
+        let frame = self.frame();
+        let ins = &frame.closure.func.instructions;
+        let mut ip = frame.ip;
+        loop {
+            match ins.get(ip).map(|op| Opcode::try_from(*op)) {
+                Some(Ok(Opcode::ReturnValue)) => return true,
+                Some(Ok(Opcode::Jump)) => ip = read_u16(&ins[ip + 1..]),
+                _ => return false,
+            }
+        }
+    }
+
+    /// Returns `value` to the caller of the current function, or returns it
+    /// from here if the program itself is returning.
+    fn return_value(&mut self, value: Object) -> Option<Object> {
+        if self.frames.len() == 1 {
+            return Some(value);
+        }
+        let frame = self.frames.pop().unwrap();
+        // the callee goes too
+        self.stack.truncate(frame.base_pointer - 1);
+        self.push(value);
+        None
+    }
+
+    /// The value of a local or captured variable, which a cell holds if
+    /// closures share it.
+    fn load(&self, value: &Object, start: usize) -> Result<Object, Object> {
+        let variable = match value {
+            Object::Cell(variable) => variable,
+            value => return Ok(value.clone()),
+        };
+        if let Some(value) = &*variable.value.borrow() {
+            return Ok(value.clone());
+        }
+        // not bound yet, so the evaluator would look further out
+        let global = self.global_names.iter().position(|name| *name == variable.name);
+        match global.and_then(|index| self.globals[index].as_ref()) {
+            Some(value) => Ok(value.clone()),
+            None => self.predefined(&variable.name, start),
+        }
+    }
+
+    /// The host function or builtin named `name`, as the evaluator would
+    /// find it for an unset global.
+    fn predefined(&self, name: &str, start: usize) -> Result<Object, Object> {
+        match self.evaluator.predefined(name) {
+            Some(value) => Ok(value),
+            None => {
+                let message = format!("identifier not found: {}", name);
+                Err(self.error(ErrorKind::UnknownIdentifier, message, start))
+            }
+        }
+    }
+
+    /// Charges the memory limit for `obj`, as the evaluator would, and
+    /// passes it on like `at`.
+    fn allocate(&self, obj: Object, start: usize) -> Result<Object, Object> {
+        self.at(self.evaluator.allocate(obj, NOWHERE), start)
+    }
+
+    /// Counts `size` bytes against the memory limit, as `allocate` does.
+    fn charge(&self, size: usize, start: usize) -> Result<(), Object> {
+        match self.evaluator.charge(size, NOWHERE) {
+            Ok(()) => Ok(()),
+            Err(err) => self.at(err, start).map(|_| ()),
+        }
+    }
+
+    /// Passes on `result` unless it is an error, which gets the position
+    /// of the instruction at `start` in the current function.
+    fn at(&self, result: Object, start: usize) -> Result<Object, Object> {
+        match result {
+            Object::Error(mut err) => {
+                err.span = self.position(start);
+                Err(Object::Error(err))
+            }
+            value => Ok(value),
+        }
+    }
+
+    fn error(&self, kind: ErrorKind, message: String, start: usize) -> Object {
+        error(kind, message, self.position(start))
+    }
+
+    fn position(&self, offset: usize) -> Span {
+        self.frame().closure.func.position(offset)
+    }
+
+    /// The calls in progress, as the evaluator reports them.
+    fn backtrace(&self, span: Span) -> Backtrace {
+        let mut frames = Vec::with_capacity(self.frames.len());
+        for (i, frame) in self.frames.iter().enumerate().rev() {
+            let function = match i {
+                0 => "<program>",
+                _ => frame.closure.func.name.as_deref().unwrap_or("<anonymous>"),
+            };
+            let span = match i + 1 == self.frames.len() {
+                true => span,
+                // the caller is at the call it made
+                false => frame.closure.func.position(frame.ip - 1),
+            };
+            frames.push(TraceFrame {
+                function: function.to_string(),
+                span,
+            });
+        }
+        Backtrace { frames }
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn push(&mut self, obj: Object) {
+        self.stack.push(obj);
+    }
+
+    fn pop(&mut self) -> Object {
+        self.stack.pop().unwrap()
+    }
+}
+
+/// The evaluator's name for the operator `op` performs.
+fn operator(op: Opcode) -> &'static str {
+    match op {
+        Opcode::Add => "+",
+        Opcode::Sub | Opcode::Minus => "-",
+        Opcode::Mul => "*",
+        Opcode::Div => "/",
+        Opcode::Equal => "==",
+        Opcode::NotEqual => "!=",
+        Opcode::LessThan => "<",
+        Opcode::GreaterThan => ">",
+        Opcode::Bang => "!",
+        _ => unreachable!("not an operator: {}", op.definition().name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::compile;
+    use crate::engine::{Engine, Error};
+    use crate::evaluator::{IntegerMode, Limits};
+    use crate::object::{ErrorKind, Object};
+    use crate::verifier::verify;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    fn run(input: &str) -> Result<Object, Error> {
+        let bytecode = compile(input)?;
+        // what the compiler makes passes as what a file holds must
+        assert_eq!(verify(&bytecode), Ok(()), "{}", input);
+        Engine::new().run(&bytecode)
+    }
+
+    /// The value or the error, span and backtrace, as text to compare.
+    fn outcome(result: Result<Object, Error>) -> String {
+        match result {
+            Ok(value) => value.to_string(),
+            Err(Error::Runtime(err)) => format!("ERROR: {}\n{}", err, err.backtrace),
+            Err(err) => panic!("not a runtime error: {}", err),
+        }
+    }
+
+    #[test]
+    fn test_same_results_as_evaluator() {
+        let tests = vec![
+            "1 + 2 * 3 - 4 / 2",
+            "-5 + 10; -(-3)",
+            "!true; !!5; !null",
+            "1 < 2 == true; 2 > 1 != false; \"a\" + \"b\" == \"ab\"",
+            "if (1 > 2) { 10 } else { 20 }",
+            "if (false) { 10 }",
+            "if (true) { let x = 1; }",
+            "let x = 5; let y = x * 2; x + y",
+            "let x = 5;",
+            "let x = 1; let x = x + 1; x",
+            "[1, 2 * 2, \"three\"][1]; [1, 2][5]; \"héllo\"[1]",
+            "{\"a\": 1, true: 2, 3: [4]}[true]; {1: 2}[5]",
+            "{\"b\": 2, \"a\": 1}",
+            "let add = fn(a, b) { a + b }; add(1, add(2, 3))",
+            "fn() { }()",
+            "fn() { let a = 1; }()",
+            "let f = fn(x) { if (x > 2) { return x * 10; } x }; [f(1), f(3)]",
+            "let newAdder = fn(a) { fn(b) { a + b } }; let addTwo = newAdder(2); addTwo(3)",
+            "let f = fn(a) { fn(b) { fn(c) { a + b + c } } }; f(1)(2)(3)",
+            "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15)",
+            "let wrap = fn() {\n\
+               let inner = fn(n) { if (n == 0) { 0 } else { inner(n - 1) } };\n\
+               inner(5)\n\
+             };\n\
+             wrap()",
+            "let map = fn(a, f) {\n\
+               let iter = fn(a, acc) {\n\
+                 if (len(a) == 0) { acc } else { iter(rest(a), push(acc, f(first(a)))) }\n\
+               };\n\
+               iter(a, [])\n\
+             };\n\
+             map([1, 2, 3], fn(x) { x * x })",
+            "let f = fn(x) { x }; f",
+            "len(\"four\"); first([1, 2]); last([1, 2]); rest([])",
+            "return 1; 2",
+            "if (true) { if (true) { return 10; } 1 }",
+            "9223372036854775807 + 1",
+            "9223372036854775808",
+            "-9223372036854775808; -9223372036854775807 - 1; -(-9223372036854775807 - 1)",
+            "-9223372036854775809",
+            "1 / 0",
+            "foobar",
+            "let x = 5; x(1)",
+            "fn(x, y) { x }(1)",
+            "first(1)",
+            "{\"a\": 1}[fn() { 1 }]",
+            "{fn() { 1 }: -true}",
+            "let h = {\"a\": 1,\n  [1]: 2}",
+            "1[0]",
+            "5 + true",
+            "\"a\" - \"b\"",
+            "let f = fn(x) { x }; f == f",
+            "let inner = fn(x) {\n  x + true\n};\n\
+             let outer = fn(x) { let y = inner(x); y * 100 };\n\
+             let viaTail = fn(x) { outer(x) };\n\
+             fn(f) { f(1) + 1 }(viaTail)",
+            "let f = fn() { g() };\nlet g = fn() { -true };\nf()",
+        ];
+
+        for input in tests {
+            let expected = outcome(Engine::new().eval(input));
+            assert_eq!(outcome(run(input)), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_hash_key_is_checked_before_its_value() {
+        let calls = Rc::new(Cell::new(0));
+        let mut engine = Engine::new();
+        let counted = Rc::clone(&calls);
+        engine.register("effect", move |_| {
+            counted.set(counted.get() + 1);
+            Ok(Object::Integer(1))
+        });
+        let input = "{1: effect(), fn() { 1 }: effect()}";
+        let expected = outcome(engine.eval(input));
+        assert_eq!(calls.get(), 1);
+        assert_eq!(outcome(engine.run(&compile(input).unwrap())), expected);
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn test_integer_mode_and_host_functions() {
+        let mut engine = Engine::new().with_integer_mode(IntegerMode::Arbitrary);
+        engine.register("double", |args| Ok(Object::Integer(args.integer(0)? * 2)));
+        engine.register("len", |_| Ok(Object::Integer(-1)));
+        let input = "let x = 9223372036854775807 + 1; [x, double(21), len([])]";
+        let bytecode = compile(input).unwrap();
+        assert_eq!(engine.run(&bytecode).unwrap().to_string(), "[9223372036854775808, 42, -1]");
+
+        // a global shadows a host function once it is bound
+        let input = "let a = double(1); let double = fn(x) { x }; [a, double(1)]";
+        let bytecode = compile(input).unwrap();
+        assert_eq!(engine.run(&bytecode).unwrap().to_string(), "[2, 1]");
+    }
+
+    #[test]
+    fn test_limits() {
+        let steps = Limits { steps: Some(10_000), ..Limits::default() };
+        let depth = Limits { call_depth: Some(100), ..Limits::default() };
+        let memory = Limits { memory: Some(1 << 20), ..Limits::default() };
+        let out_of_memory = "memory limit of 1048576 bytes exceeded";
+        let chain = "let build = fn(n, acc) {\n\
+                       if (n == 0) { 0 } else { build(n - 1, fn() { acc }) }\n\
+                     };\n\
+                     build(2000000, 0)";
+        let too_deep = "call depth limit of 100 exceeded";
+        let tests = vec![
+            (steps, "let spin = fn() { spin() }; spin()", "step limit of 10000 exceeded"),
+            (depth, "let deep = fn(n) { 1 + deep(n) }; deep(1)", too_deep),
+            (memory, r#"let grow = fn(s) { grow(s + s) }; grow("ab")"#, out_of_memory),
+            (memory, "let grow = fn(a) { grow(push(a, 1)) }; grow([])", out_of_memory),
+            // each closure keeps the one before, and the scope it was made in
+            (memory, chain, out_of_memory),
+        ];
+        for (limits, input, expected) in tests {
+            let engine = Engine::new().with_limits(limits);
+            match engine.run(&compile(input).unwrap()) {
+                Err(Error::Runtime(err)) => {
+                    assert_eq!(err.kind, ErrorKind::LimitExceeded, "input: {}", input);
+                    assert_eq!(err.message, expected, "input: {}", input);
+                }
+                result => panic!("no limit reached for {}. got={:?}", input, result),
+            }
+        }
+
+        // tail calls do not nest
+        let input = "let count = fn(n) { if (n == 0) { 0 } else { count(n - 1) } }; count(1000)";
+        let engine = Engine::new().with_limits(depth);
+        assert_eq!(engine.run(&compile(input).unwrap()).unwrap(), Object::Integer(0));
+    }
+
+    #[test]
+    fn test_cancellation_and_timeout() {
+        let spin = compile("let spin = fn() { spin() }; spin()").unwrap();
+        let message = |engine: &Engine| match engine.run(&spin) {
+            Err(Error::Runtime(err)) if err.kind == ErrorKind::Cancelled => err.message,
+            result => panic!("not cancelled. got={:?}", result),
+        };
+
+        // the timeout is a backstop, should the cancellation be lost
+        let engine = Engine::new().with_timeout(Duration::from_secs(10));
+        engine.cancel_handle().cancel();
+        assert_eq!(message(&engine), "evaluation cancelled");
+        let one = compile("1").unwrap();
+        assert_eq!(engine.run(&one).unwrap(), Object::Integer(1));
+        // however short the program
+        engine.cancel_handle().cancel();
+        let cancelled = matches!(
+            engine.run(&one),
+            Err(Error::Runtime(err)) if err.kind == ErrorKind::Cancelled
+        );
+        assert!(cancelled);
+        assert_eq!(engine.run(&one).unwrap(), Object::Integer(1));
+
+        let engine = Engine::new().with_timeout(Duration::from_millis(20));
+        assert_eq!(message(&engine), "evaluation timed out after 20ms");
+    }
+
+    #[test]
+    fn test_tail_calls_run_in_constant_space() {
+        let tests = vec![
+            ("let loop = fn(n) { if (n == 0) { 0 } else { loop(n - 1) } }; loop(1000000)", "0"),
+            (
+                "let isEven = fn(n) { if (n == 0) { true } else { isOdd(n - 1) } };\n\
+                 let isOdd = fn(n) { if (n == 0) { false } else { isEven(n - 1) } };\n\
+                 isEven(100001)",
+                "false",
+            ),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(outcome(run(input)), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_closures_share_variables() {
+        let tests = vec![
+            // bound again after the closure was made
+            ("let f = fn() { let a = 1; let g = fn() { a }; let a = 2; g() }; f()", "2"),
+            ("let f = fn(a) { let g = fn() { a }; let a = a + 1; g() }; f(1)", "2"),
+            // bound only after the closure was made
+            ("let f = fn() { let g = fn() { y }; let y = 2; g() }; f()", "2"),
+            (
+                "let f = fn() {\n\
+                   let even = fn(n) { if (n == 0) { true } else { odd(n - 1) } };\n\
+                   let odd = fn(n) { if (n == 0) { false } else { even(n - 1) } };\n\
+                   [even(10), odd(7)]\n\
+                 };\n\
+                 f()",
+                "[true, true]",
+            ),
+            // not bound yet, so the global or the builtin of that name
+            ("let y = 1; let f = fn() { let v = fn() { y }(); let y = 2; v }; f()", "1"),
+            (
+                "let f = fn() { let n = fn() { len }(); let len = 2; n }; f()",
+                "builtin function len",
+            ),
+            // the function's own name until it is bound again
+            (
+                "let f = fn() { let g = fn() { f }; let r = g(); let f = 1; [r, g()] }; f()",
+                "[fn() { let g = fn() f;let r = g();let f = 1;[r, g()] }, 1]",
+            ),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(outcome(Engine::new().eval(input)), expected, "input: {}", input);
+            assert_eq!(outcome(run(input)), expected, "input: {}", input);
+        }
+        let input = "let f = fn() { let g = fn() { y }; let v = g(); let y = 2; v }; f()";
+        assert_eq!(outcome(run(input)), outcome(Engine::new().eval(input)));
+    }
+}